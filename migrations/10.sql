@@ -0,0 +1,3 @@
+CREATE INDEX IF NOT EXISTS events_device_id_created_at_id ON events (device_id, created_at, id);
+CREATE INDEX IF NOT EXISTS device_logs_device_id_created_at_id ON device_logs (device_id, created_at, id);
+CREATE INDEX IF NOT EXISTS device_panics_device_id_created_at_id ON device_panics (device_id, created_at, id);
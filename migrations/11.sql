@@ -0,0 +1,5 @@
+ALTER TABLE events ADD COLUMN IF NOT EXISTS measured_at TIMESTAMPTZ;
+CREATE UNIQUE INDEX IF NOT EXISTS events_device_id_measured_at ON events (device_id, measured_at) WHERE measured_at IS NOT NULL;
+
+-- Events are listed and exported by when they were measured, batched events arrive after newer ones
+CREATE INDEX IF NOT EXISTS events_device_id_measured_or_created_at_id ON events (device_id, (COALESCE(measured_at, created_at)), id);
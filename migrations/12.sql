@@ -0,0 +1,28 @@
+ALTER TABLE sensor_prototype_measurements ADD COLUMN min DOUBLE PRECISION;
+ALTER TABLE sensor_prototype_measurements ADD COLUMN max DOUBLE PRECISION;
+
+CREATE TABLE IF NOT EXISTS sensor_faults (
+  id            BIGSERIAL   PRIMARY KEY NOT NULL,
+  event_id      BIGINT      NOT NULL,
+  device_id     BIGINT      NOT NULL,
+  sensor_id     BIGINT      NOT NULL,
+  variable_name TEXT        NOT NULL,
+  created_at    TIMESTAMPTZ NOT NULL,
+  UNIQUE (event_id, variable_name),
+  FOREIGN KEY (event_id) REFERENCES events (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+CREATE INDEX IF NOT EXISTS sensor_faults_device_id_created_at_id ON sensor_faults (device_id, created_at, id);
+
+-- Null readings used to be stored as measurements without value
+INSERT INTO sensor_faults (event_id, device_id, sensor_id, variable_name, created_at)
+SELECT event_id, device_id, sensor_id, variable_name, created_at
+FROM measurements
+WHERE value IS NULL
+ON CONFLICT DO NOTHING;
+
+DELETE FROM measurements WHERE value IS NULL;
+DELETE FROM measurement_rollups WHERE count = 0;
+ALTER TABLE measurements ALTER COLUMN value SET NOT NULL;
@@ -0,0 +1,19 @@
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatLux';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatPh';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatMicrosiemensPerCentimeter';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatPartsPerMillion';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatHectopascal';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatCentimeters';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatLiters';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'FloatVolts';
+ALTER TYPE SensorMeasurementType ADD VALUE IF NOT EXISTS 'Boolean';
+
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'Light';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'Ph';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'ElectricalConductivity';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'Co2';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'BarometricPressure';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'WaterLevel';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'FlowVolume';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'BatteryVoltage';
+ALTER TYPE SensorMeasurementKind ADD VALUE IF NOT EXISTS 'OnOff';
@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS sensor_calibrations (
+  id                       BIGSERIAL             PRIMARY KEY NOT NULL,
+  compiler_id              BIGINT                NOT NULL,
+  sensor_id                BIGINT                NOT NULL,
+  variable_name            TEXT                  NOT NULL,
+  calibrated_variable_name TEXT                  NOT NULL,
+  kind                     SensorMeasurementKind NOT NULL,
+  ty                       SensorMeasurementType NOT NULL,
+  curve                    JSONB                 NOT NULL,
+  recalibrating            BOOLEAN               NOT NULL DEFAULT FALSE,
+  recalibrated_after       BIGINT,
+  created_at               TIMESTAMPTZ           NOT NULL DEFAULT NOW(),
+  updated_at               TIMESTAMPTZ           NOT NULL DEFAULT NOW(),
+  UNIQUE (compiler_id, sensor_id, variable_name),
+  UNIQUE (compiler_id, calibrated_variable_name),
+  FOREIGN KEY (compiler_id) REFERENCES compilers (id),
+  FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+-- Calibrated series are rebuilt in the background, one batch per transaction
+-- recalibrated_after is the last raw measurement recalibrated, NULL until the previous series is cleared
+CREATE INDEX IF NOT EXISTS sensor_calibrations_recalibrating ON sensor_calibrations (updated_at) WHERE recalibrating;
+
+-- Calibrated series are stored as measurements derived from the raw ones, so they can be aggregated the same way
+ALTER TABLE measurements ADD COLUMN calibration_id BIGINT REFERENCES sensor_calibrations (id);
+CREATE INDEX IF NOT EXISTS measurements_calibration_id ON measurements (calibration_id);
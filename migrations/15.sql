@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS retention_policies (
+  organization_id        BIGINT      PRIMARY KEY NOT NULL,
+  raw_events_days        INTEGER     NOT NULL,
+  hourly_aggregates_days INTEGER     NOT NULL,
+  daily_aggregates_days  INTEGER     NOT NULL,
+  logs_days              INTEGER     NOT NULL,
+  panics_days            INTEGER     NOT NULL,
+  created_at             TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at             TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (organization_id) REFERENCES organizations (id)
+);
+
+CREATE INDEX IF NOT EXISTS events_created_at ON events (created_at);
+CREATE INDEX IF NOT EXISTS device_logs_created_at ON device_logs (created_at);
+CREATE INDEX IF NOT EXISTS device_panics_created_at ON device_panics (created_at);
@@ -0,0 +1,10 @@
+CREATE TYPE SensorFaultReason AS ENUM (
+  'Null', 'Missing', 'InvalidType', 'OutOfRange', 'Unexpected'
+);
+
+-- Sensor faults become the diagnostics of every measurement that couldn't be stored
+ALTER TABLE sensor_faults ADD COLUMN reason SensorFaultReason NOT NULL DEFAULT 'Null';
+ALTER TABLE sensor_faults ALTER COLUMN reason DROP DEFAULT;
+ALTER TABLE sensor_faults ADD COLUMN value JSONB;
+-- Unexpected measurements don't belong to any sensor
+ALTER TABLE sensor_faults ALTER COLUMN sensor_id DROP NOT NULL;
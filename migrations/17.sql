@@ -0,0 +1,49 @@
+CREATE TYPE AlertComparison AS ENUM ('Above', 'Below');
+CREATE TYPE AlertStatus AS ENUM ('Firing', 'Resolved');
+
+-- Rules either watch every device of a collection or a single device
+CREATE TABLE IF NOT EXISTS alert_rules (
+  id               BIGSERIAL        PRIMARY KEY NOT NULL,
+  collection_id    BIGINT,
+  device_id        BIGINT,
+  name             TEXT             NOT NULL,
+  variable_name    TEXT             NOT NULL,
+  comparison       AlertComparison  NOT NULL,
+  threshold        DOUBLE PRECISION NOT NULL,
+  duration_seconds INTEGER          NOT NULL CHECK (duration_seconds >= 0),
+  hysteresis       DOUBLE PRECISION NOT NULL CHECK (hysteresis >= 0),
+  created_at       TIMESTAMPTZ      NOT NULL DEFAULT NOW(),
+  updated_at       TIMESTAMPTZ      NOT NULL DEFAULT NOW(),
+  CHECK ((collection_id IS NULL) <> (device_id IS NULL)),
+  FOREIGN KEY (collection_id) REFERENCES collections (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id)
+);
+CREATE INDEX IF NOT EXISTS alert_rules_collection_id ON alert_rules (collection_id);
+CREATE INDEX IF NOT EXISTS alert_rules_device_id ON alert_rules (device_id);
+
+-- Every transition of a rule in a device
+CREATE TABLE IF NOT EXISTS alerts (
+  id            BIGSERIAL        PRIMARY KEY NOT NULL,
+  alert_rule_id BIGINT           NOT NULL,
+  device_id     BIGINT           NOT NULL,
+  status        AlertStatus      NOT NULL,
+  value         DOUBLE PRECISION NOT NULL,
+  created_at    TIMESTAMPTZ      NOT NULL,
+  FOREIGN KEY (alert_rule_id) REFERENCES alert_rules (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id)
+);
+CREATE INDEX IF NOT EXISTS alerts_device_id_created_at ON alerts (device_id, created_at);
+CREATE INDEX IF NOT EXISTS alerts_alert_rule_id ON alerts (alert_rule_id);
+
+-- Where each rule is in each device, alert_id is the firing alert
+CREATE TABLE IF NOT EXISTS alert_states (
+  alert_rule_id    BIGINT      NOT NULL,
+  device_id        BIGINT      NOT NULL,
+  breached_since   TIMESTAMPTZ,
+  alert_id         BIGINT,
+  last_measured_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (alert_rule_id, device_id),
+  FOREIGN KEY (alert_rule_id) REFERENCES alert_rules (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (alert_id) REFERENCES alerts (id)
+);
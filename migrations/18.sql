@@ -0,0 +1,41 @@
+CREATE TYPE NotificationChannelKind AS ENUM ('Webhook', 'Email');
+CREATE TYPE NotificationStatus AS ENUM ('Pending', 'Sent', 'Failed', 'RateLimited');
+
+CREATE TABLE IF NOT EXISTS notification_channels (
+  id              BIGSERIAL               PRIMARY KEY NOT NULL,
+  organization_id BIGINT                  NOT NULL,
+  name            TEXT                    NOT NULL,
+  kind            NotificationChannelKind NOT NULL,
+  -- Webhook URL or email address
+  target          TEXT                    NOT NULL,
+  -- Webhook payloads are signed with it
+  secret          TEXT                    NOT NULL,
+  template        TEXT,
+  max_per_hour    INTEGER                 NOT NULL CHECK (max_per_hour > 0),
+  muted_until     TIMESTAMPTZ,
+  created_at      TIMESTAMPTZ             NOT NULL DEFAULT NOW(),
+  updated_at      TIMESTAMPTZ             NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (organization_id) REFERENCES organizations (id)
+);
+CREATE INDEX IF NOT EXISTS notification_channels_organization_id ON notification_channels (organization_id);
+
+-- Outbox of the alerts to be delivered, rendered when the alert happens
+CREATE TABLE IF NOT EXISTS notifications (
+  id                      BIGSERIAL          PRIMARY KEY NOT NULL,
+  notification_channel_id BIGINT             NOT NULL,
+  alert_id                BIGINT             NOT NULL,
+  status                  NotificationStatus NOT NULL,
+  subject                 TEXT               NOT NULL,
+  message                 TEXT               NOT NULL,
+  context                 JSONB              NOT NULL,
+  attempts                INTEGER            NOT NULL DEFAULT 0,
+  last_error              TEXT,
+  next_attempt_at         TIMESTAMPTZ        NOT NULL DEFAULT NOW(),
+  sent_at                 TIMESTAMPTZ,
+  created_at              TIMESTAMPTZ        NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (notification_channel_id) REFERENCES notification_channels (id),
+  FOREIGN KEY (alert_id) REFERENCES alerts (id)
+);
+CREATE INDEX IF NOT EXISTS notifications_pending ON notifications (next_attempt_at) WHERE status = 'Pending';
+CREATE INDEX IF NOT EXISTS notifications_channel_created_at ON notifications (notification_channel_id, created_at);
+CREATE INDEX IF NOT EXISTS notifications_alert_id ON notifications (alert_id);
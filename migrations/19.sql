@@ -0,0 +1,23 @@
+CREATE TYPE AlertKind AS ENUM ('Threshold', 'Offline');
+
+-- Offline alerts come from the device going silent, not from a rule
+ALTER TABLE alerts ADD COLUMN IF NOT EXISTS kind AlertKind NOT NULL DEFAULT 'Threshold';
+ALTER TABLE alerts ALTER COLUMN alert_rule_id DROP NOT NULL;
+ALTER TABLE alerts ADD CONSTRAINT alerts_kind_alert_rule_id CHECK ((kind = 'Threshold') = (alert_rule_id IS NOT NULL));
+
+CREATE TYPE DeviceConnectivity AS ENUM ('Online', 'Offline');
+
+-- Last connectivity seen by the offline detection, alert_id is the firing offline alert
+CREATE TABLE IF NOT EXISTS device_connectivity (
+  device_id    BIGINT             PRIMARY KEY NOT NULL,
+  status       DeviceConnectivity NOT NULL,
+  last_seen_at TIMESTAMPTZ,
+  alert_id     BIGINT,
+  updated_at   TIMESTAMPTZ        NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (alert_id) REFERENCES alerts (id)
+);
+
+-- Set as soon as a device reports, even if its events are refused, connectivity is based on it
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ;
+UPDATE devices SET last_seen_at = (SELECT MAX(created_at) FROM events WHERE events.device_id = devices.id) WHERE last_seen_at IS NULL;
@@ -0,0 +1,71 @@
+-- Numbers and addresses change between occurrences of the same panic, so they are masked
+CREATE OR REPLACE FUNCTION panic_message(msg TEXT) RETURNS TEXT LANGUAGE SQL IMMUTABLE AS $$
+  SELECT btrim(regexp_replace(regexp_replace(regexp_replace(msg, '0x[0-9a-fA-F]+', '#', 'g'), '[0-9]+', '#', 'g'), '\s+', ' ', 'g'))
+$$;
+
+-- The firmware hash is left out on purpose: a group must span firmwares so a panic solved on one can reopen when it recurs on a newer one,
+-- every panic keeps its own firmware_hash and the group remembers the one it was solved on
+CREATE OR REPLACE FUNCTION panic_fingerprint(file TEXT, line INT, func TEXT, msg TEXT) RETURNS TEXT LANGUAGE SQL IMMUTABLE AS $$
+  SELECT encode(sha256(convert_to(file || E'\n' || line || E'\n' || func || E'\n' || panic_message(msg), 'UTF8')), 'hex')
+$$;
+
+-- Panics with the same fingerprint in an organization, solving the group solves all of them
+CREATE TABLE IF NOT EXISTS panic_groups (
+  id                   BIGSERIAL   PRIMARY KEY NOT NULL,
+  organization_id      BIGINT      NOT NULL,
+  fingerprint          TEXT        NOT NULL,
+  file                 TEXT        NOT NULL,
+  line                 INT         NOT NULL,
+  func                 TEXT        NOT NULL,
+  msg                  TEXT        NOT NULL,
+  occurrences          BIGINT      NOT NULL,
+  first_seen_at        TIMESTAMPTZ NOT NULL,
+  last_seen_at         TIMESTAMPTZ NOT NULL,
+  is_solved            BOOLEAN     NOT NULL DEFAULT FALSE,
+  solved_at            TIMESTAMPTZ,
+  solved_firmware_hash TEXT,
+  created_at           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at           TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (organization_id, fingerprint),
+  FOREIGN KEY (organization_id) REFERENCES organizations (id)
+);
+CREATE INDEX IF NOT EXISTS panic_groups_organization_id_last_seen_at ON panic_groups (organization_id, last_seen_at);
+
+CREATE TABLE IF NOT EXISTS panic_group_devices (
+  panic_group_id BIGINT      NOT NULL,
+  device_id      BIGINT      NOT NULL,
+  occurrences    BIGINT      NOT NULL,
+  first_seen_at  TIMESTAMPTZ NOT NULL,
+  last_seen_at   TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (panic_group_id, device_id),
+  FOREIGN KEY (panic_group_id) REFERENCES panic_groups (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id)
+);
+
+ALTER TABLE device_panics ADD COLUMN IF NOT EXISTS panic_group_id BIGINT REFERENCES panic_groups (id);
+-- Unknown for panics from before the grouping
+ALTER TABLE device_panics ADD COLUMN IF NOT EXISTS firmware_hash TEXT;
+CREATE INDEX IF NOT EXISTS device_panics_panic_group_id ON device_panics (panic_group_id);
+
+INSERT INTO panic_groups (organization_id, fingerprint, file, line, func, msg, occurrences, first_seen_at, last_seen_at, is_solved, solved_at)
+SELECT cbt.organization_id, panic_fingerprint(p.file, p.line, p.func, p.msg), MIN(p.file), MIN(p.line), MIN(p.func), MIN(panic_message(p.msg)),
+       COUNT(*), MIN(p.created_at), MAX(p.created_at), BOOL_AND(p.is_solved), CASE WHEN BOOL_AND(p.is_solved) THEN MAX(p.updated_at) END
+FROM device_panics p
+INNER JOIN devices ON devices.id = p.device_id
+INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = devices.collection_id
+GROUP BY cbt.organization_id, panic_fingerprint(p.file, p.line, p.func, p.msg)
+ON CONFLICT (organization_id, fingerprint) DO NOTHING;
+
+UPDATE device_panics p
+SET panic_group_id = g.id
+FROM devices
+INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = devices.collection_id
+INNER JOIN panic_groups g ON g.organization_id = cbt.organization_id
+WHERE devices.id = p.device_id AND g.fingerprint = panic_fingerprint(p.file, p.line, p.func, p.msg);
+
+INSERT INTO panic_group_devices (panic_group_id, device_id, occurrences, first_seen_at, last_seen_at)
+SELECT panic_group_id, device_id, COUNT(*), MIN(created_at), MAX(created_at)
+FROM device_panics
+WHERE panic_group_id IS NOT NULL
+GROUP BY panic_group_id, device_id
+ON CONFLICT (panic_group_id, device_id) DO NOTHING;
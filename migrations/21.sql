@@ -0,0 +1,33 @@
+-- Commands a sensor accepts, they don't affect the generated code so they are replaced when the prototype changes
+CREATE TABLE IF NOT EXISTS sensor_prototype_commands (
+  id                  BIGSERIAL PRIMARY KEY NOT NULL,
+  sensor_prototype_id BIGINT    NOT NULL,
+  name                TEXT      NOT NULL,
+  variable_name       TEXT      NOT NULL,
+  arguments           JSONB     NOT NULL,
+  UNIQUE (sensor_prototype_id, variable_name),
+  FOREIGN KEY (sensor_prototype_id) REFERENCES sensor_prototypes (id)
+);
+
+CREATE TYPE DeviceCommandStatus AS ENUM ('Pending', 'Delivered', 'Acknowledged', 'Failed', 'Expired', 'Cancelled');
+
+-- Queued until the device's next event, sensor_id is NULL for the commands every firmware accepts
+CREATE TABLE IF NOT EXISTS device_commands (
+  id              BIGSERIAL           PRIMARY KEY NOT NULL,
+  device_id       BIGINT              NOT NULL,
+  sensor_id       BIGINT,
+  name            TEXT                NOT NULL,
+  command         TEXT                NOT NULL,
+  arguments       JSONB               NOT NULL,
+  status          DeviceCommandStatus NOT NULL DEFAULT 'Pending',
+  error           TEXT,
+  expires_at      TIMESTAMPTZ         NOT NULL,
+  delivered_at    TIMESTAMPTZ,
+  acknowledged_at TIMESTAMPTZ,
+  created_at      TIMESTAMPTZ         NOT NULL DEFAULT NOW(),
+  updated_at      TIMESTAMPTZ         NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+CREATE INDEX IF NOT EXISTS device_commands_device_id_created_at ON device_commands (device_id, created_at);
+CREATE INDEX IF NOT EXISTS device_commands_status_expires_at ON device_commands (status, expires_at);
@@ -0,0 +1 @@
+ALTER TABLE firmwares ADD COLUMN signature TEXT;
@@ -0,0 +1,17 @@
+CREATE TYPE FirmwareUpdateStatus AS ENUM (
+  'Downloaded', 'Applied', 'Failed'
+);
+
+CREATE TABLE IF NOT EXISTS firmware_updates (
+  id          BIGSERIAL            PRIMARY KEY NOT NULL,
+  device_id   BIGINT               NOT NULL,
+  from_hash   TEXT                 NOT NULL,
+  to_hash     TEXT                 NOT NULL,
+  binary_size BIGINT               NOT NULL,
+  status      FirmwareUpdateStatus NOT NULL DEFAULT 'Downloaded',
+  created_at  TIMESTAMPTZ          NOT NULL DEFAULT NOW(),
+  updated_at  TIMESTAMPTZ          NOT NULL DEFAULT NOW(),
+  FOREIGN KEY (device_id) REFERENCES devices (id)
+);
+
+CREATE INDEX IF NOT EXISTS firmware_updates_device_id_created_at ON firmware_updates (device_id, created_at);
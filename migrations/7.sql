@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS maintenance_windows (
+  id            BIGSERIAL   PRIMARY KEY NOT NULL,
+  collection_id BIGINT      NOT NULL,
+  weekday       SMALLINT,
+  starts_at     TIME        NOT NULL,
+  ends_at       TIME        NOT NULL,
+  created_at    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  CHECK (weekday >= 0 AND weekday <= 6),
+  FOREIGN KEY (collection_id) REFERENCES collections (id)
+);
@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS measurements (
+  id            BIGSERIAL             PRIMARY KEY NOT NULL,
+  event_id      BIGINT                NOT NULL,
+  device_id     BIGINT                NOT NULL,
+  sensor_id     BIGINT                NOT NULL,
+  variable_name TEXT                  NOT NULL,
+  kind          SensorMeasurementKind NOT NULL,
+  ty            SensorMeasurementType NOT NULL,
+  value         DOUBLE PRECISION,
+  created_at    TIMESTAMPTZ           NOT NULL,
+  UNIQUE (event_id, variable_name),
+  FOREIGN KEY (event_id) REFERENCES events (id),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+CREATE INDEX IF NOT EXISTS measurements_device_id_sensor_id_created_at ON measurements (device_id, sensor_id, created_at);
+CREATE INDEX IF NOT EXISTS measurements_device_id_variable_name_created_at ON measurements (device_id, variable_name, created_at);
+
+-- Backfill from the events stored before, mapping each measurement to the sensor of the compiler that generated the firmware
+INSERT INTO measurements (event_id, device_id, sensor_id, variable_name, kind, ty, value, created_at)
+SELECT events.id, events.device_id, sensors.id, rendered.variable_name, spm.kind, spm.ty,
+       CASE jsonb_typeof(events.measurements -> rendered.variable_name)
+           WHEN 'number' THEN (events.measurements ->> rendered.variable_name)::DOUBLE PRECISION
+       END,
+       events.created_at
+FROM events
+INNER JOIN devices ON devices.id = events.device_id
+INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = devices.collection_id
+INNER JOIN firmwares ON firmwares.binary_hash = events.firmware_hash AND firmwares.organization_id = cbt.organization_id
+INNER JOIN compilations ON compilations.id = firmwares.compilation_id
+INNER JOIN sensor_belongs_to_compiler sbc ON sbc.compiler_id = compilations.compiler_id
+INNER JOIN sensors ON sensors.id = sbc.sensor_id
+INNER JOIN sensor_prototype_measurements spm ON spm.sensor_prototype_id = sensors.prototype_id
+CROSS JOIN LATERAL (SELECT replace(spm.variable_name, '{{index}}', sensors.index::TEXT) AS variable_name) AS rendered
+WHERE events.measurements -> rendered.variable_name IS NOT NULL
+ON CONFLICT (event_id, variable_name) DO NOTHING;
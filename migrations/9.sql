@@ -0,0 +1,43 @@
+CREATE TYPE RollupResolution AS ENUM (
+  'Hour', 'Day'
+);
+
+CREATE TABLE IF NOT EXISTS measurement_rollups (
+  device_id     BIGINT           NOT NULL,
+  sensor_id     BIGINT           NOT NULL,
+  variable_name TEXT             NOT NULL,
+  resolution    RollupResolution NOT NULL,
+  bucket_start  TIMESTAMPTZ      NOT NULL,
+  min           DOUBLE PRECISION,
+  max           DOUBLE PRECISION,
+  sum           DOUBLE PRECISION,
+  count         BIGINT           NOT NULL,
+  last_value    DOUBLE PRECISION,
+  last_at       TIMESTAMPTZ      NOT NULL,
+  UNIQUE (device_id, sensor_id, variable_name, resolution, bucket_start),
+  FOREIGN KEY (device_id) REFERENCES devices (id),
+  FOREIGN KEY (sensor_id) REFERENCES sensors (id)
+);
+
+CREATE INDEX IF NOT EXISTS measurement_rollups_device_id_resolution_bucket_start ON measurement_rollups (device_id, resolution, bucket_start);
+
+-- Buckets with measurements that weren't rolled up yet, marked in the same transaction that stores the measurements
+--
+-- Measurement ids are handed out when inserted, not when committed, so a watermark on them would skip measurements of slower transactions
+-- Marks aren't unique: a mark committed while the rollup runs must survive it instead of merging into the one being consumed
+CREATE TABLE IF NOT EXISTS measurement_rollup_dirty_buckets (
+  id            BIGSERIAL        PRIMARY KEY NOT NULL,
+  device_id     BIGINT           NOT NULL,
+  sensor_id     BIGINT           NOT NULL,
+  variable_name TEXT             NOT NULL,
+  resolution    RollupResolution NOT NULL,
+  bucket_start  TIMESTAMPTZ      NOT NULL
+);
+CREATE INDEX IF NOT EXISTS measurement_rollup_dirty_buckets_bucket ON measurement_rollup_dirty_buckets (device_id, resolution, bucket_start);
+
+-- Measurements stored before are rolled up in the background
+INSERT INTO measurement_rollup_dirty_buckets (device_id, sensor_id, variable_name, resolution, bucket_start)
+SELECT DISTINCT m.device_id, m.sensor_id, m.variable_name, res.resolution,
+       to_timestamp(floor(extract(epoch FROM m.created_at)::DOUBLE PRECISION / res.seconds) * res.seconds)
+FROM measurements m
+CROSS JOIN (VALUES ('Hour'::RollupResolution, 3600::DOUBLE PRECISION), ('Day'::RollupResolution, 86400::DOUBLE PRECISION)) res (resolution, seconds);
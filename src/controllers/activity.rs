@@ -0,0 +1,78 @@
+use crate::{
+    extractor::User, logger::*, Activity, Collection, CollectionId, Device, DeviceId, Pool, Result,
+};
+use axum::extract::{Extension, Query};
+use axum::response::sse::{self, KeepAlive, Sse};
+use futures::{Stream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceRequest {
+    device_id: DeviceId,
+}
+
+pub async fn device(
+    Extension(pool): Extension<&'static Pool>,
+    Extension(activities): Extension<broadcast::Sender<Activity>>,
+    User(user): User,
+    Query(request): Query<DeviceRequest>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, serde_json::Error>>>> {
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    txn.commit().await?;
+
+    let device_id = device.id();
+    Ok(stream(activities.subscribe(), move |a| {
+        a.device_id() == device_id
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionRequest {
+    collection_id: CollectionId,
+}
+
+pub async fn collection(
+    Extension(pool): Extension<&'static Pool>,
+    Extension(activities): Extension<broadcast::Sender<Activity>>,
+    User(user): User,
+    Query(request): Query<CollectionRequest>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, serde_json::Error>>>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    txn.commit().await?;
+
+    let collection_id = collection.id();
+    Ok(stream(activities.subscribe(), move |a| {
+        a.collection_id() == collection_id
+    }))
+}
+
+/// Server-Sent Events named after the activity kind, with its view as data
+fn stream(
+    receiver: broadcast::Receiver<Activity>,
+    filter: impl Fn(&Activity) -> bool + Send + 'static,
+) -> Sse<impl Stream<Item = Result<sse::Event, serde_json::Error>>> {
+    let activities = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(activity) => return Some((activity, receiver)),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Live subscriber lagged behind, skipped {skipped} activities")
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = activities
+        .filter(move |activity| futures::future::ready(filter(activity)))
+        .map(|activity| {
+            sse::Event::default()
+                .event(activity.kind().name())
+                .json_data(activity.payload())
+        });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,134 @@
+use crate::{
+    extractor::User, page_limit, Alert, AlertRule, AlertRuleId, Collection, CollectionId, Cursor,
+    DateTime, Device, DeviceId, Error, NewAlertRule, Page, Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRulesRequest {
+    collection_id: Option<CollectionId>,
+    device_id: Option<DeviceId>,
+}
+
+/// Rules of a collection, or the ones that apply to a device
+pub async fn rules(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRulesRequest>,
+) -> Result<Json<Vec<AlertRule>>> {
+    let mut txn = pool.begin().await?;
+    let rules = match (request.collection_id, request.device_id) {
+        (Some(collection_id), None) => {
+            let collection = Collection::find_by_id(&mut txn, collection_id, &user).await?;
+            AlertRule::from_collection(&mut txn, &collection).await?
+        }
+        (None, Some(device_id)) => {
+            let device = Device::find_by_id(&mut txn, device_id, &user).await?;
+            AlertRule::from_device(&mut txn, &device).await?
+        }
+        _ => {
+            return Err(Error::InvalidAlertRule(
+                "either a collection or a device is required".to_owned(),
+            ))
+        }
+    };
+    txn.commit().await?;
+    Ok(Json(rules))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRuleRequest {
+    collection_id: Option<CollectionId>,
+    device_id: Option<DeviceId>,
+    #[serde(flatten)]
+    rule: NewAlertRule,
+}
+
+pub async fn new(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<NewRuleRequest>,
+) -> Result<Json<AlertRule>> {
+    let mut txn = pool.begin().await?;
+    let rule = match (request.collection_id, request.device_id) {
+        (Some(collection_id), None) => {
+            let collection = Collection::find_by_id(&mut txn, collection_id, &user).await?;
+            AlertRule::new_for_collection(&mut txn, &collection, request.rule).await?
+        }
+        (None, Some(device_id)) => {
+            let device = Device::find_by_id(&mut txn, device_id, &user).await?;
+            AlertRule::new_for_device(&mut txn, &device, request.rule).await?
+        }
+        _ => {
+            return Err(Error::InvalidAlertRule(
+                "either a collection or a device is required".to_owned(),
+            ))
+        }
+    };
+    txn.commit().await?;
+    Ok(Json(rule))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRuleRequest {
+    alert_rule_id: AlertRuleId,
+}
+
+pub async fn delete(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<DeleteRuleRequest>,
+) -> Result<Json<()>> {
+    let mut txn = pool.begin().await?;
+    let rule = AlertRule::find_by_id(&mut txn, request.alert_rule_id, &user).await?;
+    rule.delete(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    device_id: DeviceId,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+/// Firing and resolved transitions of the device, newest first
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Page<Alert>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let alerts = Alert::list(&mut txn, &device, request.until, cursor, limit).await?;
+    txn.commit().await?;
+    Ok(Json(alerts))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FiringRequest {
+    collection_id: CollectionId,
+}
+
+pub async fn firing(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<FiringRequest>,
+) -> Result<Json<Vec<Alert>>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let alerts = Alert::firing(&mut txn, &collection).await?;
+    txn.commit().await?;
+    Ok(Json(alerts))
+}
@@ -0,0 +1,102 @@
+use crate::{
+    extractor::Body, extractor::Device, extractor::User, page_limit, AvailableCommand, Cursor,
+    DateTime, DeviceCommand, DeviceCommandAck, DeviceCommandId, DeviceId, NewDeviceCommand, Page,
+    Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableRequest {
+    device_id: DeviceId,
+}
+
+pub async fn available(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<AvailableRequest>,
+) -> Result<Json<Vec<AvailableCommand>>> {
+    let mut txn = pool.begin().await?;
+    let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let commands = AvailableCommand::from_device(&mut txn, &device).await?;
+    txn.commit().await?;
+    Ok(Json(commands))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRequest {
+    device_id: DeviceId,
+    #[serde(flatten)]
+    command: NewDeviceCommand,
+}
+
+pub async fn new(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<NewRequest>,
+) -> Result<Json<DeviceCommand>> {
+    let mut txn = pool.begin().await?;
+    let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let command = DeviceCommand::new(&mut txn, &device, request.command).await?;
+    txn.commit().await?;
+    Ok(Json(command))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelRequest {
+    device_id: DeviceId,
+    device_command_id: DeviceCommandId,
+}
+
+pub async fn cancel(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<CancelRequest>,
+) -> Result<Json<DeviceCommand>> {
+    let mut txn = pool.begin().await?;
+    let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let mut command =
+        DeviceCommand::find_by_id(&mut txn, &device, request.device_command_id).await?;
+    command.cancel(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(command))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    device_id: DeviceId,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Page<DeviceCommand>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut txn = pool.begin().await?;
+    let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let commands = DeviceCommand::list(&mut txn, &device, request.until, cursor, limit).await?;
+    txn.commit().await?;
+    Ok(Json(commands))
+}
+
+/// The device reports the result of a command it received in an event response
+pub async fn acknowledge(
+    Extension(pool): Extension<&'static Pool>,
+    Device(device): Device,
+    Body(ack): Body<DeviceCommandAck>,
+) -> Result<Json<DeviceCommand>> {
+    let mut txn = pool.begin().await?;
+    let command = DeviceCommand::acknowledge(&mut txn, &device, ack).await?;
+    txn.commit().await?;
+    Ok(Json(command))
+}
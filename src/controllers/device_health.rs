@@ -0,0 +1,39 @@
+use crate::{
+    extractor::User, Aggregation, Bucket, DateTime, Device, DeviceId, DeviceStatAggregate, Pool,
+    Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsRequest {
+    device_id: DeviceId,
+    since: DateTime,
+    until: Option<DateTime>,
+    bucket: Bucket,
+    aggregations: String,
+}
+
+pub async fn stats(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<StatsRequest>,
+) -> Result<Json<Vec<DeviceStatAggregate>>> {
+    let aggregations = Aggregation::parse_list(&request.aggregations)?;
+    let until = request.until.unwrap_or_else(chrono::Utc::now);
+
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let aggregates = DeviceStatAggregate::list(
+        &mut txn,
+        &device,
+        request.since,
+        until,
+        request.bucket,
+        &aggregations,
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(Json(aggregates))
+}
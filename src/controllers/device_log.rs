@@ -1,47 +1,53 @@
-use crate::{extractor::Device, extractor::User, DeviceId, DeviceLog, DeviceLogView, Pool, Result};
-use axum::extract::{Extension, Json, Query, RawBody};
+use crate::{
+    extractor::Device, extractor::Text, extractor::User, page_limit, Activity, ActivityKind,
+    Cursor, DateTime, DeviceId, DeviceLog, DeviceLogView, Page, Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
 use axum::http::StatusCode;
-use futures::StreamExt;
 use serde::Deserialize;
 
 pub async fn new(
     Extension(pool): Extension<&'static Pool>,
     Device(device): Device,
-    RawBody(mut log): RawBody,
+    Text(log): Text,
 ) -> Result<StatusCode> {
+    store(pool, &device, log).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Shared by `/v1/log` and the MQTT gateway
+pub async fn store(pool: &'static Pool, device: &crate::Device, log: String) -> Result<()> {
     let mut txn = pool.begin().await?;
 
-    let mut log_buffer = Vec::new();
-    while let Some(log) = log.next().await {
-        log_buffer.extend(&log?);
-    }
-    let log = String::from_utf8_lossy(log_buffer.as_ref())
-        .trim()
-        .to_owned();
+    let log = log.trim().to_owned();
 
-    DeviceLog::new(&mut txn, &device, log).await?;
+    let log = DeviceLog::new(&mut txn, device, log).await?;
+    Activity::notify(&mut txn, device, ActivityKind::Log, log.id()).await?;
 
     txn.commit().await?;
-    Ok(StatusCode::OK)
+    Ok(())
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ListRequest {
     device_id: DeviceId,
-    limit: u16,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
 }
 
 pub async fn list(
     Extension(pool): Extension<&'static Pool>,
     User(user): User,
     Query(request): Query<ListRequest>,
-) -> Result<Json<Vec<DeviceLogView>>> {
-    let mut txn = pool.begin().await?;
+) -> Result<Json<Page<DeviceLogView>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
 
+    let mut txn = pool.begin().await?;
     let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
-    let logs = DeviceLogView::first_n_from_device(&mut txn, &device, request.limit as i32).await?;
-
+    let logs = DeviceLogView::list(&mut txn, &device, request.until, cursor, limit).await?;
     txn.commit().await?;
     Ok(Json(logs))
 }
@@ -1,6 +1,7 @@
 use crate::{
-    extractor::{Device, User},
-    DeviceId, DevicePanic, DevicePanicId, DevicePanicView, NewDevicePanic, Pool, Result,
+    extractor::{Body, Device, User},
+    page_limit, Activity, ActivityKind, Cursor, DateTime, DeviceId, DevicePanic, DevicePanicId,
+    DevicePanicView, NewDevicePanic, Page, Pool, Result,
 };
 use axum::extract::{Extension, Json, Query};
 use axum::http::StatusCode;
@@ -21,7 +22,7 @@ pub async fn solve(
     let mut txn = pool.begin().await?;
     let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
     let device_panic = DevicePanic::find_by_id(&mut txn, &device, request.panic_id).await?;
-    device_panic.solve(&mut txn).await?;
+    device_panic.solve(&mut txn, &device).await?;
     txn.commit().await?;
     Ok(StatusCode::OK)
 }
@@ -29,34 +30,50 @@ pub async fn solve(
 pub async fn new(
     Extension(pool): Extension<&'static Pool>,
     Device(device): Device,
-    Json(mut error): Json<NewDevicePanic>,
+    Body(error): Body<NewDevicePanic>,
 ) -> Result<StatusCode> {
+    store(pool, &device, error).await?;
+    Ok(StatusCode::OK)
+}
+
+/// Shared by `/v1/panic` and the MQTT gateway
+pub async fn store(
+    pool: &'static Pool,
+    device: &crate::Device,
+    mut error: NewDevicePanic,
+) -> Result<()> {
     let mut txn = pool.begin().await?;
 
     if error.msg().trim() != error.msg() {
         error.msg = error.msg().trim().to_owned();
     }
-    DevicePanic::new(&mut txn, &device, error).await?;
+    let panic = DevicePanic::new(&mut txn, device, error).await?;
+    Activity::notify(&mut txn, device, ActivityKind::Panic, panic.id()).await?;
 
     txn.commit().await?;
-    Ok(StatusCode::OK)
+    Ok(())
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ListRequest {
     device_id: DeviceId,
-    limit: u16,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
 }
 
 pub async fn list(
     Extension(pool): Extension<&'static Pool>,
     User(user): User,
     Query(request): Query<ListRequest>,
-) -> Result<Json<Vec<DevicePanicView>>> {
+) -> Result<Json<Page<DevicePanicView>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
     let mut txn = pool.begin().await?;
     let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
-    let panics =
-        DevicePanicView::first_n_from_device(&mut txn, &device, request.limit as i32).await?;
+    let panics = DevicePanicView::list(&mut txn, &device, request.until, cursor, limit).await?;
+    txn.commit().await?;
     Ok(Json(panics))
 }
@@ -1,10 +1,9 @@
-use crate::extractor::{
-    BiggestDramBlock, BiggestIramBlock, Device, FreeDram, FreeIram, FreeStack, MacAddress,
-    TimeRunning, User, Vcc, Version,
-};
+use crate::extractor::{Body, Device, MacAddress, Stat, User, BODY_CONTENT_TYPES};
 use crate::{
-    logger::*, Collection, DateTime, DeviceId, DeviceStat, Error, Event, EventView, Firmware, Pool,
-    Result, SensorMeasurementType, Transaction,
+    logger::*, page_limit, Activity, ActivityKind, AlertRule, Collection, Cursor, DateTime,
+    DeviceCommand, DeviceId, DeviceStat, Error, Event, EventView, Firmware, FirmwareUpdate,
+    Measurement, NewMeasurement, NewSensorFault, Page, Pool, Result, SensorCalibration,
+    SensorFault, SensorFaultReason, Transaction, EVENT_SCHEMA_VERSION, MAX_EVENT_BATCH,
 };
 use axum::extract::{Extension, Json, Query, TypedHeader};
 use axum::http::header::{HeaderMap, HeaderName, HeaderValue};
@@ -14,48 +13,143 @@ use serde::Deserialize;
 use serde_json::json;
 use std::iter::FromIterator;
 
-#[allow(clippy::too_many_arguments)]
+/// v1 adapter, the stat comes from the headers
 pub async fn new(
     Extension(pool): Extension<&'static Pool>,
-    Device(mut device): Device,
-    Json(event): Json<serde_json::Value>,
+    Device(device): Device,
     TypedHeader(MacAddress(mac)): TypedHeader<MacAddress>,
-    TypedHeader(Version(version)): TypedHeader<Version>,
-    TypedHeader(TimeRunning(time_running)): TypedHeader<TimeRunning>,
-    TypedHeader(Vcc(vcc)): TypedHeader<Vcc>,
-    TypedHeader(FreeStack(free_stack)): TypedHeader<FreeStack>,
-    TypedHeader(FreeDram(free_dram)): TypedHeader<FreeDram>,
-    TypedHeader(BiggestDramBlock(biggest_dram_block)): TypedHeader<BiggestDramBlock>,
-    free_iram: Option<TypedHeader<FreeIram>>,
-    biggest_iram_block: Option<TypedHeader<BiggestIramBlock>>,
+    Stat(stat): Stat,
+    Body(event): Body<serde_json::Value>,
 ) -> Result<impl IntoResponse> {
-    let stat = DeviceStat {
-        version: version.to_lowercase(),
-        time_running: time_running.parse()?,
-        vcc: vcc.parse()?,
-        free_dram: free_dram.parse()?,
-        free_iram: free_iram.and_then(|TypedHeader(FreeIram(iram))| iram.parse().ok()),
-        free_stack: free_stack.parse()?,
-        biggest_dram_block: biggest_dram_block.parse()?,
-        biggest_iram_block: biggest_iram_block
-            .and_then(|TypedHeader(BiggestIramBlock(size))| size.parse().ok()),
-    };
-
     info!(target: "event", "MAC: {}, DeviceId: {:?}, Stat: {:?}", mac, device, stat);
     debug!("New Event: {:?}", event);
+    seen(pool, &device).await?;
+    ingest(pool, device, stat, vec![(None, event)], true).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchedEvent {
+    measured_at: DateTime,
+    measurements: serde_json::Value,
+}
+
+/// v1 adapter, the stat comes from the headers
+pub async fn batch(
+    Extension(pool): Extension<&'static Pool>,
+    Device(device): Device,
+    TypedHeader(MacAddress(mac)): TypedHeader<MacAddress>,
+    Stat(stat): Stat,
+    Body(events): Body<Vec<BatchedEvent>>,
+) -> Result<impl IntoResponse> {
+    info!(target: "event", "MAC: {}, DeviceId: {:?}, Stat: {:?}, Batch: {}", mac, device, stat, events.len());
+    debug!("New Events: {:?}", events);
+    seen(pool, &device).await?;
+    if events.len() > MAX_EVENT_BATCH {
+        return Err(Error::EventBatchTooBig(events.len()));
+    }
+
+    let now = chrono::Utc::now();
+    for event in &events {
+        Event::check_clock_skew(event.measured_at, now)?;
+    }
+    let events = events
+        .into_iter()
+        .map(|e| (Some(e.measured_at), e.measurements))
+        .collect();
+    ingest(pool, device, stat, events, true).await
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VersionedEvent {
+    #[serde(default)]
+    measured_at: Option<DateTime>,
+    measurements: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VersionedEvents {
+    schema_version: u32,
+    stat: DeviceStat,
+    events: Vec<VersionedEvent>,
+}
+
+/// Stat and measurements in a single body, new stats don't need new headers
+pub async fn v2(
+    Extension(pool): Extension<&'static Pool>,
+    Device(device): Device,
+    Body(body): Body<VersionedEvents>,
+) -> Result<impl IntoResponse> {
+    ingest_versioned(pool, device, body, true).await
+}
+
+/// Shared by `/v2/event` and the MQTT gateway
+///
+/// Pending commands are only delivered if the response reaches the device, MQTT has no response to carry them
+pub async fn ingest_versioned(
+    pool: &'static Pool,
+    device: crate::Device,
+    body: VersionedEvents,
+    deliver_commands: bool,
+) -> Result<HeaderMap> {
+    info!(target: "event", "DeviceId: {:?}, Stat: {:?}, Events: {}", device, body.stat, body.events.len());
+    debug!("New Events: {:?}", body.events);
+    seen(pool, &device).await?;
+    if body.schema_version != EVENT_SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(body.schema_version));
+    }
+    if body.events.len() > MAX_EVENT_BATCH {
+        return Err(Error::EventBatchTooBig(body.events.len()));
+    }
+
+    let now = chrono::Utc::now();
+    for measured_at in body.events.iter().filter_map(|e| e.measured_at) {
+        Event::check_clock_skew(measured_at, now)?;
+    }
+    let mut stat = body.stat;
+    stat.version = stat.version.to_lowercase();
+    let events = body
+        .events
+        .into_iter()
+        .map(|e| (e.measured_at, e.measurements))
+        .collect();
+    ingest(pool, device, stat, events, deliver_commands).await
+}
+
+/// Committed on its own, so devices whose events are refused aren't reported offline
+async fn seen(pool: &'static Pool, device: &crate::Device) -> Result<()> {
     let mut txn = pool.begin().await?;
+    device.touch(&mut txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Shared by single and batched events, they all carry the stat of when they were sent
+async fn ingest(
+    pool: &'static Pool,
+    mut device: crate::Device,
+    stat: DeviceStat,
+    events: Vec<(Option<DateTime>, serde_json::Value)>,
+    deliver_commands: bool,
+) -> Result<HeaderMap> {
+    // Lets the firmware know it can switch to a more compact encoding
+    let mut headers = HeaderMap::from_iter([(
+        HeaderName::from_static("accept-post"),
+        HeaderValue::from_str(&BODY_CONTENT_TYPES.join(", "))?,
+    )]);
+    let mut txn = pool.begin().await?;
+
+    FirmwareUpdate::correlate(&mut txn, &device, stat.version()).await?;
 
     let mut collection = device.collection(&mut txn).await?;
 
-    // Don't even process request if there is an update
-    if let Some(firmware) = collection.update(&mut txn).await? {
-        if firmware.binary_hash() != stat.version() {
-            return Ok(HeaderMap::from_iter([(
-                HeaderName::from_static("latest_version"),
-                HeaderValue::from_str(&firmware.binary_hash().to_lowercase())?,
-            )]));
-        }
-    }
+    // Outdated firmwares keep reporting, their events are stored before they are told to update
+    let outdated = match collection.update(&mut txn).await? {
+        Some(firmware) if firmware.binary_hash() != stat.version() => Some(firmware),
+        _ => None,
+    };
 
     let organization = collection.organization(&mut txn).await?;
     if let Some(firmware) =
@@ -108,12 +202,57 @@ pub async fn new(
         device.set_firmware(&mut txn, &firmware).await?;
     }
 
-    if !event.is_null() {
-        handle_measurements(&mut txn, &collection, &device, stat.clone(), event).await?;
+    for (measured_at, event) in events {
+        if !event.is_null() {
+            let event = handle_measurements(
+                &mut txn,
+                &collection,
+                &device,
+                stat.clone(),
+                event,
+                measured_at,
+            )
+            .await?;
+            if let Some(event) = event {
+                Activity::notify(&mut txn, &device, ActivityKind::Event, event.id()).await?;
+            }
+        }
+    }
+
+    if let Some(firmware) = &outdated {
+        // Updates are only offered while the maintenance window is open
+        if collection.is_in_maintenance_window(&mut txn).await? {
+            headers.insert(
+                HeaderName::from_static("latest_version"),
+                HeaderValue::from_str(&firmware.binary_hash().to_lowercase())?,
+            );
+        }
+    }
+
+    // Also delivered along with `latest_version`, the update may never be applied and devices acknowledge them before rebooting
+    if deliver_commands {
+        let mut commands = Vec::new();
+        for mut command in DeviceCommand::deliver(&mut txn, &device).await? {
+            // A command the header can't carry would fail the whole ingestion, it fails on its own instead
+            let payload = command.payload();
+            match HeaderValue::from_str(&payload.to_string()) {
+                Ok(_) => commands.push(payload),
+                Err(err) => {
+                    warn!("Unable to deliver command {:?}: {err}", command.id());
+                    command.fail(&mut txn, err.to_string()).await?;
+                }
+            }
+        }
+        if !commands.is_empty() {
+            headers.insert(
+                HeaderName::from_static("commands"),
+                HeaderValue::from_str(&serde_json::Value::Array(commands).to_string())?,
+            );
+        }
     }
 
     txn.commit().await?;
-    Ok(HeaderMap::new())
+    Ok(headers)
 }
 
 #[derive(Debug, Deserialize)]
@@ -121,19 +260,31 @@ pub async fn new(
 pub struct ListRequest {
     device_id: DeviceId,
     since: DateTime,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
 }
 
 pub async fn list(
     Extension(pool): Extension<&'static Pool>,
     User(user): User,
     Query(request): Query<ListRequest>,
-) -> Result<Json<Vec<EventView>>> {
+) -> Result<Json<Page<EventView>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
     let mut txn = pool.begin().await?;
-    let mut events = Vec::new();
     let device = crate::Device::find_by_id(&mut txn, request.device_id, &user).await?;
-    for event in Event::list(&mut txn, &device, request.since).await? {
-        events.push(EventView::new(event)?);
-    }
+    let events = Event::list(
+        &mut txn,
+        &device,
+        request.since,
+        request.until,
+        cursor,
+        limit,
+    )
+    .await?
+    .try_map(EventView::new)?;
     txn.commit().await?;
     Ok(Json(events))
 }
@@ -143,9 +294,13 @@ async fn handle_measurements(
     collection: &Collection,
     device: &crate::Device,
     stat: DeviceStat,
-    mut event: serde_json::Value,
-) -> Result<Event> {
-    let obj = event.as_object_mut().ok_or(Error::EventMustBeObject)?;
+    event: serde_json::Value,
+    measured_at: Option<DateTime>,
+) -> Result<Option<Event>> {
+    let obj = event.as_object().ok_or(Error::EventMustBeObject)?;
+
+    let mut new_measurements = Vec::new();
+    let mut faults = Vec::new();
 
     // If there is no compiler accept whatever. This makes processing in the frontend worse as we lack metadata about types
     if let Some(compiler) = collection.compiler(txn).await? {
@@ -153,6 +308,7 @@ async fn handle_measurements(
         let mut measurements = Vec::new();
         for sensor in sensors {
             let index = sensor.index();
+            let sensor_id = sensor.id();
             let prototype = sensor.prototype();
             measurements.extend(
                 prototype
@@ -163,64 +319,106 @@ async fn handle_measurements(
                         let reg = Handlebars::new();
                         let name =
                             reg.render_template(m.variable_name(), &json!({ "index": index }))?;
-                        Ok((m.ty().clone(), name))
+                        Ok((sensor_id, m, name))
                     })
                     .collect::<Result<Vec<_>>>()?,
             );
         }
         debug!("Expected Measurements: {:?}", measurements);
+        let calibrations = SensorCalibration::from_compiler(txn, &compiler).await?;
 
-        if obj.len() != measurements.len() {
-            error!("Invalid number of json arguments");
-            return Err(Error::MeasurementMissing);
+        // A broken sensor must not discard what the others measured, so every problem is recorded as a fault
+        for (name, value) in obj {
+            if !measurements.iter().any(|(_, _, n)| n == name) {
+                warn!("Unexpected measurement: {}", name);
+                faults.push(NewSensorFault {
+                    sensor_id: None,
+                    variable_name: name.clone(),
+                    reason: SensorFaultReason::Unexpected,
+                    value: Some(value.clone()),
+                });
+            }
         }
-        for (ty, name) in measurements {
-            if let Some(value) = obj.get_mut(&name) {
-                match ty {
-                    SensorMeasurementType::FloatCelsius => {
-                        // There is no NaN in JSON, most serializers cast it to null
-                        if value.as_null().is_some() {
-                            *value = serde_json::json!(f64::NAN);
-                        }
-
-                        if value.as_f64().is_none() {
-                            error!("Invalid celsius measured: {:?}", value);
-                            return Err(Error::InvalidMeasurementType(
-                                value.clone(),
-                                "f64".to_owned(),
-                            ));
-                        }
-                    }
-                    SensorMeasurementType::RawAnalogRead => {
-                        if value.as_i64().is_none() {
-                            error!("Invalid raw analog read: {:?}", value);
-                            return Err(Error::InvalidMeasurementType(
-                                value.clone(),
-                                "i64".to_owned(),
-                            ));
-                        }
-                    }
-                    SensorMeasurementType::Percentage => {
-                        // There is no NaN in JSON, most serializers cast it to null
-                        if value.as_null().is_some() {
-                            *value = serde_json::json!(f64::NAN);
-                        }
-
-                        if value.as_f64().is_none() {
-                            error!("Invalid percentage measured: {:?}", value);
-                            return Err(Error::InvalidMeasurementType(
-                                value.clone(),
-                                "f64".to_owned(),
-                            ));
-                        }
+        for (sensor_id, measurement, name) in measurements {
+            if let Some(value) = obj.get(&name) {
+                let fault = |reason, value: Option<&serde_json::Value>| NewSensorFault {
+                    sensor_id: Some(sensor_id),
+                    variable_name: name.clone(),
+                    reason,
+                    value: value.cloned(),
+                };
+
+                // There is no NaN in JSON, most serializers cast it to null
+                if value.is_null() {
+                    warn!("Sensor fault, {} measured null", name);
+                    faults.push(fault(SensorFaultReason::Null, None));
+                    continue;
+                }
+
+                let number = match measurement.ty().parse(value) {
+                    Some(number) => number,
+                    None => {
+                        warn!(
+                            "Invalid {:?} measured: {:?}, expected {}",
+                            measurement.ty(),
+                            value,
+                            measurement.ty().json_type()
+                        );
+                        faults.push(fault(SensorFaultReason::InvalidType, Some(value)));
+                        continue;
                     }
+                };
+
+                let range = measurement.range();
+                if !range.contains(number) {
+                    warn!(
+                        "Measurement {} out of range: {} not in {}",
+                        name, number, range
+                    );
+                    faults.push(fault(SensorFaultReason::OutOfRange, Some(value)));
+                    continue;
                 }
+
+                new_measurements.extend(
+                    calibrations
+                        .iter()
+                        .filter(|c| c.sensor_id() == sensor_id && *c.variable_name() == name)
+                        .map(|c| c.measurement(number)),
+                );
+                new_measurements.push(NewMeasurement {
+                    sensor_id,
+                    value: number,
+                    variable_name: name,
+                    kind: measurement.kind,
+                    ty: measurement.ty,
+                    calibration_id: None,
+                });
             } else {
-                error!("Missing measurement: {}", name);
-                return Err(Error::MissingMeasurement(name));
+                warn!("Missing measurement: {}", name);
+                faults.push(NewSensorFault {
+                    sensor_id: Some(sensor_id),
+                    variable_name: name,
+                    reason: SensorFaultReason::Missing,
+                    value: None,
+                });
             }
         }
     }
 
-    Event::new(txn, device, event, stat).await
+    let event = match Event::new(txn, device, event, stat, measured_at).await? {
+        Some(event) => event,
+        None => {
+            debug!("Ignoring duplicated event measured at {:?}", measured_at);
+            return Ok(None);
+        }
+    };
+    let mut measurements = Vec::with_capacity(new_measurements.len());
+    for new_measurement in new_measurements {
+        measurements.push(Measurement::new(txn, device, &event, new_measurement).await?);
+    }
+    AlertRule::evaluate(txn, device, &measurements).await?;
+    for fault in faults {
+        SensorFault::new(txn, device, &event, fault).await?;
+    }
+    Ok(Some(event))
 }
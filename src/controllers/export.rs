@@ -0,0 +1,90 @@
+use crate::{
+    extractor::User, Collection, CollectionId, DateTime, Device, DeviceId, Export, ExportFormat,
+    Pool, Result,
+};
+use axum::{body::StreamBody, extract::Extension, extract::Query, response::IntoResponse};
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceRequest {
+    device_id: DeviceId,
+    since: DateTime,
+    until: Option<DateTime>,
+    format: ExportFormat,
+    #[serde(default)]
+    stat: bool,
+}
+
+pub async fn device(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<DeviceRequest>,
+) -> Result<impl IntoResponse> {
+    let until = request.until.unwrap_or_else(chrono::Utc::now);
+
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let collection = device.collection(&mut txn).await?;
+    let export = Export::new(
+        &mut txn,
+        &collection,
+        std::slice::from_ref(&device),
+        request.since,
+        until,
+        request.format,
+        request.stat,
+    )
+    .await?;
+    txn.commit().await?;
+
+    let filename = export.filename(&format!("device-{}", device.id()));
+    respond(pool, export, filename)
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionRequest {
+    collection_id: CollectionId,
+    since: DateTime,
+    until: Option<DateTime>,
+    format: ExportFormat,
+    #[serde(default)]
+    stat: bool,
+}
+
+pub async fn collection(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<CollectionRequest>,
+) -> Result<impl IntoResponse> {
+    let until = request.until.unwrap_or_else(chrono::Utc::now);
+
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let devices = Device::from_collection(&mut txn, &collection).await?;
+    let export = Export::new(
+        &mut txn,
+        &collection,
+        &devices,
+        request.since,
+        until,
+        request.format,
+        request.stat,
+    )
+    .await?;
+    txn.commit().await?;
+
+    let filename = export.filename(&format!("collection-{}", collection.id()));
+    respond(pool, export, filename)
+}
+
+fn respond(pool: &'static Pool, export: Export, filename: String) -> Result<impl IntoResponse> {
+    let response = axum::http::Response::builder()
+        .header("Content-Type", export.format().content_type())
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(StreamBody::new(export.stream(pool)))?;
+    Ok(response)
+}
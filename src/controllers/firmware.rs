@@ -1,41 +1,40 @@
-use crate::{extractor::Device, extractor::Esp8266Md5, logger::*, Error, Pool, Result};
-use axum::{body::Bytes, body::Full, response::IntoResponse, Extension, TypedHeader};
-use std::fmt::Write;
+use crate::{
+    extractor::Device, extractor::Ota, logger::*, utils, Error, FirmwareUpdate, Pool, Result,
+};
+use axum::{body::Bytes, body::Full, response::IntoResponse, Extension};
 
 pub async fn update(
     Extension(pool): Extension<&'static Pool>,
     Device(device): Device,
-    TypedHeader(Esp8266Md5(md5)): TypedHeader<Esp8266Md5>,
+    ota: Ota,
 ) -> Result<impl IntoResponse> {
     let mut txn = pool.begin().await?;
 
     let collection = device.collection(&mut txn).await?;
 
-    //let chip_ip = headers.get("x-ESP8266-Chip-ID");
-    //let mac_address = headers.get("x-ESP8266-STA-MAC").ok_or(Error::NothingFound)?.to_str().map_err(|_|Error::NothingFound)?;
-    //let ap_mac = headers.get("x-ESP8266-AP-MAC");
-    //let free_space = headers.get("x-ESP8266-free-space");
-    //let sketch_size = headers.get("x-ESP8266-sketch-size");
-    let md5 = md5.to_lowercase();
-    //let chip_size = headers.get("x-ESP8266-chip-size");
-    //let sdk_version = headers.get("x-ESP8266-sdk-version");
+    debug!(
+        "Update request (device_id: {:?}): chip size {:?}, sdk version {:?}",
+        device.id(),
+        ota.chip_size,
+        ota.sdk_version
+    );
+    let md5 = ota.md5.to_lowercase();
 
-    let firmware = match collection.update(&mut txn).await? {
+    let mut firmware = match collection.update(&mut txn).await? {
         Some(update) => update,
         None => return Err(Error::NoBinaryAvailable)?,
     };
     if firmware.binary_hash() == &md5 {
         return Err(Error::NoUpdateAvailable)?;
     }
+    if !collection.is_in_maintenance_window(&mut txn).await? {
+        return Err(Error::OutsideMaintenanceWindow)?;
+    }
 
     let hash = firmware.binary_hash().to_owned();
+    let current_hash = md5;
     if let Some(binary) = firmware.bin(&mut txn).await? {
-        let md5 = md5::compute(&binary);
-        let md5 = &*md5;
-        let mut file_hash = String::with_capacity(md5.len() * 2);
-        for byte in md5 {
-            write!(file_hash, "{:02x}", byte)?;
-        }
+        let file_hash = utils::hex(&*md5::compute(&binary));
         if file_hash != hash {
             error!(
                 "Binary md5 didn't match the expected: {} != {}",
@@ -43,6 +42,13 @@ pub async fn update(
             );
             return Err(Error::CorruptedBinary)?;
         }
+        if let Some(free_space) = ota.free_space {
+            if binary.len() as u64 > free_space {
+                return Err(Error::NotEnoughSpace(binary.len() as u64, free_space))?;
+            }
+        }
+        let signature = firmware.sign(&mut txn, &binary).await?;
+        FirmwareUpdate::new(&mut txn, &device, current_hash, hash, binary.len()).await?;
         txn.commit().await?;
         let response = axum::http::Response::builder()
             .header("Content-Type", "application/octet-stream")
@@ -52,6 +58,7 @@ pub async fn update(
                 format!("attachment; filename=\"{}.bin\"", file_hash),
             )
             .header("x-MD5", file_hash)
+            .header("x-Signature", signature)
             .body(Full::new(Bytes::from(binary)))?;
         Ok(response)
     } else {
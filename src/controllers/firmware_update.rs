@@ -0,0 +1,42 @@
+use crate::{
+    extractor::User, Collection, CollectionId, Device, DeviceId, DeviceUpdateView, Pool, Result,
+    RolloutView,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    device_id: DeviceId,
+}
+
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<DeviceUpdateView>> {
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let updates = DeviceUpdateView::new(&mut txn, &device).await?;
+    txn.commit().await?;
+    Ok(Json(updates))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutRequest {
+    collection_id: CollectionId,
+}
+
+pub async fn rollout(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<RolloutRequest>,
+) -> Result<Json<RolloutView>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let rollout = RolloutView::new(&mut txn, &collection).await?;
+    txn.commit().await?;
+    Ok(Json(rollout))
+}
@@ -0,0 +1,26 @@
+use crate::{
+    extractor::User, GarbageCollection, Organization, OrganizationId, Pool, Result,
+    KEEP_LATEST_COMPILATIONS,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportRequest {
+    organization_id: OrganizationId,
+}
+
+/// Dry-run of what the garbage collector would delete
+pub async fn report(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ReportRequest>,
+) -> Result<Json<GarbageCollection>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let garbage =
+        GarbageCollection::find(&mut txn, Some(&organization), KEEP_LATEST_COMPILATIONS).await?;
+    txn.commit().await?;
+    Ok(Json(garbage))
+}
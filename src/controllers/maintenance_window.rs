@@ -0,0 +1,64 @@
+use crate::{
+    extractor::User, Collection, CollectionId, MaintenanceWindow, MaintenanceWindowId,
+    NewMaintenanceWindow, Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    collection_id: CollectionId,
+}
+
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Vec<MaintenanceWindow>>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let windows = collection.maintenance_windows(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(windows))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRequest {
+    collection_id: CollectionId,
+    #[serde(flatten)]
+    window: NewMaintenanceWindow,
+}
+
+pub async fn new(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<NewRequest>,
+) -> Result<Json<MaintenanceWindow>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let window = MaintenanceWindow::new(&mut txn, &collection, request.window).await?;
+    txn.commit().await?;
+    Ok(Json(window))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRequest {
+    collection_id: CollectionId,
+    window_id: MaintenanceWindowId,
+}
+
+pub async fn delete(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<DeleteRequest>,
+) -> Result<Json<()>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let window = MaintenanceWindow::find_by_id(&mut txn, &collection, request.window_id).await?;
+    window.delete(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(()))
+}
@@ -1,12 +1,26 @@
+pub mod activity;
+pub mod alert;
 pub mod collection;
 pub mod compiler;
 pub mod device;
+pub mod device_command;
+pub mod device_health;
 pub mod device_log;
 pub mod device_panic;
 pub mod event;
+pub mod export;
 pub mod firmware;
+pub mod firmware_update;
+pub mod garbage_collection;
+pub mod maintenance_window;
+pub mod measurement;
+pub mod notification;
 pub mod organization;
+pub mod panic_group;
+pub mod retention_policy;
 pub mod sensor;
+pub mod sensor_calibration;
+pub mod sensor_fault;
 pub mod sensor_prototype;
 pub mod target;
 pub mod user;
@@ -0,0 +1,116 @@
+use crate::{
+    extractor::User, page_limit, CreatedNotificationChannel, Cursor, DateTime,
+    NewNotificationChannel, Notification, NotificationChannel, NotificationChannelId, Organization,
+    OrganizationId, Page, Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelsRequest {
+    organization_id: OrganizationId,
+}
+
+pub async fn channels(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ChannelsRequest>,
+) -> Result<Json<Vec<NotificationChannel>>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let channels = NotificationChannel::from_organization(&mut txn, &organization).await?;
+    txn.commit().await?;
+    Ok(Json(channels))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRequest {
+    organization_id: OrganizationId,
+    #[serde(flatten)]
+    channel: NewNotificationChannel,
+}
+
+pub async fn new(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<NewRequest>,
+) -> Result<Json<CreatedNotificationChannel>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let channel = NotificationChannel::new(&mut txn, &organization, request.channel).await?;
+    txn.commit().await?;
+    Ok(Json(channel.into()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MuteRequest {
+    organization_id: OrganizationId,
+    notification_channel_id: NotificationChannelId,
+    muted_until: Option<DateTime>,
+}
+
+pub async fn mute(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<MuteRequest>,
+) -> Result<Json<NotificationChannel>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let mut channel =
+        NotificationChannel::find_by_id(&mut txn, &organization, request.notification_channel_id)
+            .await?;
+    channel.mute(&mut txn, request.muted_until).await?;
+    txn.commit().await?;
+    Ok(Json(channel))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRequest {
+    organization_id: OrganizationId,
+    notification_channel_id: NotificationChannelId,
+}
+
+pub async fn delete(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<DeleteRequest>,
+) -> Result<Json<()>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let channel =
+        NotificationChannel::find_by_id(&mut txn, &organization, request.notification_channel_id)
+            .await?;
+    channel.delete(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    organization_id: OrganizationId,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+/// Delivery log of the organization's channels, newest first
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Page<Notification>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let notifications =
+        Notification::list(&mut txn, &organization, request.until, cursor, limit).await?;
+    txn.commit().await?;
+    Ok(Json(notifications))
+}
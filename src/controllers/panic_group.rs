@@ -0,0 +1,60 @@
+use crate::{
+    extractor::User, page_limit, Cursor, DateTime, Organization, OrganizationId, Page, PanicGroup,
+    PanicGroupId, Pool, Result,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    organization_id: OrganizationId,
+    solved: Option<bool>,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+/// Panic groups of the organization, most recently seen first
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Page<PanicGroup>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let groups = PanicGroup::list(
+        &mut txn,
+        &organization,
+        request.solved,
+        request.until,
+        cursor,
+        limit,
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(Json(groups))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SolveRequest {
+    organization_id: OrganizationId,
+    panic_group_id: PanicGroupId,
+}
+
+pub async fn solve(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<SolveRequest>,
+) -> Result<Json<PanicGroup>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let mut group = PanicGroup::find_by_id(&mut txn, &organization, request.panic_group_id).await?;
+    group.solve(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(group))
+}
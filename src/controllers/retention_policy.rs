@@ -0,0 +1,44 @@
+use crate::{
+    extractor::User, NewRetentionPolicy, Organization, OrganizationId, Pool, Result,
+    RetentionPolicy,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FindRequest {
+    organization_id: OrganizationId,
+}
+
+pub async fn find(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<FindRequest>,
+) -> Result<Json<Option<RetentionPolicy>>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let policy = RetentionPolicy::find(&mut txn, &organization).await?;
+    txn.commit().await?;
+    Ok(Json(policy))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetRequest {
+    organization_id: OrganizationId,
+    #[serde(flatten)]
+    policy: NewRetentionPolicy,
+}
+
+pub async fn set(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<SetRequest>,
+) -> Result<Json<RetentionPolicy>> {
+    let mut txn = pool.begin().await?;
+    let organization = Organization::find_by_id(&mut txn, request.organization_id, &user).await?;
+    let policy = RetentionPolicy::set(&mut txn, &organization, request.policy).await?;
+    txn.commit().await?;
+    Ok(Json(policy))
+}
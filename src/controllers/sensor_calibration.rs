@@ -0,0 +1,79 @@
+use crate::{
+    extractor::User, Collection, CollectionId, Error, NewSensorCalibration, Pool, Result, Sensor,
+    SensorCalibration, SensorCalibrationId, SensorId,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    collection_id: CollectionId,
+}
+
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Vec<SensorCalibration>>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let calibrations = match collection.compiler(&mut txn).await? {
+        Some(compiler) => SensorCalibration::from_compiler(&mut txn, &compiler).await?,
+        None => Vec::new(),
+    };
+    txn.commit().await?;
+    Ok(Json(calibrations))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetRequest {
+    collection_id: CollectionId,
+    sensor_id: SensorId,
+    #[serde(flatten)]
+    calibration: NewSensorCalibration,
+}
+
+pub async fn set(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<SetRequest>,
+) -> Result<Json<SensorCalibration>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let compiler = collection
+        .compiler(&mut txn)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+    let sensor = Sensor::find_by_id(&mut txn, &compiler, request.sensor_id).await?;
+    let calibration =
+        SensorCalibration::new(&mut txn, &compiler, &sensor, request.calibration).await?;
+    txn.commit().await?;
+    Ok(Json(calibration))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRequest {
+    collection_id: CollectionId,
+    calibration_id: SensorCalibrationId,
+}
+
+pub async fn delete(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Json(request): Json<DeleteRequest>,
+) -> Result<Json<()>> {
+    let mut txn = pool.begin().await?;
+    let collection = Collection::find_by_id(&mut txn, request.collection_id, &user).await?;
+    let compiler = collection
+        .compiler(&mut txn)
+        .await?
+        .ok_or(Error::Unauthorized)?;
+    let calibration =
+        SensorCalibration::find_by_id(&mut txn, &compiler, request.calibration_id).await?;
+    calibration.delete(&mut txn).await?;
+    txn.commit().await?;
+    Ok(Json(()))
+}
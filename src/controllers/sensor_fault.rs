@@ -0,0 +1,52 @@
+use crate::{
+    extractor::User, page_limit, Cursor, DateTime, Device, DeviceId, Page, Pool, Result,
+    SensorFault, SensorFaultCount,
+};
+use axum::extract::{Extension, Json, Query};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListRequest {
+    device_id: DeviceId,
+    until: Option<DateTime>,
+    limit: Option<u32>,
+    cursor: Option<String>,
+}
+
+pub async fn list(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<ListRequest>,
+) -> Result<Json<Page<SensorFault>>> {
+    let limit = page_limit(request.limit)?;
+    let cursor = request.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let faults = SensorFault::list(&mut txn, &device, request.until, cursor, limit).await?;
+    txn.commit().await?;
+    Ok(Json(faults))
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CountRequest {
+    device_id: DeviceId,
+    since: DateTime,
+    until: Option<DateTime>,
+}
+
+pub async fn count(
+    Extension(pool): Extension<&'static Pool>,
+    User(user): User,
+    Query(request): Query<CountRequest>,
+) -> Result<Json<Vec<SensorFaultCount>>> {
+    let until = request.until.unwrap_or_else(chrono::Utc::now);
+
+    let mut txn = pool.begin().await?;
+    let device = Device::find_by_id(&mut txn, request.device_id, &user).await?;
+    let counts = SensorFault::count(&mut txn, &device, request.since, until).await?;
+    txn.commit().await?;
+    Ok(Json(counts))
+}
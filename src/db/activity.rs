@@ -0,0 +1,147 @@
+use crate::{
+    logger::*, CollectionId, Device, DeviceId, DeviceLog, DeviceLogId, DevicePanic, DevicePanicId,
+    Event, EventId, EventView, Pool, Result, Transaction,
+};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Postgres channel every server instance listens to
+pub const ACTIVITY_CHANNEL: &str = "device_activity";
+/// Activities buffered for each subscriber, slow subscribers skip what doesn't fit
+const ACTIVITY_BUFFER: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ActivityKind {
+    Event,
+    Log,
+    Panic,
+}
+
+impl ActivityKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Event => "event",
+            Self::Log => "log",
+            Self::Panic => "panic",
+        }
+    }
+}
+
+/// NOTIFY payloads are limited to 8000 bytes, so only the reference is sent
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct ActivityNotification {
+    device_id: DeviceId,
+    collection_id: CollectionId,
+    kind: ActivityKind,
+    id: i64,
+}
+
+/// Something a device just sent, as its view
+#[derive(Getters, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    #[copy]
+    device_id: DeviceId,
+    #[copy]
+    collection_id: CollectionId,
+    #[copy]
+    kind: ActivityKind,
+    payload: serde_json::Value,
+}
+
+impl Activity {
+    /// Delivered to the listeners when the transaction commits
+    pub async fn notify(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        kind: ActivityKind,
+        id: impl Into<i64>,
+    ) -> Result<()> {
+        let notification = ActivityNotification {
+            device_id: device.id(),
+            collection_id: device.collection_id(),
+            kind,
+            id: id.into(),
+        };
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(ACTIVITY_CHANNEL)
+            .bind(serde_json::to_string(&notification)?)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+
+    /// Forwards the activities of every server instance to the returned channel
+    pub fn listen(pool: &'static Pool) -> broadcast::Sender<Self> {
+        let (sender, _) = broadcast::channel(ACTIVITY_BUFFER);
+        let task_sender = sender.clone();
+        tokio::task::spawn(async move {
+            loop {
+                if let Err(err) = Self::forward(pool, &task_sender).await {
+                    error!("Activity listener: {err}");
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+        sender
+    }
+
+    async fn forward(pool: &'static Pool, sender: &broadcast::Sender<Self>) -> Result<()> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(ACTIVITY_CHANNEL).await?;
+        loop {
+            let notification = listener.recv().await?;
+            // Nobody is watching, skip loading the activity
+            if sender.receiver_count() == 0 {
+                continue;
+            }
+
+            let notification: ActivityNotification =
+                match serde_json::from_str(notification.payload()) {
+                    Ok(notification) => notification,
+                    Err(err) => {
+                        warn!("Invalid activity notification: {err}");
+                        continue;
+                    }
+                };
+            match Self::load(pool, notification).await {
+                // Only fails if every subscriber left in the meantime
+                Ok(activity) => drop(sender.send(activity)),
+                Err(err) => warn!("Unable to load activity {notification:?}: {err}"),
+            }
+        }
+    }
+
+    async fn load(pool: &'static Pool, notification: ActivityNotification) -> Result<Self> {
+        let mut txn = pool.begin().await?;
+        let payload = match notification.kind {
+            ActivityKind::Event => {
+                let event = Event::raw_find_by_id(&mut txn, EventId::from(notification.id)).await?;
+                serde_json::to_value(EventView::new(event)?)?
+            }
+            ActivityKind::Log => {
+                let log =
+                    DeviceLog::raw_find_by_id(&mut txn, DeviceLogId::from(notification.id)).await?;
+                serde_json::to_value(log)?
+            }
+            ActivityKind::Panic => {
+                let panic =
+                    DevicePanic::raw_find_by_id(&mut txn, DevicePanicId::from(notification.id))
+                        .await?;
+                serde_json::to_value(panic)?
+            }
+        };
+        txn.commit().await?;
+        Ok(Self {
+            device_id: notification.device_id,
+            collection_id: notification.collection_id,
+            kind: notification.kind,
+            payload,
+        })
+    }
+}
@@ -0,0 +1,620 @@
+use crate::{
+    Collection, CollectionId, Cursor, DateTime, Device, DeviceId, Error, Measurement, Notification,
+    Page, Result, Transaction, User,
+};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Longest a threshold may need to be breached before the alert fires
+const MAX_ALERT_DURATION_SECONDS: i32 = 7 * 24 * 60 * 60;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertComparison {
+    /// Fires when the value is greater than the threshold
+    Above,
+    /// Fires when the value is smaller than the threshold
+    Below,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertKind {
+    /// A rule's threshold was crossed
+    Threshold,
+    /// The device stopped reporting, see `DeviceConnectivity`
+    Offline,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewAlertRule {
+    pub name: String,
+    /// Measurement watched, calibrated series included
+    pub variable_name: String,
+    #[copy]
+    pub comparison: AlertComparison,
+    #[copy]
+    pub threshold: f64,
+    /// How long the threshold must stay breached before firing, zero fires on the first breach
+    #[copy]
+    pub duration_seconds: i32,
+    /// How far back past the threshold the value must go to resolve, so noise around it doesn't flap
+    #[copy]
+    pub hysteresis: f64,
+}
+
+impl NewAlertRule {
+    fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() || self.variable_name.trim().is_empty() {
+            return Err(Error::InvalidAlertRule(
+                "name and variable name are required".to_owned(),
+            ));
+        }
+        if !self.threshold.is_finite() || !self.hysteresis.is_finite() || self.hysteresis < 0. {
+            return Err(Error::InvalidAlertRule(
+                "threshold must be a number and hysteresis a positive one".to_owned(),
+            ));
+        }
+        if !(0..=MAX_ALERT_DURATION_SECONDS).contains(&self.duration_seconds) {
+            return Err(Error::InvalidAlertRule(format!(
+                "duration must be between 0 and {} seconds",
+                MAX_ALERT_DURATION_SECONDS
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[id]
+pub struct AlertRuleId;
+
+/// Threshold on a measurement, evaluated as events arrive
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    #[copy]
+    id: AlertRuleId,
+    #[copy]
+    collection_id: Option<CollectionId>,
+    #[copy]
+    device_id: Option<DeviceId>,
+    name: String,
+    variable_name: String,
+    #[copy]
+    comparison: AlertComparison,
+    #[copy]
+    threshold: f64,
+    #[copy]
+    duration_seconds: i32,
+    #[copy]
+    hysteresis: f64,
+    #[copy]
+    created_at: DateTime,
+    #[copy]
+    updated_at: DateTime,
+}
+
+/// Where a rule is in a device
+#[derive(sqlx::FromRow, Clone, Copy, Debug, PartialEq)]
+struct AlertState {
+    breached_since: Option<DateTime>,
+    alert_id: Option<AlertId>,
+    last_measured_at: DateTime,
+}
+
+impl AlertState {
+    /// Applies a measurement, returning the transition it caused
+    ///
+    /// Firing transitions leave `alert_id` to be set once the alert is recorded
+    fn advance(
+        &mut self,
+        rule: &AlertRule,
+        value: f64,
+        measured_at: DateTime,
+    ) -> Option<AlertStatus> {
+        if measured_at < self.last_measured_at {
+            return None;
+        }
+        self.last_measured_at = measured_at;
+
+        if self.alert_id.is_some() {
+            if rule.is_recovered(value) {
+                self.alert_id = None;
+                self.breached_since = None;
+                return Some(AlertStatus::Resolved);
+            }
+        } else if rule.is_breached(value) {
+            let breached_since = *self.breached_since.get_or_insert(measured_at);
+            if (measured_at - breached_since).num_seconds() >= i64::from(rule.duration_seconds) {
+                return Some(AlertStatus::Firing);
+            }
+        } else {
+            self.breached_since = None;
+        }
+        None
+    }
+}
+
+impl AlertRule {
+    pub async fn new_for_collection(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+        new_rule: NewAlertRule,
+    ) -> Result<Self> {
+        Self::insert(txn, Some(collection.id()), None, new_rule).await
+    }
+
+    pub async fn new_for_device(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        new_rule: NewAlertRule,
+    ) -> Result<Self> {
+        Self::insert(txn, None, Some(device.id()), new_rule).await
+    }
+
+    async fn insert(
+        txn: &mut Transaction<'_>,
+        collection_id: Option<CollectionId>,
+        device_id: Option<DeviceId>,
+        new_rule: NewAlertRule,
+    ) -> Result<Self> {
+        new_rule.validate()?;
+
+        let rule = sqlx::query_as(
+            "INSERT INTO alert_rules (collection_id, device_id, name, variable_name, comparison, threshold, duration_seconds, hysteresis)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING id, collection_id, device_id, name, variable_name, comparison, threshold, duration_seconds, hysteresis, created_at, updated_at",
+        )
+        .bind(collection_id)
+        .bind(device_id)
+        .bind(new_rule.name.trim())
+        .bind(new_rule.variable_name.trim())
+        .bind(new_rule.comparison)
+        .bind(new_rule.threshold)
+        .bind(new_rule.duration_seconds)
+        .bind(new_rule.hysteresis)
+        .fetch_one(txn)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        rule_id: AlertRuleId,
+        user: &User,
+    ) -> Result<Self> {
+        let rule = sqlx::query_as(
+            "SELECT r.id, r.collection_id, r.device_id, r.name, r.variable_name, r.comparison, r.threshold, r.duration_seconds, r.hysteresis, r.created_at, r.updated_at
+             FROM alert_rules r
+             LEFT JOIN devices dev ON dev.id = r.device_id
+             INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = COALESCE(r.collection_id, dev.collection_id)
+             INNER JOIN user_belongs_to_organization ubt ON ubt.organization_id = cbt.organization_id
+             WHERE r.id = $1 AND ubt.user_id = $2",
+        )
+        .bind(rule_id)
+        .bind(user.id())
+        .fetch_one(txn)
+        .await?;
+        Ok(rule)
+    }
+
+    pub async fn from_collection(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+    ) -> Result<Vec<Self>> {
+        let rules = sqlx::query_as(
+            "SELECT id, collection_id, device_id, name, variable_name, comparison, threshold, duration_seconds, hysteresis, created_at, updated_at
+             FROM alert_rules
+             WHERE collection_id = $1
+             ORDER BY id ASC",
+        )
+        .bind(collection.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(rules)
+    }
+
+    /// Rules of the device and of its collection
+    pub async fn from_device(txn: &mut Transaction<'_>, device: &Device) -> Result<Vec<Self>> {
+        let rules = sqlx::query_as(
+            "SELECT id, collection_id, device_id, name, variable_name, comparison, threshold, duration_seconds, hysteresis, created_at, updated_at
+             FROM alert_rules
+             WHERE device_id = $1 OR collection_id = $2
+             ORDER BY id ASC",
+        )
+        .bind(device.id())
+        .bind(device.collection_id())
+        .fetch_all(txn)
+        .await?;
+        Ok(rules)
+    }
+
+    /// Drops the rule with its history
+    pub async fn delete(self, txn: &mut Transaction<'_>) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM notifications USING alerts WHERE notifications.alert_id = alerts.id AND alerts.alert_rule_id = $1",
+        )
+        .bind(self.id)
+        .execute(&mut *txn)
+        .await?;
+        for table in ["alert_states", "alerts"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE alert_rule_id = $1"))
+                .bind(self.id)
+                .execute(&mut *txn)
+                .await?;
+        }
+        sqlx::query("DELETE FROM alert_rules WHERE id = $1")
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+
+    /// Resolves what the collection's rules were firing in a device leaving it, their states don't carry over
+    pub async fn leave_collection(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        collection: &Collection,
+    ) -> Result<()> {
+        let states: Vec<(AlertRuleId, Option<AlertId>)> = sqlx::query_as(
+            "DELETE FROM alert_states
+             USING alert_rules
+             WHERE alert_rules.id = alert_states.alert_rule_id AND alert_rules.collection_id = $1 AND alert_states.device_id = $2
+             RETURNING alert_states.alert_rule_id, alert_states.alert_id",
+        )
+        .bind(collection.id())
+        .bind(device.id())
+        .fetch_all(&mut *txn)
+        .await?;
+
+        let rules = Self::from_collection(&mut *txn, collection).await?;
+        for (rule_id, alert_id) in states {
+            let rule = rules.iter().find(|rule| rule.id == rule_id);
+            if let (Some(rule), Some(alert_id)) = (rule, alert_id) {
+                let (value,): (f64,) = sqlx::query_as("SELECT value FROM alerts WHERE id = $1")
+                    .bind(alert_id)
+                    .fetch_one(&mut *txn)
+                    .await?;
+                let alert = Alert::new(
+                    txn,
+                    Some(rule),
+                    device,
+                    AlertStatus::Resolved,
+                    value,
+                    chrono::Utc::now(),
+                )
+                .await?;
+                Notification::enqueue(txn, device, &alert, Some(rule), None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_breached(&self, value: f64) -> bool {
+        match self.comparison {
+            AlertComparison::Above => value > self.threshold,
+            AlertComparison::Below => value < self.threshold,
+        }
+    }
+
+    fn is_recovered(&self, value: f64) -> bool {
+        match self.comparison {
+            AlertComparison::Above => value <= self.threshold - self.hysteresis,
+            AlertComparison::Below => value >= self.threshold + self.hysteresis,
+        }
+    }
+
+    /// Moves the device's rules forward with the measurements just stored, recording every transition
+    ///
+    /// Measurements older than the last one evaluated, like late batched events, don't change the state
+    pub async fn evaluate(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        measurements: &[Measurement],
+    ) -> Result<()> {
+        if measurements.is_empty() {
+            return Ok(());
+        }
+        for rule in Self::from_device(&mut *txn, device).await? {
+            for measurement in measurements
+                .iter()
+                .filter(|m| *m.variable_name() == rule.variable_name)
+            {
+                rule.advance(&mut *txn, device, measurement).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn advance(
+        &self,
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        measurement: &Measurement,
+    ) -> Result<()> {
+        let (value, measured_at) = (measurement.value(), measurement.created_at());
+        let state: Option<AlertState> = sqlx::query_as(
+            "SELECT breached_since, alert_id, last_measured_at
+             FROM alert_states
+             WHERE alert_rule_id = $1 AND device_id = $2
+             FOR UPDATE",
+        )
+        .bind(self.id)
+        .bind(device.id())
+        .fetch_optional(&mut *txn)
+        .await?;
+        let mut state = state.unwrap_or(AlertState {
+            breached_since: None,
+            alert_id: None,
+            last_measured_at: measured_at,
+        });
+        if let Some(status) = state.advance(self, value, measured_at) {
+            let alert = Alert::new(txn, Some(self), device, status, value, measured_at).await?;
+            Notification::enqueue(
+                txn,
+                device,
+                &alert,
+                Some(self),
+                Some(measurement.sensor_id()),
+            )
+            .await?;
+            if status == AlertStatus::Firing {
+                state.alert_id = Some(alert.id);
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO alert_states (alert_rule_id, device_id, breached_since, alert_id, last_measured_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (alert_rule_id, device_id) DO UPDATE
+             SET breached_since = EXCLUDED.breached_since, alert_id = EXCLUDED.alert_id, last_measured_at = EXCLUDED.last_measured_at",
+        )
+        .bind(self.id)
+        .bind(device.id())
+        .bind(state.breached_since)
+        .bind(state.alert_id)
+        .bind(state.last_measured_at)
+        .execute(txn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[id]
+pub struct AlertId;
+
+/// Transition of a rule, or of the device's connectivity, in a device
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    #[copy]
+    id: AlertId,
+    #[copy]
+    kind: AlertKind,
+    /// Only threshold alerts have a rule
+    #[copy]
+    alert_rule_id: Option<AlertRuleId>,
+    #[copy]
+    device_id: DeviceId,
+    #[copy]
+    status: AlertStatus,
+    /// Measurement that caused the transition, or seconds without reports for offline alerts
+    #[copy]
+    value: f64,
+    #[copy]
+    created_at: DateTime,
+}
+
+impl Alert {
+    /// Alerts without a rule are offline alerts
+    pub(crate) async fn new(
+        txn: &mut Transaction<'_>,
+        rule: Option<&AlertRule>,
+        device: &Device,
+        status: AlertStatus,
+        value: f64,
+        created_at: DateTime,
+    ) -> Result<Self> {
+        let kind = if rule.is_some() {
+            AlertKind::Threshold
+        } else {
+            AlertKind::Offline
+        };
+        let alert_rule_id = rule.map(|rule| rule.id);
+        let (id,): (AlertId,) = sqlx::query_as(
+            "INSERT INTO alerts (kind, alert_rule_id, device_id, status, value, created_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        )
+        .bind(kind)
+        .bind(alert_rule_id)
+        .bind(device.id())
+        .bind(status)
+        .bind(value)
+        .bind(created_at)
+        .fetch_one(txn)
+        .await?;
+        Ok(Self {
+            id,
+            kind,
+            alert_rule_id,
+            device_id: device.id(),
+            status,
+            value,
+            created_at,
+        })
+    }
+
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let alerts: Vec<Self> = sqlx::query_as(
+            "SELECT id, kind, alert_rule_id, device_id, status, value, created_at
+             FROM alerts
+             WHERE device_id = $1
+                   AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR (created_at, id) < ($3, $4))
+             ORDER BY created_at DESC, id DESC
+             LIMIT $5",
+        )
+        .bind(device.id())
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
+        .fetch_all(txn)
+        .await?;
+        Ok(Page::new(alerts, limit, |a| {
+            Cursor::new(a.created_at, a.id)
+        }))
+    }
+
+    /// Alerts still firing in the collection's devices, offline devices included
+    pub async fn firing(txn: &mut Transaction<'_>, collection: &Collection) -> Result<Vec<Self>> {
+        let alerts = sqlx::query_as(
+            "SELECT alerts.id, alerts.kind, alerts.alert_rule_id, alerts.device_id, alerts.status, alerts.value, alerts.created_at
+             FROM alert_states
+             INNER JOIN alerts ON alerts.id = alert_states.alert_id
+             INNER JOIN devices ON devices.id = alert_states.device_id
+             WHERE devices.collection_id = $1
+             UNION ALL
+             SELECT alerts.id, alerts.kind, alerts.alert_rule_id, alerts.device_id, alerts.status, alerts.value, alerts.created_at
+             FROM device_connectivity
+             INNER JOIN alerts ON alerts.id = device_connectivity.alert_id
+             INNER JOIN devices ON devices.id = device_connectivity.device_id
+             WHERE devices.collection_id = $1
+             ORDER BY created_at DESC, id DESC",
+        )
+        .bind(collection.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(comparison: AlertComparison, duration_seconds: i32) -> AlertRule {
+        AlertRule {
+            id: AlertRuleId::from(1),
+            collection_id: Some(CollectionId::from(1)),
+            device_id: None,
+            name: "rule".to_owned(),
+            variable_name: "temperature".to_owned(),
+            comparison,
+            threshold: 30.,
+            duration_seconds,
+            hysteresis: 2.,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime {
+        chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp_opt(1_700_000_000 + seconds, 0).unwrap(),
+            chrono::Utc,
+        )
+    }
+
+    fn state() -> AlertState {
+        AlertState {
+            breached_since: None,
+            alert_id: None,
+            last_measured_at: at(0),
+        }
+    }
+
+    /// Records the alert like `AlertRule::advance` does
+    fn apply(
+        state: &mut AlertState,
+        rule: &AlertRule,
+        value: f64,
+        seconds: i64,
+    ) -> Option<AlertStatus> {
+        let status = state.advance(rule, value, at(seconds));
+        if status == Some(AlertStatus::Firing) {
+            state.alert_id = Some(AlertId::from(1));
+        }
+        status
+    }
+
+    #[test]
+    fn thresholds_and_hysteresis() {
+        let above = rule(AlertComparison::Above, 0);
+        assert!(!above.is_breached(30.));
+        assert!(above.is_breached(30.1));
+        assert!(!above.is_recovered(28.1));
+        assert!(above.is_recovered(28.));
+
+        let below = rule(AlertComparison::Below, 0);
+        assert!(!below.is_breached(30.));
+        assert!(below.is_breached(29.9));
+        assert!(!below.is_recovered(31.9));
+        assert!(below.is_recovered(32.));
+    }
+
+    #[test]
+    fn fires_and_resolves_past_the_hysteresis() {
+        let rule = rule(AlertComparison::Above, 0);
+        let mut state = state();
+        assert_eq!(apply(&mut state, &rule, 25., 0), None);
+        assert_eq!(apply(&mut state, &rule, 31., 10), Some(AlertStatus::Firing));
+        assert_eq!(apply(&mut state, &rule, 32., 20), None);
+        // Back under the threshold, but not past the hysteresis
+        assert_eq!(apply(&mut state, &rule, 29., 30), None);
+        assert_eq!(
+            apply(&mut state, &rule, 28., 40),
+            Some(AlertStatus::Resolved)
+        );
+        assert_eq!(
+            state,
+            AlertState {
+                breached_since: None,
+                alert_id: None,
+                last_measured_at: at(40)
+            }
+        );
+    }
+
+    #[test]
+    fn fires_after_the_duration() {
+        let rule = rule(AlertComparison::Above, 60);
+        let mut state = state();
+        assert_eq!(apply(&mut state, &rule, 31., 0), None);
+        assert_eq!(apply(&mut state, &rule, 31., 30), None);
+        // Dipping under the threshold restarts the count
+        assert_eq!(apply(&mut state, &rule, 29., 45), None);
+        assert_eq!(apply(&mut state, &rule, 31., 50), None);
+        assert_eq!(apply(&mut state, &rule, 31., 100), None);
+        assert_eq!(
+            apply(&mut state, &rule, 31., 110),
+            Some(AlertStatus::Firing)
+        );
+    }
+
+    #[test]
+    fn ignores_late_measurements() {
+        let rule = rule(AlertComparison::Above, 0);
+        let mut state = state();
+        assert_eq!(apply(&mut state, &rule, 25., 100), None);
+        assert_eq!(apply(&mut state, &rule, 40., 50), None);
+        assert_eq!(state.last_measured_at, at(100));
+        assert_eq!(
+            apply(&mut state, &rule, 40., 150),
+            Some(AlertStatus::Firing)
+        );
+        assert_eq!(apply(&mut state, &rule, 20., 120), None);
+        assert!(state.alert_id.is_some());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
-    Compiler, CompilerId, CompilerView, DateTime, Device, DeviceView, Error, Firmware,
-    Organization, Result, TargetPrototype, TargetPrototypeId, Transaction, User,
+    AlertRule, Compiler, CompilerId, CompilerView, DateTime, Device, DeviceConnectivity,
+    DeviceView, DeviceWidgetKind, Error, Firmware, MaintenanceWindow, Organization, Result,
+    TargetPrototype, TargetPrototypeId, Transaction, User,
 };
 use derive::id;
 use derive_get::Getters;
@@ -18,6 +19,8 @@ pub struct CollectionView {
     description: Option<String>,
     compiler: Option<CompilerView>,
     devices: Vec<DeviceView>,
+    #[copy]
+    offline_devices: usize,
     target_prototype: TargetPrototype,
     #[copy]
     created_at: DateTime,
@@ -28,6 +31,10 @@ pub struct CollectionView {
 impl CollectionView {
     pub async fn new(txn: &mut Transaction<'_>, collection: Collection) -> Result<Self> {
         let devices = collection.devices(txn).await?;
+        let offline_devices = devices
+            .iter()
+            .filter(|d| d.connectivity() == DeviceConnectivity::Offline)
+            .count();
         let compiler = collection.compiler(txn).await?;
         let compiler = match compiler {
             Some(c) => Some(CompilerView::new(txn, c).await?),
@@ -40,6 +47,7 @@ impl CollectionView {
             description: collection.description,
             compiler,
             devices,
+            offline_devices,
             created_at: collection.created_at,
             updated_at: collection.updated_at,
         })
@@ -155,11 +163,7 @@ impl Collection {
 
     pub async fn devices(&self, txn: &mut Transaction<'_>) -> Result<Vec<DeviceView>> {
         let devices = Device::from_collection(txn, self).await?;
-        let mut device_views = Vec::with_capacity(devices.len());
-        for device in devices {
-            device_views.push(DeviceView::new(txn, device).await?);
-        }
-        Ok(device_views)
+        DeviceView::list(txn, devices).await
     }
 
     pub async fn find_by_device(txn: &mut Transaction<'_>, device: &Device) -> Result<Self> {
@@ -233,6 +237,41 @@ impl Collection {
         }
     }
 
+    /// Offset from UTC in hours, configured by the compiler's device configs
+    pub async fn timezone(&self, txn: &mut Transaction<'_>) -> Result<i8> {
+        if let Some(compiler) = self.compiler(txn).await? {
+            for config in compiler.device_configs(txn).await? {
+                let request = config.request(txn).await?;
+                if request.ty(txn).await?.widget() == DeviceWidgetKind::Timezone {
+                    return config
+                        .value()
+                        .parse()
+                        .map_err(|err| Error::InvalidTimezone(err, config.value().clone()));
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    pub async fn maintenance_windows(
+        &self,
+        txn: &mut Transaction<'_>,
+    ) -> Result<Vec<MaintenanceWindow>> {
+        MaintenanceWindow::from_collection(txn, self).await
+    }
+
+    /// Collections without maintenance windows may be updated at any time
+    pub async fn is_in_maintenance_window(&self, txn: &mut Transaction<'_>) -> Result<bool> {
+        let windows = self.maintenance_windows(txn).await?;
+        if windows.is_empty() {
+            return Ok(true);
+        }
+
+        let timezone = self.timezone(txn).await?;
+        let now = chrono::Utc::now() + chrono::Duration::hours(timezone.into());
+        Ok(windows.iter().any(|w| w.contains(now.naive_utc())))
+    }
+
     pub async fn set_name(&mut self, txn: &mut Transaction<'_>, name: String) -> Result<()> {
         let (updated_at,): (DateTime,) = sqlx::query_as(
             "UPDATE collections SET name = $1, updated_at = NOW() WHERE id = $2 RETURNING updated_at",
@@ -247,6 +286,12 @@ impl Collection {
     }
 
     pub async fn delete(self, txn: &mut Transaction<'_>) -> Result<()> {
+        for rule in AlertRule::from_collection(&mut *txn, &self).await? {
+            rule.delete(&mut *txn).await?;
+        }
+        for window in self.maintenance_windows(&mut *txn).await? {
+            window.delete(&mut *txn).await?;
+        }
         sqlx::query("DELETE FROM collection_belongs_to_organization where collection_id = $1")
             .bind(self.id)
             .execute(&mut *txn)
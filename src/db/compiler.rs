@@ -1,5 +1,5 @@
 use crate::{
-    logger::*, Collection, CollectionId, Compilation, CompilationView, Device, DeviceConfig,
+    logger::*, utils, Collection, CollectionId, Compilation, CompilationView, Device, DeviceConfig,
     DeviceConfigView, DeviceId, DeviceWidgetKind, Error, FirmwareView, NewDeviceConfig, NewSensor,
     Organization, Result, Sensor, SensorConfigRequest, SensorView, Target, TargetId, TargetView,
     Transaction,
@@ -12,6 +12,9 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
 
+/// How often the compiled firmwares send events, it's how long devices are expected to go without reporting
+pub const MEASUREMENTS_INTERVAL_SECONDS: i64 = 30;
+
 #[derive(Getters, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct NewCompiler {
@@ -529,14 +532,17 @@ impl Compiler {
             configs.insert(0, '\n');
         }
 
+        let public_key = public_key_array(&utils::signing_key().await?.verifying_key());
+
         let main_cpp = format!(
             "#include <iop/loop.hpp>
 #include <pin.hpp>
 {includes}
 namespace config {{
-constexpr static iop::time::milliseconds measurementsInterval = 30 * 1000;
+constexpr static iop::time::milliseconds measurementsInterval = {MEASUREMENTS_INTERVAL_SECONDS} * 1000;
 constexpr static iop::time::milliseconds unauthenticatedActionsInterval = 1000;
-constexpr static iop::time::milliseconds authenticatedActionsInterval = 1000;{device_configs}{configs}
+constexpr static iop::time::milliseconds authenticatedActionsInterval = 1000;
+constexpr static uint8_t firmwarePublicKey[32] IOP_ROM = {{{public_key}}};{device_configs}{configs}
 }}{definitions}
 auto prepareJson(iop::EventLoop & loop) noexcept -> iop::Api::Json {{
   IOP_TRACE();
@@ -641,3 +647,41 @@ auto setup(EventLoop &loop) noexcept -> void {{{setups}
         Organization::find_by_compiler(txn, self).await
     }
 }
+
+/// Firmwares verify their updates with it, as a C array initializer
+fn public_key_array(key: &ed25519_dalek::VerifyingKey) -> String {
+    key.to_bytes()
+        .iter()
+        .map(|byte| format!("0x{}", utils::hex(&[*byte])))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn signatures_verify_against_the_injected_public_key() {
+        let array = public_key_array(&utils::signing_key().await.unwrap().verifying_key());
+        let injected: Vec<u8> = array
+            .split(", ")
+            .map(|byte| u8::from_str_radix(byte.trim_start_matches("0x"), 16).unwrap())
+            .collect();
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&injected.try_into().unwrap()).unwrap();
+
+        let bin = b"firmware binary";
+        let signature = unhex(&utils::sign(bin).await.unwrap());
+        let signature = ed25519_dalek::Signature::from_slice(&signature).unwrap();
+        assert!(key.verify_strict(bin, &signature).is_ok());
+        assert!(key.verify_strict(b"tampered binary", &signature).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use crate::{
-    utils, AuthToken, Collection, CollectionId, CompilerView, DateTime, Error, Event, EventView,
-    Firmware, FirmwareId, FirmwareView, Login, Organization, Result, TargetPrototype,
-    TargetPrototypeId, Transaction, User, UserId,
+    utils, AlertRule, AuthToken, Collection, CollectionId, CompilerView, DateTime,
+    DeviceConnectivity, DeviceHealthFlag, Error, Event, EventView, Firmware, FirmwareId,
+    FirmwareView, Login, Organization, Result, TargetPrototype, TargetPrototypeId, Transaction,
+    User, UserId,
 };
 use derive::id;
 use derive_get::Getters;
@@ -23,6 +24,12 @@ pub struct DeviceView {
     compiler: Option<CompilerView>,
     last_event: Option<EventView>,
     #[copy]
+    connectivity: DeviceConnectivity,
+    /// When the device last reported, even if its events were refused
+    #[copy]
+    last_seen_at: Option<DateTime>,
+    health: Vec<DeviceHealthFlag>,
+    #[copy]
     created_at: DateTime,
     #[copy]
     updated_at: DateTime,
@@ -30,6 +37,28 @@ pub struct DeviceView {
 
 impl DeviceView {
     pub async fn new(txn: &mut Transaction<'_>, device: Device) -> Result<Self> {
+        let mut health = DeviceHealthFlag::list(txn, &[device.id]).await?;
+        let health = health.remove(&device.id).unwrap_or_default();
+        Self::with_health(txn, device, health).await
+    }
+
+    /// Health is analyzed for every device in a single query
+    pub async fn list(txn: &mut Transaction<'_>, devices: Vec<Device>) -> Result<Vec<Self>> {
+        let device_ids: Vec<DeviceId> = devices.iter().map(Device::id).collect();
+        let mut health = DeviceHealthFlag::list(txn, &device_ids).await?;
+        let mut views = Vec::with_capacity(devices.len());
+        for device in devices {
+            let flags = health.remove(&device.id).unwrap_or_default();
+            views.push(Self::with_health(txn, device, flags).await?);
+        }
+        Ok(views)
+    }
+
+    async fn with_health(
+        txn: &mut Transaction<'_>,
+        device: Device,
+        health: Vec<DeviceHealthFlag>,
+    ) -> Result<Self> {
         let firmware = device.current_firmware(txn).await?;
         let firmware = FirmwareView::new(firmware);
         let collection = device.collection(txn).await?;
@@ -40,6 +69,8 @@ impl DeviceView {
         };
 
         let last_event = device.last_event(txn).await?;
+        let last_seen_at = device.last_seen_at(txn).await?;
+        let connectivity = DeviceConnectivity::new(last_seen_at, chrono::Utc::now());
         let last_event = if let Some(last_event) = last_event {
             Some(EventView::new(last_event)?)
         } else {
@@ -54,6 +85,9 @@ impl DeviceView {
             mac: device.mac,
             compiler,
             last_event,
+            connectivity,
+            last_seen_at,
+            health,
             created_at: device.created_at,
             updated_at: device.updated_at,
         })
@@ -250,6 +284,18 @@ impl Device {
         Ok(device)
     }
 
+    pub async fn raw_find_by_id(txn: &mut Transaction<'_>, device_id: DeviceId) -> Result<Self> {
+        let device = sqlx::query_as(
+            "SELECT dev.id, dev.target_prototype_id, dev.collection_id, dev.firmware_id, dev.name, dev.description, dev.mac, dev.created_at, dev.updated_at
+             FROM devices as dev
+             WHERE dev.id = $1",
+        )
+        .bind(device_id)
+        .fetch_one(txn)
+        .await?;
+        Ok(device)
+    }
+
     pub async fn find_by_id(
         txn: &mut Transaction<'_>,
         device_id: DeviceId,
@@ -403,6 +449,9 @@ impl Device {
         collection: &Collection,
     ) -> Result<()> {
         let old_collection = self.collection(txn).await?;
+        if old_collection.id() != collection.id() {
+            AlertRule::leave_collection(txn, self, &old_collection).await?;
+        }
 
         let updated_at = Self::update_collection(txn, self.id, collection).await?;
 
@@ -444,6 +493,25 @@ impl Device {
         Ok(())
     }
 
+    /// Records that the device reported, it's how connectivity is detected
+    pub async fn touch(&self, txn: &mut Transaction<'_>) -> Result<()> {
+        sqlx::query("UPDATE devices SET last_seen_at = NOW() WHERE id = $1")
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+
+    /// When the device last reported, even if its events were refused
+    pub async fn last_seen_at(&self, txn: &mut Transaction<'_>) -> Result<Option<DateTime>> {
+        let (last_seen_at,): (Option<DateTime>,) =
+            sqlx::query_as("SELECT last_seen_at FROM devices WHERE id = $1")
+                .bind(self.id)
+                .fetch_one(txn)
+                .await?;
+        Ok(last_seen_at)
+    }
+
     pub async fn current_firmware(&self, txn: &mut Transaction<'_>) -> Result<Firmware> {
         Firmware::find_by_device(txn, self).await
     }
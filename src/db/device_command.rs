@@ -0,0 +1,514 @@
+use crate::{Cursor, DateTime, Device, DeviceId, Error, Page, Result, SensorId, Transaction};
+use derive::id;
+use derive_get::Getters;
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Commands expire after this long if no expiration is chosen
+pub const DEFAULT_COMMAND_TTL_SECONDS: i64 = 60 * 60;
+/// Commands delivered with a single event response, the rest wait for the next event
+pub const MAX_DELIVERED_COMMANDS: i64 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandArgumentType {
+    Boolean,
+    Integer,
+    Float,
+}
+
+impl CommandArgumentType {
+    /// Commands travel in a header, so there are no text arguments to escape
+    fn parse(&self, value: &serde_json::Value) -> Option<f64> {
+        match self {
+            Self::Boolean => value.as_bool().map(|v| if v { 1. } else { 0. }),
+            Self::Integer => value.as_i64().map(|v| v as f64),
+            Self::Float => value.as_f64(),
+        }
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommandArgument {
+    name: String,
+    ty: CommandArgumentType,
+    #[serde(default)]
+    #[copy]
+    min: Option<f64>,
+    #[serde(default)]
+    #[copy]
+    max: Option<f64>,
+}
+
+/// Command declared by a sensor prototype, the firmware dispatches it by the rendered variable name
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SensorCommand {
+    pub name: String,
+    pub variable_name: String,
+    #[serde(default)]
+    pub arguments: Vec<CommandArgument>,
+}
+
+impl SensorCommand {
+    /// Commands and their arguments are sent in a header and dispatched by the firmware, so they must be ASCII identifiers
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidDeviceCommand(reason));
+        if self.name.trim().is_empty() {
+            return invalid(format!("{} has no name", self.variable_name));
+        }
+        let command =
+            Handlebars::new().render_template(&self.variable_name, &json!({ "index": 0 }))?;
+        if !is_identifier(&command) {
+            return invalid(format!("{} is not an identifier", self.variable_name));
+        }
+        if let Some(argument) = self.arguments.iter().find(|a| !is_identifier(&a.name)) {
+            return invalid(format!(
+                "argument {} of {} is not an identifier",
+                argument.name, self.variable_name
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Command a device accepts, either from one of its sensors or supported by every firmware
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailableCommand {
+    #[copy]
+    sensor_id: Option<SensorId>,
+    name: String,
+    command: String,
+    arguments: Vec<CommandArgument>,
+}
+
+impl AvailableCommand {
+    /// Commands every firmware accepts, they have no arguments
+    pub fn builtin() -> Vec<Self> {
+        [
+            ("Reboot", "reboot"),
+            ("Factory Reset", "factoryReset"),
+            ("Flush Logs", "flushLogs"),
+        ]
+        .iter()
+        .map(|(name, command)| Self {
+            sensor_id: None,
+            name: (*name).to_owned(),
+            command: (*command).to_owned(),
+            arguments: Vec::new(),
+        })
+        .collect()
+    }
+
+    /// Builtin commands followed by the commands of the sensors in the device's compiler
+    pub async fn from_device(txn: &mut Transaction<'_>, device: &Device) -> Result<Vec<Self>> {
+        let mut commands = Self::builtin();
+        let collection = device.collection(txn).await?;
+        if let Some(compiler) = collection.compiler(txn).await? {
+            let reg = Handlebars::new();
+            for sensor in compiler.sensors(txn).await? {
+                for command in sensor.prototype().commands() {
+                    commands.push(Self {
+                        sensor_id: Some(sensor.id()),
+                        name: command.name.clone(),
+                        command: reg.render_template(
+                            &command.variable_name,
+                            &json!({ "index": sensor.index() }),
+                        )?,
+                        arguments: command.arguments.clone(),
+                    });
+                }
+            }
+        }
+        Ok(commands)
+    }
+
+    /// Every argument must be given, with its type and within its range
+    fn validate(&self, arguments: &serde_json::Map<String, serde_json::Value>) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidDeviceCommand(reason));
+        if let Some(name) = arguments
+            .keys()
+            .find(|name| !self.arguments.iter().any(|a| a.name == **name))
+        {
+            return invalid(format!("{} doesn't take argument {}", self.command, name));
+        }
+        for argument in &self.arguments {
+            let value = match arguments.get(&argument.name) {
+                Some(value) => value,
+                None => return invalid(format!("missing argument {}", argument.name)),
+            };
+            let number = match argument.ty.parse(value) {
+                Some(number) => number,
+                None => {
+                    return invalid(format!(
+                        "argument {} must be {:?}",
+                        argument.name, argument.ty
+                    ))
+                }
+            };
+            if argument.min.is_some_and(|min| number < min)
+                || argument.max.is_some_and(|max| number > max)
+            {
+                return invalid(format!(
+                    "argument {} out of range: {}",
+                    argument.name, number
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceCommandStatus {
+    /// Waiting for the device's next event
+    Pending,
+    /// Sent in an event response, waiting for the device to acknowledge it
+    Delivered,
+    /// The device executed it
+    Acknowledged,
+    /// The device was unable to execute it, see `error`
+    Failed,
+    /// Wasn't delivered or acknowledged in time
+    Expired,
+    /// Cancelled by the user before being delivered
+    Cancelled,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewDeviceCommand {
+    /// Rendered variable name, see `AvailableCommand`
+    pub command: String,
+    #[serde(default)]
+    pub arguments: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+}
+
+/// Sent by the device after executing a delivered command, `error` is set if it failed
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCommandAck {
+    pub id: DeviceCommandId,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[id]
+pub struct DeviceCommandId;
+
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCommand {
+    #[copy]
+    id: DeviceCommandId,
+    #[copy]
+    device_id: DeviceId,
+    #[copy]
+    sensor_id: Option<SensorId>,
+    name: String,
+    command: String,
+    arguments: serde_json::Value,
+    #[copy]
+    status: DeviceCommandStatus,
+    error: Option<String>,
+    #[copy]
+    expires_at: DateTime,
+    #[copy]
+    delivered_at: Option<DateTime>,
+    #[copy]
+    acknowledged_at: Option<DateTime>,
+    #[copy]
+    created_at: DateTime,
+    #[copy]
+    updated_at: DateTime,
+}
+
+impl DeviceCommand {
+    /// Queues the command to be delivered with the device's next event response
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        new_command: NewDeviceCommand,
+    ) -> Result<Self> {
+        let now = chrono::Utc::now();
+        let expires_at = new_command
+            .expires_at
+            .unwrap_or_else(|| now + chrono::Duration::seconds(DEFAULT_COMMAND_TTL_SECONDS));
+        if expires_at <= now {
+            return Err(Error::InvalidDeviceCommand(
+                "expiration must be in the future".to_owned(),
+            ));
+        }
+
+        let available = AvailableCommand::from_device(txn, device).await?;
+        let command = available
+            .into_iter()
+            .find(|c| c.command == new_command.command)
+            .ok_or_else(|| {
+                Error::InvalidDeviceCommand(format!("unknown command {}", new_command.command))
+            })?;
+        command.validate(&new_command.arguments)?;
+
+        let command = sqlx::query_as(
+            "INSERT INTO device_commands (device_id, sensor_id, name, command, arguments, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, device_id, sensor_id, name, command, arguments, status, error, expires_at, delivered_at, acknowledged_at, created_at, updated_at",
+        )
+        .bind(device.id())
+        .bind(command.sensor_id)
+        .bind(&command.name)
+        .bind(&command.command)
+        .bind(serde_json::Value::Object(new_command.arguments))
+        .bind(expires_at)
+        .fetch_one(txn)
+        .await?;
+        Ok(command)
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        id: DeviceCommandId,
+    ) -> Result<Self> {
+        let command = sqlx::query_as(
+            "SELECT id, device_id, sensor_id, name, command, arguments, status, error, expires_at, delivered_at, acknowledged_at, created_at, updated_at
+             FROM device_commands
+             WHERE id = $1 AND device_id = $2",
+        )
+        .bind(id)
+        .bind(device.id())
+        .fetch_one(txn)
+        .await?;
+        Ok(command)
+    }
+
+    /// Newest first
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let commands: Vec<Self> = sqlx::query_as(
+            "SELECT id, device_id, sensor_id, name, command, arguments, status, error, expires_at, delivered_at, acknowledged_at, created_at, updated_at
+             FROM device_commands
+             WHERE device_id = $1
+                   AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR (created_at, id) < ($3, $4))
+             ORDER BY created_at DESC, id DESC
+             LIMIT $5",
+        )
+        .bind(device.id())
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
+        .fetch_all(txn)
+        .await?;
+        Ok(Page::new(commands, limit, |c| {
+            Cursor::new(c.created_at, c.id)
+        }))
+    }
+
+    /// Marks the oldest pending commands as delivered, they must be sent in the event response
+    ///
+    /// Delivered commands aren't sent again, they expire if the device doesn't acknowledge them
+    pub async fn deliver(txn: &mut Transaction<'_>, device: &Device) -> Result<Vec<Self>> {
+        let mut commands: Vec<Self> = sqlx::query_as(
+            "UPDATE device_commands SET status = 'Delivered', delivered_at = NOW(), updated_at = NOW()
+             WHERE id IN (
+                 SELECT id FROM device_commands
+                 WHERE device_id = $1 AND status = 'Pending' AND expires_at > NOW()
+                 ORDER BY id ASC
+                 LIMIT $2
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, device_id, sensor_id, name, command, arguments, status, error, expires_at, delivered_at, acknowledged_at, created_at, updated_at",
+        )
+        .bind(device.id())
+        .bind(MAX_DELIVERED_COMMANDS)
+        .fetch_all(txn)
+        .await?;
+        commands.sort_by_key(|c| c.id);
+        Ok(commands)
+    }
+
+    /// What the device receives, it must acknowledge the id
+    pub fn payload(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "command": self.command,
+            "arguments": self.arguments,
+        })
+    }
+
+    /// The device never gets it, used when it can't be delivered
+    pub async fn fail(&mut self, txn: &mut Transaction<'_>, error: String) -> Result<()> {
+        let (updated_at,): (DateTime,) = sqlx::query_as(
+            "UPDATE device_commands SET status = 'Failed', error = $1, updated_at = NOW() WHERE id = $2 RETURNING updated_at",
+        )
+        .bind(&error)
+        .bind(self.id)
+        .fetch_one(txn)
+        .await?;
+        self.status = DeviceCommandStatus::Failed;
+        self.error = Some(error);
+        self.updated_at = updated_at;
+        Ok(())
+    }
+
+    /// Records the device's result, acknowledgements of already acknowledged commands are ignored so devices can retry
+    ///
+    /// Commands that expired after being delivered still record it, the device did execute them
+    pub async fn acknowledge(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        ack: DeviceCommandAck,
+    ) -> Result<Self> {
+        let status = if ack.error.is_some() {
+            DeviceCommandStatus::Failed
+        } else {
+            DeviceCommandStatus::Acknowledged
+        };
+        sqlx::query(
+            "UPDATE device_commands SET status = $1, error = $2, acknowledged_at = NOW(), updated_at = NOW()
+             WHERE id = $3 AND device_id = $4 AND delivered_at IS NOT NULL AND acknowledged_at IS NULL",
+        )
+        .bind(status)
+        .bind(&ack.error)
+        .bind(ack.id)
+        .bind(device.id())
+        .execute(&mut *txn)
+        .await?;
+
+        let command = Self::find_by_id(txn, device, ack.id).await?;
+        if command.delivered_at.is_none() {
+            return Err(Error::DeviceCommandNotDelivered(command.id));
+        }
+        Ok(command)
+    }
+
+    /// Only pending commands can be cancelled, delivered ones may have been executed already
+    pub async fn cancel(&mut self, txn: &mut Transaction<'_>) -> Result<()> {
+        let cancelled: Option<(DateTime,)> = sqlx::query_as(
+            "UPDATE device_commands SET status = 'Cancelled', updated_at = NOW()
+             WHERE id = $1 AND status = 'Pending'
+             RETURNING updated_at",
+        )
+        .bind(self.id)
+        .fetch_optional(txn)
+        .await?;
+        match cancelled {
+            Some((updated_at,)) => {
+                self.status = DeviceCommandStatus::Cancelled;
+                self.updated_at = updated_at;
+                Ok(())
+            }
+            None => Err(Error::DeviceCommandNotPending(self.id)),
+        }
+    }
+
+    /// Expires every command that wasn't delivered or acknowledged in time
+    pub async fn expire(txn: &mut Transaction<'_>) -> Result<u64> {
+        let expired = sqlx::query(
+            "UPDATE device_commands SET status = 'Expired', updated_at = NOW()
+             WHERE status IN ('Pending', 'Delivered') AND expires_at <= NOW()",
+        )
+        .execute(txn)
+        .await?;
+        Ok(expired.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> AvailableCommand {
+        AvailableCommand {
+            sensor_id: None,
+            name: "Run".to_owned(),
+            command: "runWaterPump0".to_owned(),
+            arguments: vec![CommandArgument {
+                name: "seconds".to_owned(),
+                ty: CommandArgumentType::Integer,
+                min: Some(1.),
+                max: Some(255.),
+            }],
+        }
+    }
+
+    fn arguments(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn validates_arguments() {
+        let command = command();
+        assert!(command
+            .validate(&arguments(json!({ "seconds": 10 })))
+            .is_ok());
+        assert!(command
+            .validate(&arguments(json!({ "seconds": 1 })))
+            .is_ok());
+        assert!(command
+            .validate(&arguments(json!({ "seconds": 255 })))
+            .is_ok());
+        for invalid in [
+            json!({}),
+            json!({ "seconds": 0 }),
+            json!({ "seconds": 256 }),
+            json!({ "seconds": 1.5 }),
+            json!({ "seconds": "10" }),
+            json!({ "seconds": 10, "other": 1 }),
+        ] {
+            assert!(
+                matches!(
+                    command.validate(&arguments(invalid.clone())),
+                    Err(Error::InvalidDeviceCommand(_))
+                ),
+                "{invalid}"
+            );
+        }
+        for builtin in AvailableCommand::builtin() {
+            assert!(builtin.validate(&serde_json::Map::new()).is_ok());
+        }
+    }
+
+    #[test]
+    fn sensor_commands_are_identifiers() {
+        let sensor_command = |variable_name: &str, argument: &str| SensorCommand {
+            name: "Run".to_owned(),
+            variable_name: variable_name.to_owned(),
+            arguments: vec![CommandArgument {
+                name: argument.to_owned(),
+                ty: CommandArgumentType::Boolean,
+                min: None,
+                max: None,
+            }],
+        };
+        assert!(sensor_command("runWaterPump{{index}}", "seconds")
+            .validate()
+            .is_ok());
+        assert!(sensor_command("run water", "seconds").validate().is_err());
+        assert!(sensor_command("run\u{7f}", "seconds").validate().is_err());
+        assert!(sensor_command("0run", "seconds").validate().is_err());
+        assert!(sensor_command("runWaterPump{{index}}", "sec onds")
+            .validate()
+            .is_err());
+        assert!(sensor_command("runWaterPump{{index}}", "")
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    logger::*, Alert, AlertId, AlertStatus, DateTime, Device, DeviceId, Notification, Result,
+    Transaction, MEASUREMENTS_INTERVAL_SECONDS,
+};
+use serde::{Deserialize, Serialize};
+
+/// Devices are offline after missing this many reports in a row
+pub const OFFLINE_MISSED_INTERVALS: i64 = 5;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceConnectivity {
+    Online,
+    /// Went too long without sending events, or never sent one
+    Offline,
+}
+
+/// What the last detection recorded, next to when the device last reported
+#[derive(sqlx::FromRow, Clone, Copy, Debug, PartialEq)]
+struct ConnectivityState {
+    device_id: DeviceId,
+    status: Option<DeviceConnectivity>,
+    /// Last report when the status last changed, the one before the silence for offline devices
+    last_seen_at: Option<DateTime>,
+    /// Firing offline alert
+    alert_id: Option<AlertId>,
+    latest_seen_at: Option<DateTime>,
+}
+
+impl DeviceConnectivity {
+    /// How long a device may go without reporting, its expected report interval times the allowed misses
+    ///
+    /// Note: the interval is the one this server compiles into every firmware, devices running firmwares compiled with another interval,
+    /// or built outside of the server, are judged by this one
+    pub fn offline_after() -> chrono::Duration {
+        chrono::Duration::seconds(MEASUREMENTS_INTERVAL_SECONDS * OFFLINE_MISSED_INTERVALS)
+    }
+
+    /// `last_seen_at` is when the device last reported, see `Device::touch`
+    pub fn new(last_seen_at: Option<DateTime>, now: DateTime) -> Self {
+        match last_seen_at {
+            Some(last_seen_at) if now - last_seen_at <= Self::offline_after() => Self::Online,
+            _ => Self::Offline,
+        }
+    }
+
+    /// Records every device's connectivity, alerting when a device goes offline and when it's back
+    ///
+    /// Devices are only recorded the first time they are seen, so a new deployment doesn't alert about every silent device
+    pub async fn detect(txn: &mut Transaction<'_>) -> Result<()> {
+        let now = chrono::Utc::now();
+        let states: Vec<ConnectivityState> = sqlx::query_as(
+            "SELECT devices.id AS device_id, dc.status, dc.last_seen_at, dc.alert_id, devices.last_seen_at AS latest_seen_at
+             FROM devices
+             LEFT JOIN device_connectivity dc ON dc.device_id = devices.id",
+        )
+        .fetch_all(&mut *txn)
+        .await?;
+
+        for state in states {
+            let connectivity = Self::new(state.latest_seen_at, now);
+            match state.status {
+                None => {
+                    sqlx::query(
+                        "INSERT INTO device_connectivity (device_id, status, last_seen_at) VALUES ($1, $2, $3)
+                         ON CONFLICT (device_id) DO NOTHING",
+                    )
+                    .bind(state.device_id)
+                    .bind(connectivity)
+                    .bind(state.latest_seen_at)
+                    .execute(&mut *txn)
+                    .await?;
+                }
+                Some(status) if status != connectivity => {
+                    Self::transition(&mut *txn, state, connectivity, now).await?;
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn transition(
+        txn: &mut Transaction<'_>,
+        state: ConnectivityState,
+        connectivity: Self,
+        now: DateTime,
+    ) -> Result<()> {
+        // Server instances race to detect the same transition, only the one that moves the status alerts
+        let moved = sqlx::query(
+            "UPDATE device_connectivity SET status = $1, last_seen_at = $2, alert_id = NULL, updated_at = NOW()
+             WHERE device_id = $3 AND status <> $1",
+        )
+        .bind(connectivity)
+        .bind(state.latest_seen_at)
+        .bind(state.device_id)
+        .execute(&mut *txn)
+        .await?;
+        if moved.rows_affected() == 0 {
+            return Ok(());
+        }
+
+        let device = Device::raw_find_by_id(&mut *txn, state.device_id).await?;
+        match connectivity {
+            Self::Offline => {
+                info!("Device {:?} is offline", device.id());
+                let silence = state
+                    .latest_seen_at
+                    .map_or(Self::offline_after(), |last_seen_at| now - last_seen_at);
+                let alert = Alert::new(
+                    &mut *txn,
+                    None,
+                    &device,
+                    AlertStatus::Firing,
+                    silence.num_seconds() as f64,
+                    now,
+                )
+                .await?;
+                Notification::enqueue(&mut *txn, &device, &alert, None, None).await?;
+                sqlx::query("UPDATE device_connectivity SET alert_id = $1 WHERE device_id = $2")
+                    .bind(alert.id())
+                    .bind(device.id())
+                    .execute(txn)
+                    .await?;
+            }
+            Self::Online => {
+                info!("Device {:?} is back online", device.id());
+                // Devices recorded as offline when first seen never fired
+                if state.alert_id.is_none() {
+                    return Ok(());
+                }
+
+                // Resolved when the first event after the silence arrived, or when it reported if its events were refused
+                let (back_at,): (Option<DateTime>,) = sqlx::query_as(
+                    "SELECT MIN(created_at) FROM events WHERE device_id = $1 AND ($2::TIMESTAMPTZ IS NULL OR created_at > $2)",
+                )
+                .bind(device.id())
+                .bind(state.last_seen_at)
+                .fetch_one(&mut *txn)
+                .await?;
+                let back_at = back_at.or(state.latest_seen_at).unwrap_or(now);
+                let silence = state
+                    .last_seen_at
+                    .map_or(Self::offline_after(), |last_seen_at| back_at - last_seen_at);
+                let alert = Alert::new(
+                    &mut *txn,
+                    None,
+                    &device,
+                    AlertStatus::Resolved,
+                    silence.num_seconds() as f64,
+                    back_at,
+                )
+                .await?;
+                Notification::enqueue(txn, &device, &alert, None, None).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connectivity() {
+        let now = chrono::Utc::now();
+        assert_eq!(
+            DeviceConnectivity::new(None, now),
+            DeviceConnectivity::Offline
+        );
+        assert_eq!(
+            DeviceConnectivity::new(Some(now), now),
+            DeviceConnectivity::Online
+        );
+        let limit = now - DeviceConnectivity::offline_after();
+        assert_eq!(
+            DeviceConnectivity::new(Some(limit), now),
+            DeviceConnectivity::Online
+        );
+        assert_eq!(
+            DeviceConnectivity::new(Some(limit - chrono::Duration::seconds(1)), now),
+            DeviceConnectivity::Offline
+        );
+        assert_eq!(
+            DeviceConnectivity::offline_after(),
+            chrono::Duration::seconds(MEASUREMENTS_INTERVAL_SECONDS * OFFLINE_MISSED_INTERVALS)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    Aggregation, Bucket, DateTime, Device, DeviceId, DeviceStat, Error, Result, Transaction,
+    MAX_BUCKETS,
+};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How far back the health of a device is analyzed
+pub const HEALTH_WINDOW_HOURS: i64 = 24;
+/// Newest events considered when analyzing the health of a device
+const HEALTH_SAMPLES: i64 = 500;
+/// A leak needs this many samples since the last reboot to be told apart from noise
+const LEAK_MIN_SAMPLES: usize = 6;
+/// Share of consecutive samples in which free DRAM must not grow
+const LEAK_MIN_DECLINING_RATIO: f64 = 0.9;
+/// Free DRAM must have shrunk at least this much since the reboot
+const LEAK_MIN_DECLINE_RATIO: f64 = 0.05;
+/// The heap is fragmented when the biggest block is smaller than this share of the free DRAM
+const FRAGMENTATION_RATIO: f64 = 0.5;
+/// VCC drops that reach this far below the median are brownouts
+const BROWNOUT_DROP_RATIO: f64 = 0.1;
+
+/// Aggregated values of a `DeviceStat` field inside a bucket, only the requested aggregations are filled
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStatAggregate {
+    stat: String,
+    #[copy]
+    bucket: DateTime,
+    #[copy]
+    min: Option<f64>,
+    #[copy]
+    max: Option<f64>,
+    #[copy]
+    avg: Option<f64>,
+    #[copy]
+    last: Option<f64>,
+    #[copy]
+    count: Option<i64>,
+}
+
+impl DeviceStatAggregate {
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        since: DateTime,
+        until: DateTime,
+        bucket: Bucket,
+        aggregations: &[Aggregation],
+    ) -> Result<Vec<Self>> {
+        let since = bucket.floor(since);
+        if (until - since).num_seconds() / bucket.seconds() > MAX_BUCKETS {
+            return Err(Error::AskedForTooMany);
+        }
+
+        // Stats are collected when the event is sent, so device side timestamps don't apply
+        let mut aggregates: Vec<Self> = sqlx::query_as(
+            "SELECT stat.key AS stat, to_timestamp(floor(extract(epoch FROM events.created_at)::DOUBLE PRECISION / $4) * $4) AS bucket,
+                    MIN((stat.value #>> '{}')::DOUBLE PRECISION) AS min, MAX((stat.value #>> '{}')::DOUBLE PRECISION) AS max,
+                    AVG((stat.value #>> '{}')::DOUBLE PRECISION) AS avg,
+                    (ARRAY_AGG((stat.value #>> '{}')::DOUBLE PRECISION ORDER BY events.created_at DESC))[1] AS last,
+                    COUNT(*) AS count
+             FROM events
+             CROSS JOIN LATERAL jsonb_each(events.stat) stat
+             WHERE events.device_id = $1 AND events.created_at >= $2 AND events.created_at < $3
+                   AND jsonb_typeof(stat.value) = 'number'
+             GROUP BY stat.key, bucket
+             ORDER BY bucket ASC, stat ASC",
+        )
+        .bind(device.id())
+        .bind(since)
+        .bind(until)
+        .bind(bucket.seconds() as f64)
+        .fetch_all(txn)
+        .await?;
+
+        for aggregate in &mut aggregates {
+            Aggregation::mask(
+                aggregations,
+                &mut aggregate.min,
+                &mut aggregate.max,
+                &mut aggregate.avg,
+                &mut aggregate.last,
+                &mut aggregate.count,
+            );
+        }
+        Ok(aggregates)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceHealthFlag {
+    /// Free DRAM keeps shrinking since the last reboot
+    MemoryLeak,
+    /// Free DRAM is mostly made of blocks too small to be used
+    Fragmentation,
+    /// VCC dropped well below its usual level
+    Brownout,
+}
+
+impl DeviceHealthFlag {
+    /// Analyzes the recent stats of every device at once, devices without stats have no flags
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device_ids: &[DeviceId],
+    ) -> Result<HashMap<DeviceId, Vec<Self>>> {
+        let rows: Vec<(DeviceId, serde_json::Value)> = sqlx::query_as(
+            "SELECT device_id, stat
+             FROM (
+                 SELECT device_id, stat, created_at, id,
+                        ROW_NUMBER() OVER (PARTITION BY device_id ORDER BY created_at DESC, id DESC) AS newest
+                 FROM events
+                 WHERE device_id = ANY($1) AND created_at >= $2
+             ) recent
+             WHERE newest <= $3
+             ORDER BY device_id ASC, created_at ASC, id ASC",
+        )
+        .bind(device_ids)
+        .bind(chrono::Utc::now() - chrono::Duration::hours(HEALTH_WINDOW_HOURS))
+        .bind(HEALTH_SAMPLES)
+        .fetch_all(txn)
+        .await?;
+
+        // Events from before the stat was sent are skipped, as they can't be parsed
+        let mut stats: HashMap<DeviceId, Vec<DeviceStat>> = HashMap::new();
+        for (device_id, stat) in rows {
+            if let Ok(stat) = serde_json::from_value(stat) {
+                stats.entry(device_id).or_default().push(stat);
+            }
+        }
+        Ok(stats
+            .into_iter()
+            .map(|(device_id, stats)| (device_id, Self::analyze(&stats)))
+            .collect())
+    }
+
+    /// Expects stats in chronological order
+    pub fn analyze(stats: &[DeviceStat]) -> Vec<Self> {
+        let mut flags = Vec::new();
+        if Self::is_leaking(stats) {
+            flags.push(Self::MemoryLeak);
+        }
+        if let Some(last) = stats.last() {
+            if (last.biggest_dram_block as f64) < last.free_dram as f64 * FRAGMENTATION_RATIO {
+                flags.push(Self::Fragmentation);
+            }
+        }
+        if Self::browned_out(stats) {
+            flags.push(Self::Brownout);
+        }
+        flags
+    }
+
+    fn is_leaking(stats: &[DeviceStat]) -> bool {
+        // Only the samples since the last reboot matter, rebooting frees everything
+        let boot = stats
+            .windows(2)
+            .rposition(|pair| pair[1].time_running < pair[0].time_running)
+            .map_or(0, |index| index + 1);
+        let stats = &stats[boot..];
+        if stats.len() < LEAK_MIN_SAMPLES {
+            return false;
+        }
+
+        let declining = stats
+            .windows(2)
+            .filter(|pair| pair[1].free_dram <= pair[0].free_dram)
+            .count();
+        let (first, last) = (
+            stats[0].free_dram as f64,
+            stats[stats.len() - 1].free_dram as f64,
+        );
+        declining as f64 >= (stats.len() - 1) as f64 * LEAK_MIN_DECLINING_RATIO
+            && last <= first * (1. - LEAK_MIN_DECLINE_RATIO)
+    }
+
+    fn browned_out(stats: &[DeviceStat]) -> bool {
+        let mut vccs: Vec<u16> = stats.iter().map(|s| s.vcc).collect();
+        if vccs.is_empty() {
+            return false;
+        }
+        vccs.sort_unstable();
+        let median = vccs[vccs.len() / 2] as f64;
+        (vccs[0] as f64) < median * (1. - BROWNOUT_DROP_RATIO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(time_running: u64, vcc: u16, free_dram: u64, biggest_dram_block: u64) -> DeviceStat {
+        DeviceStat {
+            version: "aaaa".to_owned(),
+            time_running,
+            vcc,
+            free_dram,
+            free_iram: None,
+            free_stack: 4096,
+            biggest_dram_block,
+            biggest_iram_block: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn healthy() {
+        let stats: Vec<_> = (0..10).map(|i| stat(i * 60, 3300, 40000, 30000)).collect();
+        assert_eq!(DeviceHealthFlag::analyze(&stats), vec![]);
+        assert_eq!(DeviceHealthFlag::analyze(&[]), vec![]);
+    }
+
+    #[test]
+    fn memory_leak_since_last_reboot() {
+        let leaking: Vec<_> = (0..10)
+            .map(|i| stat(i * 60, 3300, 40000 - i * 500, 30000))
+            .collect();
+        assert_eq!(
+            DeviceHealthFlag::analyze(&leaking),
+            vec![DeviceHealthFlag::MemoryLeak]
+        );
+
+        // Rebooting frees everything, too few samples are left to tell
+        let mut rebooted = leaking;
+        rebooted.extend((0..3).map(|i| stat(i * 60, 3300, 40000 - i * 500, 30000)));
+        assert_eq!(DeviceHealthFlag::analyze(&rebooted), vec![]);
+    }
+
+    #[test]
+    fn fragmentation_of_the_last_sample() {
+        let mut stats = vec![stat(0, 3300, 40000, 10000), stat(60, 3300, 40000, 30000)];
+        assert_eq!(DeviceHealthFlag::analyze(&stats), vec![]);
+        stats.reverse();
+        assert_eq!(
+            DeviceHealthFlag::analyze(&stats),
+            vec![DeviceHealthFlag::Fragmentation]
+        );
+    }
+
+    #[test]
+    fn brownout_below_the_median() {
+        let mut stats: Vec<_> = (0..5).map(|i| stat(i * 60, 3300, 40000, 30000)).collect();
+        stats[2].vcc = 3000;
+        assert_eq!(DeviceHealthFlag::analyze(&stats), vec![]);
+        stats[2].vcc = 2900;
+        assert_eq!(
+            DeviceHealthFlag::analyze(&stats),
+            vec![DeviceHealthFlag::Brownout]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{logger::*, DateTime, Device, Error, Result, Transaction};
+use crate::{logger::*, Cursor, DateTime, Device, Page, Result, Transaction};
 use derive::id;
 use serde::{Deserialize, Serialize};
 
@@ -32,30 +32,51 @@ impl DeviceLog {
         })
     }
 
+    pub async fn raw_find_by_id(txn: &mut Transaction<'_>, id: DeviceLogId) -> Result<Self> {
+        let device_log = sqlx::query_as(
+            "SELECT device_logs.id, device_logs.log, device_logs.created_at
+            FROM device_logs
+            WHERE device_logs.id = $1",
+        )
+        .bind(id)
+        .fetch_one(txn)
+        .await?;
+        Ok(device_log)
+    }
+
+    pub fn id(&self) -> DeviceLogId {
+        self.id
+    }
+
     pub fn log(&self) -> &str {
         &self.log
     }
 
-    pub async fn first_n_from_device(
+    pub async fn list(
         txn: &mut Transaction<'_>,
         device: &Device,
-        limit: i32,
-    ) -> Result<Vec<Self>> {
-        if limit > 10000 {
-            return Err(Error::AskedForTooMany);
-        }
-
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
         let device_logs: Vec<DeviceLog> = sqlx::query_as(
             "SELECT device_logs.id, device_logs.log, device_logs.created_at
             FROM device_logs
             WHERE device_id = $1
-            ORDER BY device_logs.created_at DESC
-            LIMIT $2",
+                  AND ($2::TIMESTAMPTZ IS NULL OR device_logs.created_at < $2)
+                  AND ($3::TIMESTAMPTZ IS NULL OR (device_logs.created_at, device_logs.id) < ($3, $4))
+            ORDER BY device_logs.created_at DESC, device_logs.id DESC
+            LIMIT $5",
         )
         .bind(device.id())
-        .bind(limit)
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
         .fetch_all(txn)
         .await?;
-        Ok(device_logs.into_iter().rev().collect())
+        Ok(Page::new(device_logs, limit, |l| {
+            Cursor::new(l.created_at, l.id)
+        }))
     }
 }
@@ -1,4 +1,6 @@
-use crate::{logger::*, DateTime, Device, Error, Result, Transaction};
+use crate::{
+    logger::*, Cursor, DateTime, Device, Page, PanicGroup, PanicGroupId, Result, Transaction,
+};
 use derive::id;
 use derive_get::Getters;
 use serde::{Deserialize, Serialize};
@@ -22,11 +24,16 @@ pub type DevicePanicView = DevicePanic;
 pub struct DevicePanic {
     #[copy]
     id: DevicePanicId,
+    /// Only missing for panics from before the grouping
+    #[copy]
+    panic_group_id: Option<PanicGroupId>,
     file: String,
     #[copy]
     line: i32,
     func: String,
     msg: String,
+    /// Firmware the device was running
+    firmware_hash: Option<String>,
     #[copy]
     created_at: DateTime,
 }
@@ -38,31 +45,53 @@ impl DevicePanic {
         new_device_panic: NewDevicePanic,
     ) -> Result<Self> {
         info!("Log (device_id: {:?}): {:?}", device.id(), new_device_panic);
-        let (id, now): (DevicePanicId, DateTime) = sqlx::query_as("INSERT INTO device_panics (device_id, \"file\", line, func, msg) VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at")
+        let organization = device.collection(txn).await?.organization(txn).await?;
+        let firmware = device.current_firmware(txn).await?;
+        let panic_group_id =
+            PanicGroup::record(txn, &organization, device, &firmware, &new_device_panic).await?;
+
+        // Devices still running the firmware the group was solved on keep reporting it, those are solved already
+        let (id, now): (DevicePanicId, DateTime) = sqlx::query_as("INSERT INTO device_panics (device_id, panic_group_id, \"file\", line, func, msg, firmware_hash, is_solved) VALUES ($1, $2, $3, $4, $5, $6, $7, (SELECT is_solved FROM panic_groups WHERE id = $2)) RETURNING id, created_at")
             .bind(device.id())
+            .bind(panic_group_id)
             .bind(&new_device_panic.file)
             .bind(new_device_panic.line)
             .bind(&new_device_panic.func)
             .bind(&new_device_panic.msg)
+            .bind(firmware.binary_hash())
             .fetch_one(txn)
             .await?;
         Ok(Self {
             id,
+            panic_group_id: Some(panic_group_id),
             file: new_device_panic.file,
             line: new_device_panic.line,
             func: new_device_panic.func,
             msg: new_device_panic.msg,
+            firmware_hash: Some(firmware.binary_hash().clone()),
             created_at: now,
         })
     }
 
+    pub async fn raw_find_by_id(txn: &mut Transaction<'_>, id: DevicePanicId) -> Result<Self> {
+        let panic = sqlx::query_as(
+            "SELECT p.id, p.panic_group_id, p.file, p.line, p.func, p.msg, p.firmware_hash, p.created_at
+            FROM device_panics as p
+            WHERE p.id = $1",
+        )
+        .bind(id)
+        .fetch_one(txn)
+        .await?;
+        Ok(panic)
+    }
+
     pub async fn find_by_id(
         txn: &mut Transaction<'_>,
         device: &Device,
         id: DevicePanicId,
     ) -> Result<Self> {
         let panic = sqlx::query_as(
-            "SELECT p.id, p.file, p.line, p.func, p.msg, p.created_at
+            "SELECT p.id, p.panic_group_id, p.file, p.line, p.func, p.msg, p.firmware_hash, p.created_at
             FROM device_panics as p
             WHERE p.device_id = $1 AND p.id = $2",
         )
@@ -73,30 +102,42 @@ impl DevicePanic {
         Ok(panic)
     }
 
-    pub async fn first_n_from_device(
+    pub async fn list(
         txn: &mut Transaction<'_>,
         device: &Device,
-        limit: i32,
-    ) -> Result<Vec<Self>> {
-        if limit > 10000 {
-            return Err(Error::AskedForTooMany);
-        }
-
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
         let device_panics: Vec<Self> = sqlx::query_as(
-            "SELECT p.id, p.file, p.line, p.func, p.msg, p.created_at
+            "SELECT p.id, p.panic_group_id, p.file, p.line, p.func, p.msg, p.firmware_hash, p.created_at
             FROM device_panics as p
             WHERE p.device_id = $1
-            ORDER BY p.created_at ASC
-            LIMIT $2",
+                  AND ($2::TIMESTAMPTZ IS NULL OR p.created_at < $2)
+                  AND ($3::TIMESTAMPTZ IS NULL OR (p.created_at, p.id) < ($3, $4))
+            ORDER BY p.created_at DESC, p.id DESC
+            LIMIT $5",
         )
         .bind(device.id())
-        .bind(limit)
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
         .fetch_all(txn)
         .await?;
-        Ok(device_panics.into_iter().rev().collect())
+        Ok(Page::new(device_panics, limit, |p| {
+            Cursor::new(p.created_at, p.id)
+        }))
     }
 
-    pub async fn solve(self, txn: &mut Transaction<'_>) -> Result<()> {
+    /// Solves every panic of its group
+    pub async fn solve(self, txn: &mut Transaction<'_>, device: &Device) -> Result<()> {
+        if let Some(panic_group_id) = self.panic_group_id {
+            let organization = device.collection(txn).await?.organization(txn).await?;
+            let mut group = PanicGroup::find_by_id(txn, &organization, panic_group_id).await?;
+            return group.solve(txn).await;
+        }
+
         sqlx::query("UPDATE device_panics SET is_solved = TRUE, updated_at = NOW() WHERE id = $1")
             .bind(self.id)
             .execute(txn)
@@ -1,4 +1,7 @@
-use crate::{logger::*, DateTime, Device, Firmware, Result, SensorMeasurementView, Transaction};
+use crate::{
+    logger::*, Cursor, DateTime, Device, Error, Firmware, Page, Result, SensorMeasurementView,
+    Transaction,
+};
 use derive::id;
 use derive_get::Getters;
 use handlebars::Handlebars;
@@ -23,8 +26,21 @@ pub struct DeviceStat {
     pub biggest_dram_block: u64,
     #[copy]
     pub biggest_iram_block: Option<u64>,
+    /// Stats this server doesn't know about yet, they are stored as sent
+    #[serde(default, flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
+/// Latest version of the `/v2/event` body
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Maximum amount of events in a single batch
+pub const MAX_EVENT_BATCH: usize = 500;
+/// How far in the future a device's clock may be
+pub const MAX_CLOCK_SKEW_SECONDS: i64 = 5 * 60;
+/// How long a device may hold events before sending them
+pub const MAX_EVENT_AGE_DAYS: i64 = 30;
+
 #[id]
 pub struct EventId;
 
@@ -35,6 +51,8 @@ pub struct EventView {
     stat: DeviceStat,
     metadatas: Vec<SensorMeasurementView>,
     #[copy]
+    measured_at: DateTime,
+    #[copy]
     created_at: DateTime,
 }
 
@@ -43,6 +61,7 @@ impl EventView {
         Ok(Self {
             measurements: event.measurements().clone(),
             metadatas: serde_json::from_value(event.metadatas().clone())?,
+            measured_at: event.measured_or_created_at(),
             created_at: event.created_at(),
             stat: serde_json::from_value(event.stat().clone())?,
         })
@@ -57,17 +76,22 @@ pub struct Event {
     stat: serde_json::Value,
     metadatas: serde_json::Value,
     firmware_hash: String,
+    // Only set when the device timestamped it, otherwise it was measured when received
+    #[copy]
+    measured_at: Option<DateTime>,
     #[copy]
     created_at: DateTime,
 }
 
 impl Event {
+    /// Returns `None` if the device already sent an event measured at the same moment
     pub async fn new(
         txn: &mut Transaction<'_>,
         device: &Device,
         measurements: serde_json::Value,
         stat: DeviceStat,
-    ) -> Result<Self> {
+        measured_at: Option<DateTime>,
+    ) -> Result<Option<Self>> {
         let collection = device.collection(txn).await?;
         let organization = collection.organization(txn).await?;
 
@@ -108,23 +132,58 @@ impl Event {
         let metadatas = serde_json::to_value(metadatas)?;
 
         let stat_json = serde_json::to_value(&stat)?;
-        let (id, now): (EventId, DateTime) =
-            sqlx::query_as("INSERT INTO events (device_id, measurements, metadatas, firmware_hash, stat) VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at")
-                .bind(device.id())
-                .bind(&measurements)
-                .bind(&metadatas)
-                .bind(&stat.version)
-                .bind(&stat_json)
-                .fetch_one(&mut *txn)
-                .await?;
-        Ok(Self {
+        let row: Option<(EventId, DateTime)> = sqlx::query_as(
+            "INSERT INTO events (device_id, measurements, metadatas, firmware_hash, stat, measured_at) VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (device_id, measured_at) WHERE measured_at IS NOT NULL DO NOTHING
+             RETURNING id, created_at",
+        )
+        .bind(device.id())
+        .bind(&measurements)
+        .bind(&metadatas)
+        .bind(&stat.version)
+        .bind(&stat_json)
+        .bind(measured_at)
+        .fetch_optional(&mut *txn)
+        .await?;
+        let (id, now) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        Ok(Some(Self {
             id,
             measurements,
             metadatas,
             stat: stat_json,
             firmware_hash: stat.version,
+            measured_at,
             created_at: now,
-        })
+        }))
+    }
+
+    pub fn check_clock_skew(measured_at: DateTime, now: DateTime) -> Result<()> {
+        if measured_at > now + chrono::Duration::seconds(MAX_CLOCK_SKEW_SECONDS)
+            || measured_at < now - chrono::Duration::days(MAX_EVENT_AGE_DAYS)
+        {
+            return Err(Error::ClockSkew(measured_at, now));
+        }
+        Ok(())
+    }
+
+    /// When the measurements were taken
+    pub fn measured_or_created_at(&self) -> DateTime {
+        self.measured_at.unwrap_or(self.created_at)
+    }
+
+    pub async fn raw_find_by_id(txn: &mut Transaction<'_>, id: EventId) -> Result<Self> {
+        let event = sqlx::query_as(
+            "SELECT id, measurements, metadatas, firmware_hash, stat, measured_at, created_at
+            FROM events
+            WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(txn)
+        .await?;
+        Ok(event)
     }
 
     pub async fn last_from_device(
@@ -132,7 +191,7 @@ impl Event {
         device: &Device,
     ) -> Result<Option<Self>> {
         let event: Option<Event> = sqlx::query_as(
-            "SELECT id, measurements, metadatas, firmware_hash, stat, created_at
+            "SELECT id, measurements, metadatas, firmware_hash, stat, measured_at, created_at
             FROM events
             WHERE device_id = $1
             ORDER BY created_at DESC",
@@ -148,17 +207,30 @@ impl Event {
         txn: &mut Transaction<'_>,
         device: &Device,
         since: DateTime,
-    ) -> Result<Vec<Self>> {
-        let event: Vec<Event> = sqlx::query_as(
-            "SELECT id, measurements, metadatas, firmware_hash, stat, created_at
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let events: Vec<Event> = sqlx::query_as(
+            "SELECT id, measurements, metadatas, firmware_hash, stat, measured_at, created_at
             FROM events
-            WHERE device_id = $1 AND created_at >= $2
-            ORDER BY created_at DESC",
+            WHERE device_id = $1
+                  AND COALESCE(measured_at, created_at) >= $2
+                  AND ($3::TIMESTAMPTZ IS NULL OR COALESCE(measured_at, created_at) < $3)
+                  AND ($4::TIMESTAMPTZ IS NULL OR (COALESCE(measured_at, created_at), id) < ($4, $5))
+            ORDER BY COALESCE(measured_at, created_at) DESC, id DESC
+            LIMIT $6",
         )
         .bind(device.id())
         .bind(since)
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
         .fetch_all(txn)
         .await?;
-        Ok(event)
+        Ok(Page::new(events, limit, |e| {
+            Cursor::new(e.measured_or_created_at(), e.id)
+        }))
     }
 }
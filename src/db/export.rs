@@ -0,0 +1,259 @@
+use crate::{Collection, DateTime, Device, DeviceId, Pool, Result, SensorView, Transaction};
+use derive_get::Getters;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// `DeviceStat` fields, as stored in the event
+pub const STAT_COLUMNS: [&str; 8] = [
+    "version",
+    "timeRunning",
+    "vcc",
+    "freeDram",
+    "freeIram",
+    "freeStack",
+    "biggestDramBlock",
+    "biggestIramBlock",
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportColumn {
+    variable_name: String,
+    label: String,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct ExportRow {
+    device_id: DeviceId,
+    device_name: String,
+    measurements: serde_json::Value,
+    stat: serde_json::Value,
+    measured_at: DateTime,
+    created_at: DateTime,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ExportLine<'a> {
+    device_id: DeviceId,
+    device_name: &'a str,
+    measured_at: DateTime,
+    created_at: DateTime,
+    measurements: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat: Option<&'a serde_json::Value>,
+}
+
+/// Events of a set of devices over a time range, streamed straight from the database
+#[derive(Getters, Debug, Clone)]
+pub struct Export {
+    #[copy]
+    format: ExportFormat,
+    columns: Vec<ExportColumn>,
+    #[copy]
+    with_stat: bool,
+    device_ids: Vec<DeviceId>,
+    #[copy]
+    since: DateTime,
+    #[copy]
+    until: DateTime,
+}
+
+impl Export {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+        devices: &[Device],
+        since: DateTime,
+        until: DateTime,
+        format: ExportFormat,
+        with_stat: bool,
+    ) -> Result<Self> {
+        let device_ids: Vec<DeviceId> = devices.iter().map(|d| d.id()).collect();
+
+        // Columns follow the compiler's sensors, whatever else was reported comes after them
+        let mut columns = Vec::new();
+        if let Some(compiler) = collection.compiler(txn).await? {
+            for sensor in SensorView::list_for_compiler(txn, &compiler).await? {
+                for measurement in sensor.measurements() {
+                    let label = if sensor.alias().is_empty() {
+                        measurement.name().to_owned()
+                    } else {
+                        format!("{} - {}", sensor.alias(), measurement.name())
+                    };
+                    columns.push(ExportColumn {
+                        variable_name: measurement.variable_name().to_owned(),
+                        label,
+                    });
+                }
+            }
+        }
+
+        let reported: Vec<(String,)> = sqlx::query_as(
+            "SELECT DISTINCT jsonb_object_keys(measurements) AS variable_name
+             FROM events
+             WHERE device_id = ANY($1)
+                   AND COALESCE(measured_at, created_at) >= $2
+                   AND COALESCE(measured_at, created_at) < $3
+                   AND jsonb_typeof(measurements) = 'object'
+             ORDER BY variable_name ASC",
+        )
+        .bind(&device_ids)
+        .bind(since)
+        .bind(until)
+        .fetch_all(txn)
+        .await?;
+        for (variable_name,) in reported {
+            if !columns.iter().any(|c| c.variable_name == variable_name) {
+                columns.push(ExportColumn {
+                    label: variable_name.clone(),
+                    variable_name,
+                });
+            }
+        }
+
+        Ok(Self {
+            format,
+            columns,
+            with_stat,
+            device_ids,
+            since,
+            until,
+        })
+    }
+
+    pub fn filename(&self, name: &str) -> String {
+        format!(
+            "{}-{}-{}.{}",
+            name,
+            self.since.format("%Y%m%d%H%M%S"),
+            self.until.format("%Y%m%d%H%M%S"),
+            self.format.extension()
+        )
+    }
+
+    /// Each item is a line of the file, the whole export is never held in memory
+    pub fn stream(self, pool: &'static Pool) -> impl Stream<Item = Result<String>> + Send {
+        let header = match self.format {
+            ExportFormat::Csv => Some(Ok(self.csv_header())),
+            ExportFormat::Ndjson => None,
+        };
+        let rows = sqlx::query_as::<_, ExportRow>(
+            "SELECT events.device_id, devices.name AS device_name, events.measurements, events.stat,
+                    COALESCE(events.measured_at, events.created_at) AS measured_at, events.created_at
+             FROM events
+             INNER JOIN devices ON devices.id = events.device_id
+             WHERE events.device_id = ANY($1)
+                   AND COALESCE(events.measured_at, events.created_at) >= $2
+                   AND COALESCE(events.measured_at, events.created_at) < $3
+             ORDER BY COALESCE(events.measured_at, events.created_at) ASC, events.id ASC",
+        )
+        .bind(self.device_ids.clone())
+        .bind(self.since)
+        .bind(self.until)
+        .fetch(pool);
+
+        futures::stream::iter(header).chain(rows.map(move |row| self.line(row?)))
+    }
+
+    fn csv_header(&self) -> String {
+        let mut header = vec!["measuredAt", "createdAt", "deviceId", "deviceName"];
+        header.extend(self.columns.iter().map(|c| c.label.as_str()));
+        if self.with_stat {
+            header.extend(STAT_COLUMNS);
+        }
+        csv_line(header.into_iter().map(Cow::Borrowed))
+    }
+
+    fn line(&self, row: ExportRow) -> Result<String> {
+        let measurement = |name: &str| row.measurements.get(name).cloned();
+        match self.format {
+            ExportFormat::Csv => {
+                let mut fields = vec![
+                    Cow::Owned(row.measured_at.to_rfc3339()),
+                    Cow::Owned(row.created_at.to_rfc3339()),
+                    Cow::Owned(row.device_id.to_string()),
+                    Cow::Borrowed(row.device_name.as_str()),
+                ];
+                fields.extend(
+                    self.columns
+                        .iter()
+                        .map(|c| csv_value(measurement(&c.variable_name))),
+                );
+                if self.with_stat {
+                    fields.extend(
+                        STAT_COLUMNS
+                            .iter()
+                            .map(|name| csv_value(row.stat.get(name).cloned())),
+                    );
+                }
+                Ok(csv_line(fields.into_iter()))
+            }
+            ExportFormat::Ndjson => {
+                let measurements = self
+                    .columns
+                    .iter()
+                    .filter_map(|c| Some((c.label.clone(), measurement(&c.variable_name)?)))
+                    .collect();
+                let line = ExportLine {
+                    device_id: row.device_id,
+                    device_name: &row.device_name,
+                    measured_at: row.measured_at,
+                    created_at: row.created_at,
+                    measurements,
+                    stat: self.with_stat.then_some(&row.stat),
+                };
+                let mut line = serde_json::to_string(&line)?;
+                line.push('\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+fn csv_value(value: Option<serde_json::Value>) -> Cow<'static, str> {
+    match value {
+        None | Some(serde_json::Value::Null) => Cow::Borrowed(""),
+        Some(serde_json::Value::String(string)) => Cow::Owned(string),
+        Some(value) => Cow::Owned(value.to_string()),
+    }
+}
+
+fn csv_line<'a>(fields: impl Iterator<Item = Cow<'a, str>>) -> String {
+    let mut line = fields
+        .map(|field| {
+            if field.contains(&[',', '"', '\n', '\r'][..]) {
+                Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
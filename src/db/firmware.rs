@@ -1,8 +1,7 @@
-use crate::{Compilation, CompilationId, Device, Organization, Result, Transaction};
+use crate::{utils, Compilation, CompilationId, Device, Organization, Result, Transaction};
 use derive::id;
 use derive_get::Getters;
 use serde::{Deserialize, Serialize};
-use std::fmt::Write;
 
 #[derive(Getters, Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -31,6 +30,7 @@ pub struct Firmware {
     #[copy]
     compilation_id: Option<CompilationId>,
     binary_hash: String,
+    signature: Option<String>,
 }
 
 impl Firmware {
@@ -50,6 +50,7 @@ impl Firmware {
             id,
             compilation_id: None,
             binary_hash,
+            signature: None,
         })
     }
 
@@ -62,20 +63,17 @@ impl Firmware {
         let organization = compiler.organization(txn).await?;
 
         // TODO: move to SHA-256
-        let md5 = md5::compute(&bin);
-        let md5 = &*md5;
-        let mut binary_hash = String::with_capacity(md5.len() * 2);
-        for byte in md5 {
-            write!(binary_hash, "{:02x}", byte)?;
-        }
+        let binary_hash = utils::hex(&*md5::compute(&bin));
+        let signature = utils::sign(&bin).await?;
 
         let (id,): (FirmwareId,) = sqlx::query_as(
-            "INSERT INTO firmwares (compilation_id, organization_id, bin, binary_hash) VALUES ($1, $2, $3, $4) RETURNING id",
+            "INSERT INTO firmwares (compilation_id, organization_id, bin, binary_hash, signature) VALUES ($1, $2, $3, $4, $5) RETURNING id",
         )
         .bind(compilation.id())
         .bind(organization.id())
         .bind(&bin)
         .bind(&binary_hash)
+        .bind(&signature)
         .fetch_one(txn)
         .await?;
 
@@ -83,12 +81,13 @@ impl Firmware {
             id,
             compilation_id: Some(compilation.id()),
             binary_hash,
+            signature: Some(signature),
         })
     }
 
     pub async fn find_by_device(txn: &mut Transaction<'_>, device: &Device) -> Result<Self> {
         let firmware = sqlx::query_as(
-            "SELECT firmwares.id, compilation_id, binary_hash
+            "SELECT firmwares.id, compilation_id, binary_hash, signature
                  FROM firmwares
                  WHERE firmwares.id = $1",
         )
@@ -104,14 +103,14 @@ impl Firmware {
         hash: &str,
     ) -> Result<Option<Self>> {
         let firmware = sqlx::query_as(
-            "SELECT firmwares.id, compilation_id, binary_hash
+            "SELECT firmwares.id, compilation_id, binary_hash, signature
              FROM firmwares
              INNER JOIN devices ON devices.firmware_id = firmwares.id
              INNER JOIN collections ON collections.id = devices.collection_id
              INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = collections.id
              WHERE binary_hash = $1 AND cbt.organization_id = $2
              UNION
-             SELECT firmwares.id, compilation_id, binary_hash
+             SELECT firmwares.id, compilation_id, binary_hash, signature
              FROM firmwares
              INNER JOIN compilations ON compilations.id = firmwares.compilation_id
              INNER JOIN compilers ON compilers.id = compilations.compiler_id
@@ -130,7 +129,7 @@ impl Firmware {
         compilation: &Compilation,
     ) -> Result<Self> {
         let firmware = sqlx::query_as(
-            "SELECT id, compilation_id, binary_hash FROM firmwares WHERE compilation_id = $1 ORDER BY created_at DESC",
+            "SELECT id, compilation_id, binary_hash, signature FROM firmwares WHERE compilation_id = $1 ORDER BY created_at DESC",
         )
         .bind(compilation.id())
         .fetch_one(txn)
@@ -146,6 +145,22 @@ impl Firmware {
         Ok(bin.map(|(bin,)| bin))
     }
 
+    /// Signs firmwares created before signing was introduced
+    pub async fn sign(&mut self, txn: &mut Transaction<'_>, bin: &[u8]) -> Result<String> {
+        if let Some(signature) = &self.signature {
+            return Ok(signature.clone());
+        }
+
+        let signature = utils::sign(bin).await?;
+        sqlx::query("UPDATE firmwares SET signature = $1 WHERE id = $2")
+            .bind(&signature)
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        self.signature = Some(signature.clone());
+        Ok(signature)
+    }
+
     pub async fn compilation(&self, txn: &mut Transaction<'_>) -> Result<Option<Compilation>> {
         match self.compilation_id {
             Some(id) => Ok(Some(Compilation::find_by_id(txn, self, id).await?)),
@@ -0,0 +1,276 @@
+use crate::{Collection, DateTime, Device, DeviceId, FirmwareView, Result, Transaction};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Events sent before the device rebooted into the update still report the old firmware, so it's only failed after this
+pub const UPDATE_GRACE_SECONDS: i64 = 10 * 60;
+
+#[id]
+pub struct FirmwareUpdateId;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FirmwareUpdateStatus {
+    Downloaded,
+    Applied,
+    Failed,
+}
+
+/// Status of the device's update to the collection's latest firmware
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateStatus {
+    Pending,
+    Downloaded,
+    Applied,
+    Failed,
+}
+
+impl UpdateStatus {
+    /// Derives it from the last update to the latest firmware the device downloaded
+    pub fn new(
+        latest_hash: &str,
+        current_hash: &str,
+        update: Option<FirmwareUpdateStatus>,
+    ) -> Self {
+        if latest_hash == current_hash {
+            return Self::Applied;
+        }
+        match update {
+            Some(FirmwareUpdateStatus::Downloaded) => Self::Downloaded,
+            Some(FirmwareUpdateStatus::Failed) => Self::Failed,
+            // Device applied it at some point but is running something else now
+            Some(FirmwareUpdateStatus::Applied) | None => Self::Pending,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareUpdate {
+    #[copy]
+    id: FirmwareUpdateId,
+    from_hash: String,
+    to_hash: String,
+    /// Size of the binary offered, the transfer may have been interrupted
+    #[copy]
+    binary_size: i64,
+    #[copy]
+    status: FirmwareUpdateStatus,
+    #[copy]
+    created_at: DateTime,
+    #[copy]
+    updated_at: DateTime,
+}
+
+impl FirmwareUpdate {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        from_hash: String,
+        to_hash: String,
+        binary_size: usize,
+    ) -> Result<Self> {
+        let binary_size = binary_size as i64;
+        let (id, now): (FirmwareUpdateId, DateTime) = sqlx::query_as(
+            "INSERT INTO firmware_updates (device_id, from_hash, to_hash, binary_size) VALUES ($1, $2, $3, $4) RETURNING id, created_at",
+        )
+        .bind(device.id())
+        .bind(&from_hash)
+        .bind(&to_hash)
+        .bind(binary_size)
+        .fetch_one(txn)
+        .await?;
+        Ok(Self {
+            id,
+            from_hash,
+            to_hash,
+            binary_size,
+            status: FirmwareUpdateStatus::Downloaded,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    /// Resolves downloaded updates based on the firmware version the device reported after them
+    ///
+    /// Reporting another version only fails them after `UPDATE_GRACE_SECONDS`, events may have been in flight when it downloaded
+    pub async fn correlate(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        version: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE firmware_updates
+             SET status = (CASE WHEN to_hash = $2 THEN 'Applied' ELSE 'Failed' END)::FirmwareUpdateStatus, updated_at = NOW()
+             WHERE device_id = $1
+                   AND status = 'Downloaded'
+                   AND (to_hash = $2 OR created_at <= NOW() - make_interval(secs => $3))",
+        )
+        .bind(device.id())
+        .bind(version)
+        .bind(UPDATE_GRACE_SECONDS as f64)
+        .execute(txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_by_device(txn: &mut Transaction<'_>, device: &Device) -> Result<Vec<Self>> {
+        let updates = sqlx::query_as(
+            "SELECT id, from_hash, to_hash, binary_size, status, created_at, updated_at
+             FROM firmware_updates
+             WHERE device_id = $1
+             ORDER BY created_at DESC",
+        )
+        .bind(device.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(updates)
+    }
+
+    pub async fn latest_to(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        to_hash: &str,
+    ) -> Result<Option<Self>> {
+        let update = sqlx::query_as(
+            "SELECT id, from_hash, to_hash, binary_size, status, created_at, updated_at
+             FROM firmware_updates
+             WHERE device_id = $1 AND to_hash = $2
+             ORDER BY created_at DESC
+             LIMIT 1",
+        )
+        .bind(device.id())
+        .bind(to_hash)
+        .fetch_optional(txn)
+        .await?;
+        Ok(update)
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceUpdateView {
+    #[copy]
+    device_id: DeviceId,
+    current_hash: String,
+    latest: Option<FirmwareView>,
+    #[copy]
+    status: Option<UpdateStatus>,
+    history: Vec<FirmwareUpdate>,
+}
+
+impl DeviceUpdateView {
+    pub async fn new(txn: &mut Transaction<'_>, device: &Device) -> Result<Self> {
+        let collection = device.collection(txn).await?;
+        let (current_hash, latest, status) = device_status(txn, &collection, device).await?;
+        Ok(Self {
+            device_id: device.id(),
+            current_hash,
+            latest,
+            status,
+            history: FirmwareUpdate::list_by_device(txn, device).await?,
+        })
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceRolloutView {
+    #[copy]
+    device_id: DeviceId,
+    name: String,
+    current_hash: String,
+    #[copy]
+    status: UpdateStatus,
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutView {
+    latest: Option<FirmwareView>,
+    #[copy]
+    pending: usize,
+    #[copy]
+    downloaded: usize,
+    #[copy]
+    applied: usize,
+    #[copy]
+    failed: usize,
+    devices: Vec<DeviceRolloutView>,
+}
+
+impl RolloutView {
+    pub async fn new(txn: &mut Transaction<'_>, collection: &Collection) -> Result<Self> {
+        let latest = collection.update(txn).await?.map(FirmwareView::new);
+
+        let mut view = Self {
+            latest,
+            pending: 0,
+            downloaded: 0,
+            applied: 0,
+            failed: 0,
+            devices: Vec::new(),
+        };
+        let latest_hash = match &view.latest {
+            Some(latest) => latest.hash().to_owned(),
+            None => return Ok(view),
+        };
+        let rows: Vec<(DeviceId, String, String, Option<FirmwareUpdateStatus>)> = sqlx::query_as(
+            "SELECT dev.id, dev.name, fw.binary_hash, upd.status
+             FROM devices as dev
+             INNER JOIN collection_belongs_to_organization as cbt ON cbt.collection_id = dev.collection_id
+             INNER JOIN firmwares as fw ON fw.id = dev.firmware_id
+             LEFT JOIN LATERAL (
+                 SELECT status FROM firmware_updates
+                 WHERE device_id = dev.id AND to_hash = $2
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT 1
+             ) as upd ON true
+             WHERE dev.collection_id = $1
+             ORDER BY dev.id",
+        )
+        .bind(collection.id())
+        .bind(&latest_hash)
+        .fetch_all(&mut *txn)
+        .await?;
+
+        for (device_id, name, current_hash, update) in rows {
+            let status = UpdateStatus::new(&latest_hash, &current_hash, update);
+            match status {
+                UpdateStatus::Pending => view.pending += 1,
+                UpdateStatus::Downloaded => view.downloaded += 1,
+                UpdateStatus::Applied => view.applied += 1,
+                UpdateStatus::Failed => view.failed += 1,
+            }
+            view.devices.push(DeviceRolloutView {
+                device_id,
+                name,
+                current_hash,
+                status,
+            });
+        }
+        Ok(view)
+    }
+}
+
+async fn device_status(
+    txn: &mut Transaction<'_>,
+    collection: &Collection,
+    device: &Device,
+) -> Result<(String, Option<FirmwareView>, Option<UpdateStatus>)> {
+    let current_hash = device.current_firmware(txn).await?.binary_hash().to_owned();
+    let latest = match collection.update(txn).await? {
+        Some(latest) => latest,
+        None => return Ok((current_hash, None, None)),
+    };
+
+    let update = if latest.binary_hash() == &current_hash {
+        None
+    } else {
+        FirmwareUpdate::latest_to(txn, device, latest.binary_hash())
+            .await?
+            .map(|update| update.status())
+    };
+    let status = UpdateStatus::new(latest.binary_hash(), &current_hash, update);
+    Ok((current_hash, Some(FirmwareView::new(latest)), Some(status)))
+}
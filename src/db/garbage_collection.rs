@@ -0,0 +1,92 @@
+use crate::{logger::*, CompilationId, FirmwareId, Organization, Result, Transaction};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Amount of compilations kept per compiler, the latest one is what collections update to
+pub const KEEP_LATEST_COMPILATIONS: i64 = 3;
+
+/// Superseded compilations (and their firmwares) that no device is running
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GarbageCollection {
+    compilations: Vec<CompilationId>,
+    firmwares: Vec<FirmwareId>,
+    #[copy]
+    bytes: i64,
+}
+
+impl GarbageCollection {
+    pub async fn find(
+        txn: &mut Transaction<'_>,
+        organization: Option<&Organization>,
+        keep: i64,
+    ) -> Result<Self> {
+        // Never delete the compilation collections are pinned to
+        let keep = keep.max(1);
+
+        let compilations: Vec<(CompilationId,)> = sqlx::query_as(
+            "SELECT ranked.id
+             FROM (
+                 SELECT id, compiler_id, ROW_NUMBER() OVER (PARTITION BY compiler_id ORDER BY created_at DESC, id DESC) AS position
+                 FROM compilations
+             ) as ranked
+             INNER JOIN compilers ON compilers.id = ranked.compiler_id
+             WHERE ranked.position > $1
+                   AND ($2::BIGINT IS NULL OR compilers.organization_id = $2)
+                   AND NOT EXISTS (
+                       SELECT 1
+                       FROM firmwares
+                       INNER JOIN devices ON devices.firmware_id = firmwares.id
+                       WHERE firmwares.compilation_id = ranked.id
+                   )
+             ORDER BY ranked.id ASC",
+        )
+        .bind(keep)
+        .bind(organization.map(|o| o.id()))
+        .fetch_all(&mut *txn)
+        .await?;
+        let compilations: Vec<CompilationId> = compilations.into_iter().map(|(id,)| id).collect();
+
+        let firmwares: Vec<(FirmwareId, Option<i32>)> = sqlx::query_as(
+            "SELECT id, OCTET_LENGTH(bin) FROM firmwares WHERE compilation_id = ANY($1) ORDER BY id ASC",
+        )
+        .bind(&compilations)
+        .fetch_all(&mut *txn)
+        .await?;
+
+        Ok(Self {
+            compilations,
+            bytes: firmwares
+                .iter()
+                .map(|(_, size)| size.map_or(0, i64::from))
+                .sum(),
+            firmwares: firmwares.into_iter().map(|(id, _)| id).collect(),
+        })
+    }
+
+    pub async fn execute(self, txn: &mut Transaction<'_>) -> Result<()> {
+        if self.compilations.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Deleting {} compilations and {} firmwares ({} bytes)",
+            self.compilations.len(),
+            self.firmwares.len(),
+            self.bytes
+        );
+        sqlx::query("DELETE FROM dependency_belongs_to_compilation WHERE compilation_id = ANY($1)")
+            .bind(&self.compilations)
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM firmwares WHERE id = ANY($1)")
+            .bind(&self.firmwares)
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM compilations WHERE id = ANY($1)")
+            .bind(&self.compilations)
+            .execute(&mut *txn)
+            .await?;
+        Ok(())
+    }
+}
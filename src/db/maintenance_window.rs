@@ -0,0 +1,175 @@
+use crate::{Collection, DateTime, Error, Result, Transaction};
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+#[derive(Getters, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewMaintenanceWindow {
+    #[copy]
+    weekday: Option<u8>, // 0 is monday, none means every day
+    #[copy]
+    starts_at: NaiveTime,
+    #[copy]
+    ends_at: NaiveTime,
+}
+
+#[id]
+pub struct MaintenanceWindowId;
+
+/// Period, in the collection's timezone, where devices are allowed to update
+///
+/// If `ends_at` is not after `starts_at` the window wraps around to the next day
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceWindow {
+    #[copy]
+    id: MaintenanceWindowId,
+    #[copy]
+    weekday: Option<i16>,
+    #[copy]
+    starts_at: NaiveTime,
+    #[copy]
+    ends_at: NaiveTime,
+    #[copy]
+    created_at: DateTime,
+}
+
+impl MaintenanceWindow {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+        new_window: NewMaintenanceWindow,
+    ) -> Result<Self> {
+        if let Some(weekday) = new_window.weekday {
+            if weekday > 6 {
+                return Err(Error::InvalidWeekday(weekday));
+            }
+        }
+        let weekday = new_window.weekday.map(i16::from);
+
+        let (id, now): (MaintenanceWindowId, DateTime) = sqlx::query_as(
+            "INSERT INTO maintenance_windows (collection_id, weekday, starts_at, ends_at) VALUES ($1, $2, $3, $4) RETURNING id, created_at",
+        )
+        .bind(collection.id())
+        .bind(weekday)
+        .bind(new_window.starts_at)
+        .bind(new_window.ends_at)
+        .fetch_one(txn)
+        .await?;
+        Ok(Self {
+            id,
+            weekday,
+            starts_at: new_window.starts_at,
+            ends_at: new_window.ends_at,
+            created_at: now,
+        })
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+        id: MaintenanceWindowId,
+    ) -> Result<Self> {
+        let window = sqlx::query_as(
+            "SELECT id, weekday, starts_at, ends_at, created_at
+             FROM maintenance_windows
+             WHERE collection_id = $1 AND id = $2",
+        )
+        .bind(collection.id())
+        .bind(id)
+        .fetch_one(txn)
+        .await?;
+        Ok(window)
+    }
+
+    pub async fn from_collection(
+        txn: &mut Transaction<'_>,
+        collection: &Collection,
+    ) -> Result<Vec<Self>> {
+        let windows = sqlx::query_as(
+            "SELECT id, weekday, starts_at, ends_at, created_at
+             FROM maintenance_windows
+             WHERE collection_id = $1
+             ORDER BY weekday ASC NULLS FIRST, starts_at ASC",
+        )
+        .bind(collection.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(windows)
+    }
+
+    pub fn contains(&self, moment: NaiveDateTime) -> bool {
+        let time = moment.time();
+        let today = moment.weekday().num_days_from_monday() as i16;
+        let is_day = |weekday: i16| self.weekday.is_none() || self.weekday == Some(weekday);
+
+        if self.starts_at < self.ends_at {
+            is_day(today) && self.starts_at <= time && time < self.ends_at
+        } else {
+            let yesterday = (today + 6) % 7;
+            (is_day(today) && time >= self.starts_at) || (is_day(yesterday) && time < self.ends_at)
+        }
+    }
+
+    pub async fn delete(self, txn: &mut Transaction<'_>) -> Result<()> {
+        sqlx::query("DELETE FROM maintenance_windows WHERE id = $1")
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn window(weekday: Option<i16>, starts_at: &str, ends_at: &str) -> MaintenanceWindow {
+        MaintenanceWindow {
+            id: MaintenanceWindowId::from(1),
+            weekday,
+            starts_at: starts_at.parse().unwrap(),
+            ends_at: ends_at.parse().unwrap(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    // 2024-01-01 is a monday
+    fn moment(day: u32, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(time.parse().unwrap())
+    }
+
+    #[test]
+    fn contains_same_day() {
+        let every_day = window(None, "02:00:00", "04:00:00");
+        assert!(every_day.contains(moment(3, "02:00:00")));
+        assert!(every_day.contains(moment(3, "03:59:59")));
+        assert!(!every_day.contains(moment(3, "04:00:00")));
+        assert!(!every_day.contains(moment(3, "01:59:59")));
+
+        let tuesday = window(Some(1), "02:00:00", "04:00:00");
+        assert!(tuesday.contains(moment(2, "03:00:00")));
+        assert!(!tuesday.contains(moment(3, "03:00:00")));
+    }
+
+    #[test]
+    fn contains_wrapping_past_midnight() {
+        let every_day = window(None, "22:00:00", "02:00:00");
+        assert!(every_day.contains(moment(3, "23:00:00")));
+        assert!(every_day.contains(moment(3, "01:00:00")));
+        assert!(!every_day.contains(moment(3, "02:00:00")));
+        assert!(!every_day.contains(moment(3, "12:00:00")));
+
+        // Starts on sunday night, ends on monday morning
+        let sunday = window(Some(6), "22:00:00", "02:00:00");
+        assert!(sunday.contains(moment(7, "23:00:00")));
+        assert!(sunday.contains(moment(8, "01:00:00")));
+        assert!(!sunday.contains(moment(8, "23:00:00")));
+        assert!(!sunday.contains(moment(7, "01:00:00")));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    DateTime, Device, Event, EventId, MeasurementRollup, Result, SensorCalibrationId, SensorId,
+    SensorMeasurementKind, SensorMeasurementType, Transaction,
+};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewMeasurement {
+    #[copy]
+    pub sensor_id: SensorId,
+    pub variable_name: String,
+    pub kind: SensorMeasurementKind,
+    pub ty: SensorMeasurementType,
+    #[copy]
+    pub value: f64,
+    /// Set if the value was derived from a raw measurement
+    #[copy]
+    pub calibration_id: Option<SensorCalibrationId>,
+}
+
+#[id]
+pub struct MeasurementId;
+
+/// Single reading of a sensor, extracted from the event's measurements
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Measurement {
+    #[copy]
+    id: MeasurementId,
+    #[copy]
+    event_id: EventId,
+    #[copy]
+    sensor_id: SensorId,
+    variable_name: String,
+    kind: SensorMeasurementKind,
+    ty: SensorMeasurementType,
+    #[copy]
+    value: f64,
+    #[copy]
+    calibration_id: Option<SensorCalibrationId>,
+    #[copy]
+    created_at: DateTime,
+}
+
+impl Measurement {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        event: &Event,
+        new_measurement: NewMeasurement,
+    ) -> Result<Self> {
+        let (id,): (MeasurementId,) = sqlx::query_as(
+            "INSERT INTO measurements (event_id, device_id, sensor_id, variable_name, kind, ty, value, calibration_id, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+        )
+        .bind(event.id())
+        .bind(device.id())
+        .bind(new_measurement.sensor_id)
+        .bind(&new_measurement.variable_name)
+        .bind(&new_measurement.kind)
+        .bind(&new_measurement.ty)
+        .bind(new_measurement.value)
+        .bind(new_measurement.calibration_id)
+        .bind(event.measured_or_created_at())
+        .fetch_one(&mut *txn)
+        .await?;
+        MeasurementRollup::mark_dirty(
+            txn,
+            &[device.id()],
+            &[new_measurement.sensor_id],
+            std::slice::from_ref(&new_measurement.variable_name),
+            &[event.measured_or_created_at()],
+        )
+        .await?;
+        Ok(Self {
+            id,
+            event_id: event.id(),
+            sensor_id: new_measurement.sensor_id,
+            variable_name: new_measurement.variable_name,
+            kind: new_measurement.kind,
+            ty: new_measurement.ty,
+            value: new_measurement.value,
+            calibration_id: new_measurement.calibration_id,
+            created_at: event.measured_or_created_at(),
+        })
+    }
+
+    pub async fn list_for_sensor(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        sensor_id: SensorId,
+        since: DateTime,
+        until: DateTime,
+    ) -> Result<Vec<Self>> {
+        let measurements = sqlx::query_as(
+            "SELECT id, event_id, sensor_id, variable_name, kind, ty, value, calibration_id, created_at
+             FROM measurements
+             WHERE device_id = $1 AND sensor_id = $2 AND created_at >= $3 AND created_at < $4
+             ORDER BY created_at ASC",
+        )
+        .bind(device.id())
+        .bind(sensor_id)
+        .bind(since)
+        .bind(until)
+        .fetch_all(txn)
+        .await?;
+        Ok(measurements)
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{logger::*, DateTime, Device, DeviceId, Error, Result, SensorId, Transaction};
+use chrono::Timelike;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Maximum amount of buckets per measurement in a single request
+pub const MAX_BUCKETS: i64 = 10000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bucket {
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl Bucket {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Self::FiveMinutes => 5 * 60,
+            Self::Hour => 3600,
+            Self::Day => 24 * 3600,
+        }
+    }
+
+    pub fn resolution(&self) -> Option<RollupResolution> {
+        match self {
+            Self::FiveMinutes => None,
+            Self::Hour => Some(RollupResolution::Hour),
+            Self::Day => Some(RollupResolution::Day),
+        }
+    }
+
+    /// Start of the bucket that contains the moment
+    pub fn floor(&self, moment: DateTime) -> DateTime {
+        let offset = moment.timestamp().rem_euclid(self.seconds());
+        let start = moment - chrono::Duration::seconds(offset);
+        start.with_nanosecond(0).unwrap_or(start)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Avg,
+    Last,
+    Count,
+}
+
+impl Aggregation {
+    /// Parses comma separated aggregations, like `min,max,avg`
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| match name.to_lowercase().as_str() {
+                "min" => Ok(Self::Min),
+                "max" => Ok(Self::Max),
+                "avg" => Ok(Self::Avg),
+                "last" => Ok(Self::Last),
+                "count" => Ok(Self::Count),
+                _ => Err(Error::InvalidAggregation(name.to_owned())),
+            })
+            .collect()
+    }
+
+    /// Clears the aggregated values that weren't requested, the query always computes all of them
+    pub(crate) fn mask(
+        requested: &[Self],
+        min: &mut Option<f64>,
+        max: &mut Option<f64>,
+        avg: &mut Option<f64>,
+        last: &mut Option<f64>,
+        count: &mut Option<i64>,
+    ) {
+        if !requested.contains(&Self::Min) {
+            *min = None;
+        }
+        if !requested.contains(&Self::Max) {
+            *max = None;
+        }
+        if !requested.contains(&Self::Avg) {
+            *avg = None;
+        }
+        if !requested.contains(&Self::Last) {
+            *last = None;
+        }
+        if !requested.contains(&Self::Count) {
+            *count = None;
+        }
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollupResolution {
+    Hour,
+    Day,
+}
+
+impl RollupResolution {
+    pub fn bucket(&self) -> Bucket {
+        match self {
+            Self::Hour => Bucket::Hour,
+            Self::Day => Bucket::Day,
+        }
+    }
+}
+
+/// Aggregated values of a measurement inside a bucket, only the requested aggregations are filled
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MeasurementAggregate {
+    #[copy]
+    sensor_id: SensorId,
+    variable_name: String,
+    #[copy]
+    bucket: DateTime,
+    #[copy]
+    min: Option<f64>,
+    #[copy]
+    max: Option<f64>,
+    #[copy]
+    avg: Option<f64>,
+    #[copy]
+    last: Option<f64>,
+    #[copy]
+    count: Option<i64>,
+}
+
+impl MeasurementAggregate {
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        since: DateTime,
+        until: DateTime,
+        bucket: Bucket,
+        aggregations: &[Aggregation],
+    ) -> Result<Vec<Self>> {
+        let since = bucket.floor(since);
+        if (until - since).num_seconds() / bucket.seconds() > MAX_BUCKETS {
+            return Err(Error::AskedForTooMany);
+        }
+
+        // Hourly and daily buckets read what was already rolled up and only aggregate the buckets that changed since
+        let (rollups, dirty) = match bucket.resolution() {
+            Some(_) => (
+                "SELECT sensor_id, variable_name, bucket_start AS bucket, min, max, sum, count, last_value, last_at
+                 FROM measurement_rollups r
+                 WHERE device_id = $1 AND resolution = $5 AND bucket_start >= $2 AND bucket_start < $3
+                       AND NOT EXISTS (
+                           SELECT 1 FROM measurement_rollup_dirty_buckets d
+                           WHERE d.device_id = r.device_id AND d.sensor_id = r.sensor_id AND d.variable_name = r.variable_name
+                                 AND d.resolution = r.resolution AND d.bucket_start = r.bucket_start
+                       )
+                 UNION ALL",
+                "AND EXISTS (
+                     SELECT 1 FROM measurement_rollup_dirty_buckets d
+                     WHERE d.device_id = m.device_id AND d.sensor_id = m.sensor_id AND d.variable_name = m.variable_name
+                           AND d.resolution = $5 AND d.bucket_start = to_timestamp(floor(extract(epoch FROM m.created_at)::DOUBLE PRECISION / $4) * $4)
+                 )",
+            ),
+            None => ("", ""),
+        };
+        let query = format!(
+            "WITH parts AS (
+                 {rollups}
+                 SELECT sensor_id, variable_name, to_timestamp(floor(extract(epoch FROM created_at)::DOUBLE PRECISION / $4) * $4) AS bucket,
+                        MIN(value) AS min, MAX(value) AS max, SUM(value) AS sum, COUNT(value) AS count,
+                        (ARRAY_AGG(value ORDER BY created_at DESC) FILTER (WHERE value IS NOT NULL))[1] AS last_value,
+                        MAX(created_at) AS last_at
+                 FROM measurements m
+                 WHERE device_id = $1 AND created_at >= $2 AND created_at < $3 {dirty}
+                 GROUP BY sensor_id, variable_name, bucket
+             )
+             SELECT sensor_id, variable_name, bucket,
+                    MIN(min) AS min, MAX(max) AS max, SUM(sum) / NULLIF(SUM(count), 0) AS avg,
+                    (ARRAY_AGG(last_value ORDER BY last_at DESC) FILTER (WHERE last_value IS NOT NULL))[1] AS last,
+                    SUM(count)::BIGINT AS count
+             FROM parts
+             GROUP BY sensor_id, variable_name, bucket
+             ORDER BY bucket ASC, sensor_id ASC, variable_name ASC"
+        );
+        let mut aggregates: Vec<Self> = sqlx::query_as(&query)
+            .bind(device.id())
+            .bind(since)
+            .bind(until)
+            .bind(bucket.seconds() as f64)
+            .bind(bucket.resolution())
+            .fetch_all(txn)
+            .await?;
+
+        for aggregate in &mut aggregates {
+            Aggregation::mask(
+                aggregations,
+                &mut aggregate.min,
+                &mut aggregate.max,
+                &mut aggregate.avg,
+                &mut aggregate.last,
+                &mut aggregate.count,
+            );
+        }
+        Ok(aggregates)
+    }
+}
+
+pub struct MeasurementRollup;
+
+impl MeasurementRollup {
+    /// Marks the buckets of a new measurement to be rolled up, in the transaction that stores it
+    pub async fn mark_dirty(
+        txn: &mut Transaction<'_>,
+        device_ids: &[DeviceId],
+        sensor_ids: &[SensorId],
+        variable_names: &[String],
+        created_ats: &[DateTime],
+    ) -> Result<()> {
+        for resolution in [RollupResolution::Hour, RollupResolution::Day] {
+            sqlx::query(
+                "INSERT INTO measurement_rollup_dirty_buckets (device_id, sensor_id, variable_name, resolution, bucket_start)
+                 SELECT DISTINCT device_id, sensor_id, variable_name, $5, to_timestamp(floor(extract(epoch FROM created_at)::DOUBLE PRECISION / $6) * $6)
+                 FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::TEXT[], $4::TIMESTAMPTZ[]) AS m (device_id, sensor_id, variable_name, created_at)",
+            )
+            .bind(device_ids)
+            .bind(sensor_ids)
+            .bind(variable_names)
+            .bind(created_ats)
+            .bind(resolution)
+            .bind(resolution.bucket().seconds() as f64)
+            .execute(&mut *txn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Recomputes every bucket that received measurements since the last update
+    ///
+    /// Marks committed while it runs aren't visible to it, so their buckets are picked up by the next update
+    pub async fn update(txn: &mut Transaction<'_>, resolution: RollupResolution) -> Result<()> {
+        let (buckets,): (i64,) = sqlx::query_as(
+            "WITH dirty AS (
+                 DELETE FROM measurement_rollup_dirty_buckets WHERE resolution = $1
+                 RETURNING device_id, sensor_id, variable_name, bucket_start
+             ), buckets AS (
+                 SELECT DISTINCT device_id, sensor_id, variable_name, bucket_start FROM dirty
+             ), rolled_up AS (
+                 INSERT INTO measurement_rollups (device_id, sensor_id, variable_name, resolution, bucket_start, min, max, sum, count, last_value, last_at)
+                 SELECT m.device_id, m.sensor_id, m.variable_name, $1, buckets.bucket_start,
+                        MIN(m.value), MAX(m.value), SUM(m.value), COUNT(m.value),
+                        (ARRAY_AGG(m.value ORDER BY m.created_at DESC) FILTER (WHERE m.value IS NOT NULL))[1],
+                        MAX(m.created_at)
+                 FROM measurements m
+                 INNER JOIN buckets ON buckets.device_id = m.device_id
+                                    AND buckets.sensor_id = m.sensor_id
+                                    AND buckets.variable_name = m.variable_name
+                                    AND m.created_at >= buckets.bucket_start
+                                    AND m.created_at < buckets.bucket_start + make_interval(secs => $2)
+                 GROUP BY m.device_id, m.sensor_id, m.variable_name, buckets.bucket_start
+                 ON CONFLICT (device_id, sensor_id, variable_name, resolution, bucket_start) DO UPDATE
+                 SET min = EXCLUDED.min, max = EXCLUDED.max, sum = EXCLUDED.sum, count = EXCLUDED.count,
+                     last_value = EXCLUDED.last_value, last_at = EXCLUDED.last_at
+             )
+             SELECT COUNT(*) FROM buckets",
+        )
+        .bind(resolution)
+        .bind(resolution.bucket().seconds() as f64)
+        .fetch_one(txn)
+        .await?;
+        if buckets > 0 {
+            info!("Rolled up {buckets} {resolution:?} buckets");
+        }
+        Ok(())
+    }
+}
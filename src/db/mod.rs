@@ -1,22 +1,39 @@
+pub mod activity;
+pub mod alert;
 pub mod auth;
 pub mod builtin;
 pub mod collection;
 pub mod compilation;
 pub mod compiler;
 pub mod device;
+pub mod device_command;
 pub mod device_config;
 pub mod device_config_request;
 pub mod device_config_type;
+pub mod device_connectivity;
+pub mod device_health;
 pub mod device_log;
 pub mod device_panic;
 pub mod event;
+pub mod export;
 pub mod firmware;
+pub mod firmware_update;
+pub mod garbage_collection;
+pub mod maintenance_window;
+pub mod measurement;
+pub mod measurement_aggregate;
+pub mod notification;
 pub mod organization;
+pub mod page;
+pub mod panic_group;
+pub mod retention_policy;
 pub mod secret;
 pub mod sensor;
+pub mod sensor_calibration;
 pub mod sensor_config;
 pub mod sensor_config_request;
 pub mod sensor_config_type;
+pub mod sensor_fault;
 pub mod sensor_measurement;
 pub mod sensor_prototype;
 pub mod target;
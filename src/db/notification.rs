@@ -0,0 +1,722 @@
+use crate::{
+    logger::*, utils, Alert, AlertId, AlertKind, AlertRule, Cursor, DateTime, Device,
+    DeviceConnectivity, DeviceId, Error, Organization, OrganizationId, Page, Pool, Result,
+    SensorId, Transaction,
+};
+use derive::id;
+use derive_get::Getters;
+use handlebars::Handlebars;
+use hmac::{Hmac, Mac};
+use lettre::{message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Used when the channel has no template of its own
+pub const DEFAULT_NOTIFICATION_TEMPLATE: &str = "{{ruleName}} is {{status}} on {{deviceName}} ({{collectionName}}): {{#if sensorAlias}}{{sensorAlias}} {{/if}}{{variableName}} is {{value}}, expected {{comparison}} {{threshold}} to fire";
+const NOTIFICATION_SUBJECT: &str = "[{{status}}] {{ruleName}} on {{deviceName}}";
+const MAX_NOTIFICATIONS_PER_HOUR: i32 = 1000;
+/// Deliveries are given up after this many attempts
+const MAX_NOTIFICATION_ATTEMPTS: i32 = 8;
+/// First retry delay, doubled on every failure up to an hour
+const NOTIFICATION_RETRY_SECONDS: i64 = 30;
+const NOTIFICATION_BATCH: i64 = 50;
+/// Claimed notifications are retried after this if the instance sending them dies
+const NOTIFICATION_CLAIM_SECONDS: i64 = 5 * 60;
+const WEBHOOK_TIMEOUT_SECONDS: u64 = 10;
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationChannelKind {
+    /// Signed JSON POST, see `Notification::send_webhook`
+    Webhook,
+    /// Sent through the SMTP server in `SMTP_URL`, from `SMTP_FROM`
+    Email,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationStatus {
+    Pending,
+    Sent,
+    /// Every attempt failed
+    Failed,
+    /// The channel already got too many notifications in the last hour, so this one wasn't sent
+    RateLimited,
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewNotificationChannel {
+    pub name: String,
+    #[copy]
+    pub kind: NotificationChannelKind,
+    /// Webhook URL or email address
+    pub target: String,
+    /// Handlebars template of the message, `DEFAULT_NOTIFICATION_TEMPLATE` if missing
+    pub template: Option<String>,
+    #[copy]
+    pub max_per_hour: i32,
+}
+
+impl NewNotificationChannel {
+    fn validate(&self) -> Result<()> {
+        let invalid = |reason: String| Err(Error::InvalidNotificationChannel(reason));
+        if self.name.trim().is_empty() {
+            return invalid("name is required".to_owned());
+        }
+        if !(1..=MAX_NOTIFICATIONS_PER_HOUR).contains(&self.max_per_hour) {
+            return invalid(format!(
+                "max per hour must be between 1 and {}",
+                MAX_NOTIFICATIONS_PER_HOUR
+            ));
+        }
+        match self.kind {
+            NotificationChannelKind::Webhook => match reqwest::Url::parse(&self.target) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                    let literal = url
+                        .host_str()
+                        .and_then(|host| host.trim_matches(['[', ']']).parse::<IpAddr>().ok());
+                    if literal.is_some_and(|ip| !is_public(ip)) && !is_allowed_host(&url) {
+                        return invalid(format!("{} is not a public address", self.target));
+                    }
+                }
+                _ => return invalid(format!("{} is not an http url", self.target)),
+            },
+            NotificationChannelKind::Email => {
+                if self.target.parse::<Mailbox>().is_err() {
+                    return invalid(format!("{} is not an email address", self.target));
+                }
+            }
+        }
+        if let Some(template) = &self.template {
+            if let Err(err) = NotificationContext::sample().render(template) {
+                return invalid(format!("invalid template: {err}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[id]
+pub struct NotificationChannelId;
+
+/// Where an organization's alerts are delivered
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationChannel {
+    #[copy]
+    id: NotificationChannelId,
+    #[copy]
+    organization_id: OrganizationId,
+    name: String,
+    #[copy]
+    kind: NotificationChannelKind,
+    target: String,
+    /// Webhook receivers verify the signature with it, only sent when the channel is created
+    #[serde(skip)]
+    secret: String,
+    template: Option<String>,
+    #[copy]
+    max_per_hour: i32,
+    /// Alerts aren't notified until then, already queued ones are still delivered
+    #[copy]
+    muted_until: Option<DateTime>,
+    #[copy]
+    created_at: DateTime,
+    #[copy]
+    updated_at: DateTime,
+}
+
+/// The channel with its secret, returned once when it's created
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedNotificationChannel {
+    #[serde(flatten)]
+    channel: NotificationChannel,
+    secret: String,
+}
+
+impl From<NotificationChannel> for CreatedNotificationChannel {
+    fn from(channel: NotificationChannel) -> Self {
+        Self {
+            secret: channel.secret.clone(),
+            channel,
+        }
+    }
+}
+
+impl NotificationChannel {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        new_channel: NewNotificationChannel,
+    ) -> Result<Self> {
+        new_channel.validate()?;
+
+        let channel = sqlx::query_as(
+            "INSERT INTO notification_channels (organization_id, name, kind, target, secret, template, max_per_hour)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, organization_id, name, kind, target, secret, template, max_per_hour, muted_until, created_at, updated_at",
+        )
+        .bind(organization.id())
+        .bind(new_channel.name.trim())
+        .bind(new_channel.kind)
+        .bind(new_channel.target.trim())
+        .bind(utils::random_string(64))
+        .bind(&new_channel.template)
+        .bind(new_channel.max_per_hour)
+        .fetch_one(txn)
+        .await?;
+        Ok(channel)
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        channel_id: NotificationChannelId,
+    ) -> Result<Self> {
+        let channel = sqlx::query_as(
+            "SELECT id, organization_id, name, kind, target, secret, template, max_per_hour, muted_until, created_at, updated_at
+             FROM notification_channels
+             WHERE id = $1 AND organization_id = $2",
+        )
+        .bind(channel_id)
+        .bind(organization.id())
+        .fetch_one(txn)
+        .await?;
+        Ok(channel)
+    }
+
+    async fn raw_find_by_id(
+        txn: &mut Transaction<'_>,
+        channel_id: NotificationChannelId,
+    ) -> Result<Self> {
+        let channel = sqlx::query_as(
+            "SELECT id, organization_id, name, kind, target, secret, template, max_per_hour, muted_until, created_at, updated_at
+             FROM notification_channels
+             WHERE id = $1",
+        )
+        .bind(channel_id)
+        .fetch_one(txn)
+        .await?;
+        Ok(channel)
+    }
+
+    pub async fn from_organization(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+    ) -> Result<Vec<Self>> {
+        let channels = sqlx::query_as(
+            "SELECT id, organization_id, name, kind, target, secret, template, max_per_hour, muted_until, created_at, updated_at
+             FROM notification_channels
+             WHERE organization_id = $1
+             ORDER BY id ASC",
+        )
+        .bind(organization.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(channels)
+    }
+
+    /// `None` unmutes the channel
+    pub async fn mute(
+        &mut self,
+        txn: &mut Transaction<'_>,
+        muted_until: Option<DateTime>,
+    ) -> Result<()> {
+        let (updated_at,): (DateTime,) = sqlx::query_as(
+            "UPDATE notification_channels SET muted_until = $1, updated_at = NOW() WHERE id = $2 RETURNING updated_at",
+        )
+        .bind(muted_until)
+        .bind(self.id)
+        .fetch_one(txn)
+        .await?;
+        self.muted_until = muted_until;
+        self.updated_at = updated_at;
+        Ok(())
+    }
+
+    /// Drops the channel with its notifications
+    pub async fn delete(self, txn: &mut Transaction<'_>) -> Result<()> {
+        sqlx::query("DELETE FROM notifications WHERE notification_channel_id = $1")
+            .bind(self.id)
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM notification_channels WHERE id = $1")
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+/// What templates can refer to, also sent in webhook payloads
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationContext {
+    alert_id: AlertId,
+    kind: AlertKind,
+    status: String,
+    rule_name: String,
+    variable_name: String,
+    comparison: String,
+    threshold: f64,
+    value: f64,
+    device_id: DeviceId,
+    device_name: String,
+    collection_name: String,
+    sensor_alias: Option<String>,
+    created_at: DateTime,
+}
+
+impl NotificationContext {
+    fn sample() -> Self {
+        Self {
+            alert_id: AlertId::from(0),
+            kind: AlertKind::Threshold,
+            status: "Firing".to_owned(),
+            rule_name: "Too dry".to_owned(),
+            variable_name: "soil_moisture".to_owned(),
+            comparison: "Below".to_owned(),
+            threshold: 30.,
+            value: 25.,
+            device_id: DeviceId::from(0),
+            device_name: "Device".to_owned(),
+            collection_name: "Collection".to_owned(),
+            sensor_alias: Some("Soil".to_owned()),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    fn render(&self, template: &str) -> Result<String> {
+        let mut reg = Handlebars::new();
+        // Messages are plain text, not HTML
+        reg.register_escape_fn(handlebars::no_escape);
+        Ok(reg.render_template(template, self)?)
+    }
+}
+
+#[id]
+pub struct NotificationId;
+
+/// Delivery of an alert to a channel
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    #[copy]
+    id: NotificationId,
+    #[copy]
+    notification_channel_id: NotificationChannelId,
+    #[copy]
+    status: NotificationStatus,
+    subject: String,
+    message: String,
+    context: sqlx::types::Json<NotificationContext>,
+    #[copy]
+    attempts: i32,
+    last_error: Option<String>,
+    #[copy]
+    next_attempt_at: DateTime,
+    #[copy]
+    sent_at: Option<DateTime>,
+    #[copy]
+    created_at: DateTime,
+}
+
+impl Notification {
+    /// Queues the alert to every unmuted channel of the organizations the device belongs to
+    ///
+    /// Offline alerts have no rule, they are described as the seconds without reports going above the limit
+    pub async fn enqueue(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        alert: &Alert,
+        rule: Option<&AlertRule>,
+        sensor_id: Option<SensorId>,
+    ) -> Result<()> {
+        let (collection_name, sensor_alias): (String, Option<String>) = sqlx::query_as(
+            "SELECT collections.name, bt.alias
+             FROM collections
+             LEFT JOIN sensor_belongs_to_compiler bt ON bt.compiler_id = collections.compiler_id AND bt.sensor_id = $2
+             WHERE collections.id = $1",
+        )
+        .bind(device.collection_id())
+        .bind(sensor_id)
+        .fetch_one(&mut *txn)
+        .await?;
+        let (rule_name, variable_name, comparison, threshold) = match rule {
+            Some(rule) => (
+                rule.name().clone(),
+                rule.variable_name().clone(),
+                format!("{:?}", rule.comparison()),
+                rule.threshold(),
+            ),
+            None => (
+                "Offline".to_owned(),
+                "secondsWithoutEvents".to_owned(),
+                "Above".to_owned(),
+                DeviceConnectivity::offline_after().num_seconds() as f64,
+            ),
+        };
+        let context = NotificationContext {
+            alert_id: alert.id(),
+            kind: alert.kind(),
+            status: format!("{:?}", alert.status()),
+            rule_name,
+            variable_name,
+            comparison,
+            threshold,
+            value: alert.value(),
+            device_id: device.id(),
+            device_name: device.name().clone(),
+            collection_name,
+            sensor_alias,
+            created_at: alert.created_at(),
+        };
+
+        let channels: Vec<NotificationChannel> = sqlx::query_as(
+            "SELECT ch.id, ch.organization_id, ch.name, ch.kind, ch.target, ch.secret, ch.template, ch.max_per_hour, ch.muted_until, ch.created_at, ch.updated_at
+             FROM notification_channels ch
+             INNER JOIN collection_belongs_to_organization cbt ON cbt.organization_id = ch.organization_id
+             WHERE cbt.collection_id = $1 AND (ch.muted_until IS NULL OR ch.muted_until < NOW())
+             ORDER BY ch.id ASC",
+        )
+        .bind(device.collection_id())
+        .fetch_all(&mut *txn)
+        .await?;
+
+        for channel in channels {
+            let (recent,): (i64,) = sqlx::query_as(
+                "SELECT COUNT(*)
+                 FROM notifications
+                 WHERE notification_channel_id = $1 AND status <> 'RateLimited' AND created_at > NOW() - INTERVAL '1 hour'",
+            )
+            .bind(channel.id)
+            .fetch_one(&mut *txn)
+            .await?;
+            let template = channel
+                .template
+                .as_deref()
+                .unwrap_or(DEFAULT_NOTIFICATION_TEMPLATE);
+            let status = if recent >= i64::from(channel.max_per_hour) {
+                NotificationStatus::RateLimited
+            } else {
+                NotificationStatus::Pending
+            };
+            sqlx::query(
+                "INSERT INTO notifications (notification_channel_id, alert_id, status, subject, message, context)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(channel.id)
+            .bind(alert.id())
+            .bind(status)
+            .bind(context.render(NOTIFICATION_SUBJECT)?)
+            .bind(context.render(template)?)
+            .bind(sqlx::types::Json(&context))
+            .execute(&mut *txn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let notifications: Vec<Self> = sqlx::query_as(
+            "SELECT n.id, n.notification_channel_id, n.status, n.subject, n.message, n.context, n.attempts, n.last_error, n.next_attempt_at, n.sent_at, n.created_at
+             FROM notifications n
+             INNER JOIN notification_channels ch ON ch.id = n.notification_channel_id
+             WHERE ch.organization_id = $1
+                   AND ($2::TIMESTAMPTZ IS NULL OR n.created_at < $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR (n.created_at, n.id) < ($3, $4))
+             ORDER BY n.created_at DESC, n.id DESC
+             LIMIT $5",
+        )
+        .bind(organization.id())
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
+        .fetch_all(txn)
+        .await?;
+        Ok(Page::new(notifications, limit, |n| {
+            Cursor::new(n.created_at, n.id)
+        }))
+    }
+
+    /// Sends the notifications that are due
+    ///
+    /// They are claimed in a short transaction, by pushing `next_attempt_at` forward, so server instances don't send them twice,
+    /// then every attempt is recorded in its own transaction
+    pub async fn deliver(pool: &'static Pool, mailer: &Mailer) -> Result<()> {
+        let mut txn = pool.begin().await?;
+        let due: Vec<Self> = sqlx::query_as(
+            "UPDATE notifications
+             SET next_attempt_at = NOW() + make_interval(secs => $2)
+             WHERE id IN (SELECT id
+                          FROM notifications
+                          WHERE status = 'Pending' AND next_attempt_at <= NOW()
+                          ORDER BY next_attempt_at ASC
+                          LIMIT $1
+                          FOR UPDATE SKIP LOCKED)
+             RETURNING id, notification_channel_id, status, subject, message, context, attempts, last_error, next_attempt_at, sent_at, created_at",
+        )
+        .bind(NOTIFICATION_BATCH)
+        .bind(NOTIFICATION_CLAIM_SECONDS as f64)
+        .fetch_all(&mut txn)
+        .await?;
+        let mut channels = Vec::with_capacity(due.len());
+        for notification in &due {
+            channels.push(
+                NotificationChannel::raw_find_by_id(&mut txn, notification.notification_channel_id)
+                    .await?,
+            );
+        }
+        txn.commit().await?;
+
+        for (notification, channel) in due.into_iter().zip(channels) {
+            let result = match channel.kind {
+                NotificationChannelKind::Webhook => notification.send_webhook(&channel).await,
+                NotificationChannelKind::Email => notification.send_email(&channel, mailer).await,
+            };
+            let mut txn = pool.begin().await?;
+            notification.record(&mut txn, result).await?;
+            txn.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// POSTs `{ subject, message, context }`, redirects aren't followed
+    ///
+    /// `x-iop-signature` is `sha256=` followed by the hex HMAC-SHA256, keyed by the channel's secret, of `x-iop-timestamp`, a dot and the body
+    async fn send_webhook(&self, channel: &NotificationChannel) -> std::result::Result<(), String> {
+        let body = serde_json::to_string(&serde_json::json!({
+            "subject": self.subject,
+            "message": self.message,
+            "context": self.context,
+        }))
+        .map_err(|err| err.to_string())?;
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signature = signature(&channel.secret, &timestamp, &body)?;
+
+        // The address is pinned so the host can't resolve to a private one after it's checked
+        let url = reqwest::Url::parse(&channel.target).map_err(|err| err.to_string())?;
+        let (host, addr) = resolve_webhook(&url).await?;
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .resolve(&host, addr)
+            .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECONDS))
+            .build()
+            .map_err(|err| err.to_string())?
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("x-iop-timestamp", timestamp)
+            .header("x-iop-signature", signature)
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    async fn send_email(
+        &self,
+        channel: &NotificationChannel,
+        mailer: &Mailer,
+    ) -> std::result::Result<(), String> {
+        let (transport, from) = mailer.0.as_ref().map_err(Clone::clone)?;
+        let to = channel
+            .target
+            .parse::<Mailbox>()
+            .map_err(|err| err.to_string())?;
+        let email = Message::builder()
+            .from(from.clone())
+            .to(to)
+            .subject(&self.subject)
+            .body(self.message.clone())
+            .map_err(|err| err.to_string())?;
+        transport.send(email).await.map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    async fn record(
+        &self,
+        txn: &mut Transaction<'_>,
+        result: std::result::Result<(), String>,
+    ) -> Result<()> {
+        let attempts = self.attempts + 1;
+        match result {
+            Ok(()) => {
+                sqlx::query(
+                    "UPDATE notifications SET status = 'Sent', attempts = $1, last_error = NULL, sent_at = NOW() WHERE id = $2",
+                )
+                .bind(attempts)
+                .bind(self.id)
+                .execute(txn)
+                .await?;
+            }
+            Err(err) => {
+                warn!(
+                    "Notification {:?} failed (attempt {attempts}): {err}",
+                    self.id
+                );
+                let status = if attempts >= MAX_NOTIFICATION_ATTEMPTS {
+                    NotificationStatus::Failed
+                } else {
+                    NotificationStatus::Pending
+                };
+                let delay = (NOTIFICATION_RETRY_SECONDS << (attempts - 1).min(7)).min(3600);
+                sqlx::query(
+                    "UPDATE notifications
+                     SET status = $1, attempts = $2, last_error = $3, next_attempt_at = NOW() + make_interval(secs => $4)
+                     WHERE id = $5",
+                )
+                .bind(status)
+                .bind(attempts)
+                .bind(err)
+                .bind(delay as f64)
+                .bind(self.id)
+                .execute(txn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SMTP transport shared by every delivery, so its connections are reused
+pub struct Mailer(std::result::Result<(AsyncSmtpTransport<Tokio1Executor>, Mailbox), String>);
+
+impl Mailer {
+    /// SMTP is optional, email channels fail until it's configured
+    pub fn from_env() -> Self {
+        match (std::env::var("SMTP_URL"), std::env::var("SMTP_FROM")) {
+            (Ok(url), Ok(from)) => Self::new(&url, &from),
+            (Err(_), _) => Self(Err("SMTP_URL is not set".to_owned())),
+            (_, Err(_)) => Self(Err("SMTP_FROM is not set".to_owned())),
+        }
+    }
+
+    /// `url` and `from` are parsed like `SMTP_URL` and `SMTP_FROM`
+    pub fn new(url: &str, from: &str) -> Self {
+        Self((|| {
+            let from = from
+                .parse::<Mailbox>()
+                .map_err(|err| format!("invalid SMTP_FROM: {err}"))?;
+            let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+                .map_err(|err| format!("invalid SMTP_URL: {err}"))?
+                .build();
+            Ok((transport, from))
+        })())
+    }
+}
+
+fn signature(secret: &str, timestamp: &str, body: &str) -> std::result::Result<String, String> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|err| err.to_string())?;
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    Ok(format!(
+        "sha256={}",
+        utils::hex(&mac.finalize().into_bytes())
+    ))
+}
+
+/// Webhooks can't target the server's own network, unless the host is in `WEBHOOK_ALLOWED_HOSTS` (comma separated)
+fn is_allowed_host(url: &reqwest::Url) -> bool {
+    match (url.host_str(), std::env::var("WEBHOOK_ALLOWED_HOSTS")) {
+        (Some(host), Ok(hosts)) => hosts.split(',').any(|allowed| allowed.trim() == host),
+        _ => false,
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolves the webhook's host, refusing private addresses unless the host is allowed
+async fn resolve_webhook(url: &reqwest::Url) -> std::result::Result<(String, SocketAddr), String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("{url} has no host"))?
+        .to_owned();
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| format!("{url} has no port"))?;
+    let allowed = is_allowed_host(url);
+    let addr = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|err| format!("unable to resolve {host}: {err}"))?
+        .find(|addr| allowed || is_public(addr.ip()))
+        .ok_or_else(|| format!("{host} doesn't resolve to a public address"))?;
+    Ok((host, addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature() {
+        assert_eq!(
+            signature("secret", "1700000000", r#"{"subject":"s"}"#).unwrap(),
+            "sha256=dc2c74c4b1ad24d2f6476ff02499595566bad18cf8e1eb7469c1101a2e523846"
+        );
+        assert_ne!(
+            signature("other", "1700000000", r#"{"subject":"s"}"#).unwrap(),
+            signature("secret", "1700000000", r#"{"subject":"s"}"#).unwrap()
+        );
+    }
+
+    #[test]
+    fn private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["1.1.1.1", "8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{DateTime, Error, Result};
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Maximum amount of items per page
+pub const MAX_PAGE_SIZE: u32 = 10000;
+pub const DEFAULT_PAGE_SIZE: u32 = 100;
+
+/// Position after the last item of a page, items are ordered by `(created_at, id)` descending
+///
+/// Events are ordered by when they were measured, so their cursor holds that instead
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: DateTime,
+    pub id: i64,
+}
+
+impl Cursor {
+    pub fn new(created_at: DateTime, id: impl Into<i64>) -> Self {
+        Self {
+            created_at,
+            id: id.into(),
+        }
+    }
+
+    /// Opaque representation, clients should only pass it back
+    pub fn encode(&self) -> String {
+        let raw = format!("{}:{}", self.created_at.timestamp_micros(), self.id);
+        let mut encoded = String::with_capacity(raw.len() * 2);
+        for byte in raw.as_bytes() {
+            // Writing to a string never fails
+            let _ = write!(encoded, "{:02x}", byte);
+        }
+        encoded
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let invalid = || Error::InvalidCursor(encoded.to_owned());
+        let bytes = encoded
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+                match pair.len() {
+                    2 => u8::from_str_radix(pair, 16).map_err(|_| invalid()),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<u8>>>()?;
+        let raw = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+        let id: i64 = id.parse().map_err(|_| invalid())?;
+
+        let created_at = chrono::Utc
+            .timestamp_opt(
+                micros.div_euclid(1_000_000),
+                (micros.rem_euclid(1_000_000) * 1000) as u32,
+            )
+            .single()
+            .ok_or_else(invalid)?;
+        Ok(Self { created_at, id })
+    }
+}
+
+/// Validates the requested page size, defaulting to `DEFAULT_PAGE_SIZE`
+pub fn page_limit(limit: Option<u32>) -> Result<u32> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit > MAX_PAGE_SIZE {
+        return Err(Error::AskedForTooMany);
+    }
+    Ok(limit.max(1))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    items: Vec<T>,
+    next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Expects up to `limit + 1` items, the extra one only signals that there is a next page
+    pub fn new(mut items: Vec<T>, limit: u32, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next_cursor = if items.len() > limit as usize {
+            items.truncate(limit as usize);
+            items.last().map(|item| cursor(item).encode())
+        } else {
+            None
+        };
+        Self { items, next_cursor }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn next_cursor(&self) -> Option<&str> {
+        self.next_cursor.as_deref()
+    }
+
+    pub fn try_map<U>(self, f: impl FnMut(T) -> Result<U>) -> Result<Page<U>> {
+        Ok(Page {
+            items: self.items.into_iter().map(f).collect::<Result<Vec<U>>>()?,
+            next_cursor: self.next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let created_at = chrono::Utc
+            .timestamp_opt(1_700_000_000, 123_456_000)
+            .unwrap();
+        let cursor = Cursor::new(created_at, 42);
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        // Timestamps before the epoch floor towards the past
+        let created_at = chrono::Utc.timestamp_opt(-1, 999_999_000).unwrap();
+        let cursor = Cursor::new(created_at, 1);
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        for encoded in ["", "0", "zz", "3132", "313a", "3a3132", "ff3a31"] {
+            assert!(
+                matches!(Cursor::decode(encoded), Err(Error::InvalidCursor(_))),
+                "{encoded}"
+            );
+        }
+    }
+}
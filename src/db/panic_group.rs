@@ -0,0 +1,194 @@
+use crate::{
+    Cursor, DateTime, Device, Firmware, NewDevicePanic, Organization, OrganizationId, Page, Result,
+    Transaction,
+};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+#[id]
+pub struct PanicGroupId;
+
+/// Panics of an organization with the same fingerprint: file, line, function and message, with numbers and addresses masked (see `panic_message` in the migrations)
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PanicGroup {
+    #[copy]
+    id: PanicGroupId,
+    #[copy]
+    organization_id: OrganizationId,
+    fingerprint: String,
+    file: String,
+    #[copy]
+    line: i32,
+    func: String,
+    /// Masked message
+    msg: String,
+    #[copy]
+    occurrences: i64,
+    #[copy]
+    affected_devices: i64,
+    #[copy]
+    first_seen_at: DateTime,
+    #[copy]
+    last_seen_at: DateTime,
+    #[copy]
+    is_solved: bool,
+    #[copy]
+    solved_at: Option<DateTime>,
+    /// Newest firmware it happened on when solved, recurring on a later one reopens the group
+    solved_firmware_hash: Option<String>,
+}
+
+impl PanicGroup {
+    /// Counts the occurrence in its group, reopening it if it was solved on an older firmware
+    pub async fn record(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        device: &Device,
+        firmware: &Firmware,
+        panic: &NewDevicePanic,
+    ) -> Result<PanicGroupId> {
+        let (id, is_solved, solved_firmware_hash): (PanicGroupId, bool, Option<String>) =
+            sqlx::query_as(
+                "INSERT INTO panic_groups (organization_id, fingerprint, file, line, func, msg, occurrences, first_seen_at, last_seen_at)
+                 VALUES ($1, panic_fingerprint($2, $3, $4, $5), $2, $3, $4, panic_message($5), 1, NOW(), NOW())
+                 ON CONFLICT (organization_id, fingerprint) DO UPDATE
+                 SET occurrences = panic_groups.occurrences + 1, last_seen_at = NOW(), updated_at = NOW()
+                 RETURNING id, is_solved, solved_firmware_hash",
+            )
+            .bind(organization.id())
+            .bind(panic.file())
+            .bind(panic.line())
+            .bind(panic.func())
+            .bind(&panic.msg)
+            .fetch_one(&mut *txn)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO panic_group_devices (panic_group_id, device_id, occurrences, first_seen_at, last_seen_at)
+             VALUES ($1, $2, 1, NOW(), NOW())
+             ON CONFLICT (panic_group_id, device_id) DO UPDATE
+             SET occurrences = panic_group_devices.occurrences + 1, last_seen_at = NOW()",
+        )
+        .bind(id)
+        .bind(device.id())
+        .execute(&mut *txn)
+        .await?;
+
+        if is_solved {
+            // Binaries are shared between organizations, so the solved one is looked up in the group's. If it's gone it was older than any in use
+            let (is_newer,): (bool,) = sqlx::query_as(
+                "SELECT $1::TEXT IS NULL OR NOT EXISTS (
+                     SELECT 1
+                     FROM firmwares solved
+                     INNER JOIN firmwares current ON current.id = $2
+                     WHERE solved.organization_id = $3 AND solved.binary_hash = $1
+                           AND (solved.created_at, solved.id) >= (current.created_at, current.id))",
+            )
+            .bind(&solved_firmware_hash)
+            .bind(firmware.id())
+            .bind(organization.id())
+            .fetch_one(&mut *txn)
+            .await?;
+            if is_newer {
+                sqlx::query(
+                    "UPDATE panic_groups SET is_solved = FALSE, solved_at = NULL, solved_firmware_hash = NULL, updated_at = NOW() WHERE id = $1",
+                )
+                .bind(id)
+                .execute(&mut *txn)
+                .await?;
+            }
+        }
+        Ok(id)
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        id: PanicGroupId,
+    ) -> Result<Self> {
+        let group = sqlx::query_as(
+            "SELECT g.id, g.organization_id, g.fingerprint, g.file, g.line, g.func, g.msg, g.occurrences,
+                    (SELECT COUNT(*) FROM panic_group_devices WHERE panic_group_id = g.id) AS affected_devices,
+                    g.first_seen_at, g.last_seen_at, g.is_solved, g.solved_at, g.solved_firmware_hash
+             FROM panic_groups g
+             WHERE g.id = $1 AND g.organization_id = $2",
+        )
+        .bind(id)
+        .bind(organization.id())
+        .fetch_one(txn)
+        .await?;
+        Ok(group)
+    }
+
+    /// Most recently seen first, `solved` filters by status
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        solved: Option<bool>,
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let groups: Vec<Self> = sqlx::query_as(
+            "SELECT g.id, g.organization_id, g.fingerprint, g.file, g.line, g.func, g.msg, g.occurrences,
+                    (SELECT COUNT(*) FROM panic_group_devices WHERE panic_group_id = g.id) AS affected_devices,
+                    g.first_seen_at, g.last_seen_at, g.is_solved, g.solved_at, g.solved_firmware_hash
+             FROM panic_groups g
+             WHERE g.organization_id = $1
+                   AND ($2::BOOLEAN IS NULL OR g.is_solved = $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR g.last_seen_at < $3)
+                   AND ($4::TIMESTAMPTZ IS NULL OR (g.last_seen_at, g.id) < ($4, $5))
+             ORDER BY g.last_seen_at DESC, g.id DESC
+             LIMIT $6",
+        )
+        .bind(organization.id())
+        .bind(solved)
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
+        .fetch_all(txn)
+        .await?;
+        Ok(Page::new(groups, limit, |g| {
+            Cursor::new(g.last_seen_at, g.id)
+        }))
+    }
+
+    /// Solves every occurrence, remembering the newest firmware it happened on
+    pub async fn solve(&mut self, txn: &mut Transaction<'_>) -> Result<()> {
+        let firmware_hash: Option<(String,)> = sqlx::query_as(
+            "SELECT p.firmware_hash
+             FROM device_panics p
+             INNER JOIN firmwares ON firmwares.binary_hash = p.firmware_hash AND firmwares.organization_id = $2
+             WHERE p.panic_group_id = $1
+             ORDER BY firmwares.created_at DESC, firmwares.id DESC
+             LIMIT 1",
+        )
+        .bind(self.id)
+        .bind(self.organization_id)
+        .fetch_optional(&mut *txn)
+        .await?;
+        let solved_firmware_hash = firmware_hash.map(|(hash,)| hash);
+
+        let (solved_at,): (DateTime,) = sqlx::query_as(
+            "UPDATE panic_groups SET is_solved = TRUE, solved_at = NOW(), solved_firmware_hash = $1, updated_at = NOW() WHERE id = $2 RETURNING solved_at",
+        )
+        .bind(&solved_firmware_hash)
+        .bind(self.id)
+        .fetch_one(&mut *txn)
+        .await?;
+        sqlx::query(
+            "UPDATE device_panics SET is_solved = TRUE, updated_at = NOW() WHERE panic_group_id = $1 AND NOT is_solved",
+        )
+        .bind(self.id)
+        .execute(txn)
+        .await?;
+
+        self.is_solved = true;
+        self.solved_at = Some(solved_at);
+        self.solved_firmware_hash = solved_firmware_hash;
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    logger::*, DateTime, Error, MeasurementRollup, Organization, OrganizationId, Pool, Result,
+    RollupResolution, Transaction, MAX_EVENT_AGE_DAYS,
+};
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+/// Rows deleted per transaction, so enforcing doesn't hold locks over whole tables
+pub const RETENTION_BATCH_SIZE: i64 = 10_000;
+/// Late batched events recompute the rollups of their bucket, the raw measurements around them must still exist
+pub const MIN_RAW_EVENTS_DAYS: i32 = MAX_EVENT_AGE_DAYS as i32 + 2;
+const MAX_RETENTION_DAYS: i32 = 100 * 365;
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewRetentionPolicy {
+    #[copy]
+    pub raw_events_days: i32,
+    #[copy]
+    pub hourly_aggregates_days: i32,
+    #[copy]
+    pub daily_aggregates_days: i32,
+    #[copy]
+    pub logs_days: i32,
+    #[copy]
+    pub panics_days: i32,
+}
+
+impl NewRetentionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        let days = [
+            self.raw_events_days,
+            self.hourly_aggregates_days,
+            self.daily_aggregates_days,
+            self.logs_days,
+            self.panics_days,
+        ];
+        if days.iter().any(|d| !(1..=MAX_RETENTION_DAYS).contains(d)) {
+            return Err(Error::InvalidRetentionPolicy(format!(
+                "retention must be between 1 and {} days",
+                MAX_RETENTION_DAYS
+            )));
+        }
+        if self.raw_events_days < MIN_RAW_EVENTS_DAYS {
+            return Err(Error::InvalidRetentionPolicy(format!(
+                "raw events must be kept for at least {} days",
+                MIN_RAW_EVENTS_DAYS
+            )));
+        }
+        // Aggregates are what is left after the raw events are gone
+        if self.hourly_aggregates_days < self.raw_events_days
+            || self.daily_aggregates_days < self.hourly_aggregates_days
+        {
+            return Err(Error::InvalidRetentionPolicy(
+                "aggregates can't be deleted before the data they summarize".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How long an organization's device data is kept, organizations without one keep everything
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    #[copy]
+    organization_id: OrganizationId,
+    #[copy]
+    raw_events_days: i32,
+    #[copy]
+    hourly_aggregates_days: i32,
+    #[copy]
+    daily_aggregates_days: i32,
+    #[copy]
+    logs_days: i32,
+    #[copy]
+    panics_days: i32,
+    #[copy]
+    updated_at: DateTime,
+}
+
+impl RetentionPolicy {
+    pub async fn find(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+    ) -> Result<Option<Self>> {
+        let policy = sqlx::query_as(
+            "SELECT organization_id, raw_events_days, hourly_aggregates_days, daily_aggregates_days, logs_days, panics_days, updated_at
+             FROM retention_policies
+             WHERE organization_id = $1",
+        )
+        .bind(organization.id())
+        .fetch_optional(txn)
+        .await?;
+        Ok(policy)
+    }
+
+    pub async fn set(
+        txn: &mut Transaction<'_>,
+        organization: &Organization,
+        new_policy: NewRetentionPolicy,
+    ) -> Result<Self> {
+        new_policy.validate()?;
+
+        let policy = sqlx::query_as(
+            "INSERT INTO retention_policies (organization_id, raw_events_days, hourly_aggregates_days, daily_aggregates_days, logs_days, panics_days)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (organization_id) DO UPDATE
+             SET raw_events_days = EXCLUDED.raw_events_days, hourly_aggregates_days = EXCLUDED.hourly_aggregates_days,
+                 daily_aggregates_days = EXCLUDED.daily_aggregates_days, logs_days = EXCLUDED.logs_days,
+                 panics_days = EXCLUDED.panics_days, updated_at = NOW()
+             RETURNING organization_id, raw_events_days, hourly_aggregates_days, daily_aggregates_days, logs_days, panics_days, updated_at",
+        )
+        .bind(organization.id())
+        .bind(new_policy.raw_events_days)
+        .bind(new_policy.hourly_aggregates_days)
+        .bind(new_policy.daily_aggregates_days)
+        .bind(new_policy.logs_days)
+        .bind(new_policy.panics_days)
+        .fetch_one(txn)
+        .await?;
+        Ok(policy)
+    }
+
+    /// Deletes whatever every policy allows, measurements are rolled up before their events go away
+    ///
+    /// Devices whose collection is shared by many organizations follow the longest retention.
+    /// Each batch of `batch_size` rows is committed on its own
+    pub async fn enforce(pool: &'static Pool, batch_size: i64) -> Result<()> {
+        for resolution in [RollupResolution::Hour, RollupResolution::Day] {
+            let mut txn = pool.begin().await?;
+            MeasurementRollup::update(&mut txn, resolution).await?;
+            txn.commit().await?;
+        }
+
+        // Fixed for the whole run, so no event expires after its measurements were deleted
+        let now = chrono::Utc::now();
+
+        let expired_events = Self::expired("raw_events_days");
+        for table in ["measurements", "sensor_faults"] {
+            let rows = format!(
+                "SELECT {table}.ctid
+                 FROM {table}
+                 INNER JOIN events ON events.id = {table}.event_id
+                 INNER JOIN expired ON expired.device_id = events.device_id
+                 WHERE events.created_at < expired.cutoff"
+            );
+            Self::delete(pool, table, &expired_events, &rows, now, batch_size).await?;
+        }
+
+        let targets = [
+            ("events", "created_at", expired_events, ""),
+            ("device_logs", "created_at", Self::expired("logs_days"), ""),
+            (
+                "device_panics",
+                "created_at",
+                Self::expired("panics_days"),
+                "",
+            ),
+            (
+                "measurement_rollups",
+                "bucket_start",
+                Self::expired("hourly_aggregates_days"),
+                "AND measurement_rollups.resolution = 'Hour'",
+            ),
+            (
+                "measurement_rollups",
+                "bucket_start",
+                Self::expired("daily_aggregates_days"),
+                "AND measurement_rollups.resolution = 'Day'",
+            ),
+        ];
+        for (table, column, expired, filter) in targets {
+            let rows = format!(
+                "SELECT {table}.ctid
+                 FROM {table}
+                 INNER JOIN expired ON expired.device_id = {table}.device_id
+                 WHERE {table}.{column} < expired.cutoff {filter}"
+            );
+            Self::delete(pool, table, &expired, &rows, now, batch_size).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the `rows` (`ctid`s of `table`) in batches until there is none left
+    async fn delete(
+        pool: &'static Pool,
+        table: &str,
+        expired: &str,
+        rows: &str,
+        now: DateTime,
+        batch_size: i64,
+    ) -> Result<()> {
+        let query = format!(
+            "WITH {expired}
+             DELETE FROM {table}
+             WHERE ctid = ANY(ARRAY({rows} LIMIT $2))"
+        );
+        let mut deleted = 0;
+        loop {
+            let mut txn = pool.begin().await?;
+            let batch = sqlx::query(&query)
+                .bind(now)
+                .bind(batch_size)
+                .execute(&mut txn)
+                .await?
+                .rows_affected();
+            txn.commit().await?;
+
+            deleted += batch;
+            if batch < batch_size as u64 {
+                break;
+            }
+        }
+        info!("Retention deleted {deleted} from {table}");
+        Ok(())
+    }
+
+    /// `expired` CTE, with the moment before `$1` when the device's data can be deleted
+    ///
+    /// Devices of an organization without a policy are left out, their data is kept
+    fn expired(column: &str) -> String {
+        format!(
+            "expired AS (
+                 SELECT devices.id AS device_id, $1::TIMESTAMPTZ - make_interval(days => MAX(policy.{column})) AS cutoff
+                 FROM devices
+                 INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = devices.collection_id
+                 LEFT JOIN retention_policies policy ON policy.organization_id = cbt.organization_id
+                 GROUP BY devices.id
+                 HAVING BOOL_AND(policy.organization_id IS NOT NULL)
+             )"
+        )
+    }
+}
@@ -0,0 +1,432 @@
+use crate::{
+    logger::*, Compiler, CompilerId, DateTime, DeviceId, Error, EventId, MeasurementId,
+    MeasurementRollup, NewMeasurement, Result, Sensor, SensorId, SensorMeasurementKind,
+    SensorMeasurementType, Transaction,
+};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+
+/// Raw measurements are recalibrated in chunks of this size, one per transaction
+const RECOMPUTE_BATCH: i64 = 1000;
+
+#[derive(Getters, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CalibrationPoint {
+    #[copy]
+    raw: f64,
+    #[copy]
+    calibrated: f64,
+}
+
+/// Maps a raw reading to the calibrated value
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum CalibrationCurve {
+    /// Line through both points, extrapolated outside of them
+    Linear { points: [CalibrationPoint; 2] },
+    /// Interpolates between the neighbouring points, clamped to the first and last ones
+    Piecewise { points: Vec<CalibrationPoint> },
+}
+
+impl CalibrationCurve {
+    /// Sorts the points by their raw value, refusing curves that don't describe a function
+    fn normalize(&mut self) -> Result<()> {
+        let points = match self {
+            Self::Linear { points } => &mut points[..],
+            Self::Piecewise { points } => &mut points[..],
+        };
+        if points.len() < 2 {
+            return Err(Error::InvalidCalibration(
+                "at least two points are needed".to_owned(),
+            ));
+        }
+        if points
+            .iter()
+            .any(|p| !p.raw.is_finite() || !p.calibrated.is_finite())
+        {
+            return Err(Error::InvalidCalibration(
+                "points must be finite numbers".to_owned(),
+            ));
+        }
+        points.sort_by(|a, b| a.raw.total_cmp(&b.raw));
+        if points.windows(2).any(|pair| pair[0].raw == pair[1].raw) {
+            return Err(Error::InvalidCalibration(
+                "raw values must be distinct".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, raw: f64) -> f64 {
+        let points = match self {
+            Self::Linear { points } => &points[..],
+            Self::Piecewise { points } => {
+                let (first, last) = (points[0], points[points.len() - 1]);
+                if raw <= first.raw {
+                    return first.calibrated;
+                } else if raw >= last.raw {
+                    return last.calibrated;
+                }
+                let index = points.iter().position(|p| p.raw > raw).unwrap_or(1);
+                &points[index - 1..=index]
+            }
+        };
+        let (a, b) = (points[0], points[1]);
+        a.calibrated + (raw - a.raw) * (b.calibrated - a.calibrated) / (b.raw - a.raw)
+    }
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewSensorCalibration {
+    /// Raw measurement of the sensor being calibrated
+    pub variable_name: String,
+    /// Name of the derived series, must not clash with the compiler's raw measurements
+    pub calibrated_variable_name: String,
+    pub kind: SensorMeasurementKind,
+    pub ty: SensorMeasurementType,
+    pub curve: CalibrationCurve,
+}
+
+#[id]
+pub struct SensorCalibrationId;
+
+/// Produces a calibrated series next to one of the sensor's raw measurements
+///
+/// Raw measurements are always kept, so the calibration can be changed and applied retroactively
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorCalibration {
+    #[copy]
+    id: SensorCalibrationId,
+    #[copy]
+    compiler_id: CompilerId,
+    #[copy]
+    sensor_id: SensorId,
+    variable_name: String,
+    calibrated_variable_name: String,
+    kind: SensorMeasurementKind,
+    ty: SensorMeasurementType,
+    curve: Json<CalibrationCurve>,
+    /// The calibrated series is being rebuilt in the background and is still incomplete
+    #[copy]
+    recalibrating: bool,
+    #[copy]
+    created_at: DateTime,
+    #[copy]
+    updated_at: DateTime,
+}
+
+impl SensorCalibration {
+    /// Replaces the sensor measurement's calibration if it already exists, its history is recalibrated in the background
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        compiler: &Compiler,
+        sensor: &Sensor,
+        mut new_calibration: NewSensorCalibration,
+    ) -> Result<Self> {
+        new_calibration.curve.normalize()?;
+
+        let mut variable_names = Vec::new();
+        for view in compiler.sensors(txn).await? {
+            for measurement in view.measurements() {
+                variable_names.push((view.id(), measurement.variable_name().to_owned()));
+            }
+        }
+        if !variable_names.contains(&(sensor.id(), new_calibration.variable_name.clone())) {
+            return Err(Error::MissingMeasurement(new_calibration.variable_name));
+        }
+        let calibrated_names = Self::from_compiler(txn, compiler)
+            .await?
+            .into_iter()
+            .filter(|c| {
+                c.sensor_id != sensor.id() || c.variable_name != new_calibration.variable_name
+            })
+            .map(|c| c.calibrated_variable_name);
+        if variable_names
+            .into_iter()
+            .map(|(_, name)| name)
+            .chain(calibrated_names)
+            .any(|name| name == new_calibration.calibrated_variable_name)
+        {
+            return Err(Error::InvalidCalibration(format!(
+                "{} is already a measurement",
+                new_calibration.calibrated_variable_name
+            )));
+        }
+
+        let calibration: Self = sqlx::query_as(
+            "INSERT INTO sensor_calibrations (compiler_id, sensor_id, variable_name, calibrated_variable_name, kind, ty, curve, recalibrating)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, TRUE)
+             ON CONFLICT (compiler_id, sensor_id, variable_name) DO UPDATE
+             SET calibrated_variable_name = EXCLUDED.calibrated_variable_name, kind = EXCLUDED.kind,
+                 ty = EXCLUDED.ty, curve = EXCLUDED.curve, recalibrating = TRUE, recalibrated_after = NULL,
+                 updated_at = NOW()
+             RETURNING id, compiler_id, sensor_id, variable_name, calibrated_variable_name, kind, ty, curve, recalibrating, created_at, updated_at",
+        )
+        .bind(compiler.id())
+        .bind(sensor.id())
+        .bind(&new_calibration.variable_name)
+        .bind(&new_calibration.calibrated_variable_name)
+        .bind(&new_calibration.kind)
+        .bind(&new_calibration.ty)
+        .bind(Json(&new_calibration.curve))
+        .fetch_one(txn)
+        .await?;
+        Ok(calibration)
+    }
+
+    pub async fn find_by_id(
+        txn: &mut Transaction<'_>,
+        compiler: &Compiler,
+        calibration_id: SensorCalibrationId,
+    ) -> Result<Self> {
+        let calibration = sqlx::query_as(
+            "SELECT id, compiler_id, sensor_id, variable_name, calibrated_variable_name, kind, ty, curve, recalibrating, created_at, updated_at
+             FROM sensor_calibrations
+             WHERE id = $1 AND compiler_id = $2",
+        )
+        .bind(calibration_id)
+        .bind(compiler.id())
+        .fetch_one(txn)
+        .await?;
+        Ok(calibration)
+    }
+
+    pub async fn from_compiler(
+        txn: &mut Transaction<'_>,
+        compiler: &Compiler,
+    ) -> Result<Vec<Self>> {
+        let calibrations = sqlx::query_as(
+            "SELECT id, compiler_id, sensor_id, variable_name, calibrated_variable_name, kind, ty, curve, recalibrating, created_at, updated_at
+             FROM sensor_calibrations
+             WHERE compiler_id = $1
+             ORDER BY sensor_id ASC, variable_name ASC",
+        )
+        .bind(compiler.id())
+        .fetch_all(txn)
+        .await?;
+        Ok(calibrations)
+    }
+
+    /// Calibrated value, clamped to what the calibrated type can hold
+    pub fn apply(&self, raw: f64) -> f64 {
+        let range = self.ty.range();
+        self.curve.apply(raw).clamp(range.min, range.max)
+    }
+
+    pub fn measurement(&self, raw: f64) -> NewMeasurement {
+        NewMeasurement {
+            sensor_id: self.sensor_id,
+            variable_name: self.calibrated_variable_name.clone(),
+            kind: self.kind.clone(),
+            ty: self.ty.clone(),
+            value: self.apply(raw),
+            calibration_id: Some(self.id),
+        }
+    }
+
+    /// Drops the calibrated series, with its rollups, so it can be derived again from the raw measurements
+    async fn clear(&self, txn: &mut Transaction<'_>) -> Result<()> {
+        sqlx::query(
+            "DELETE FROM measurement_rollups r
+             USING (SELECT DISTINCT device_id, sensor_id, variable_name FROM measurements WHERE calibration_id = $1) c
+             WHERE r.device_id = c.device_id AND r.sensor_id = c.sensor_id AND r.variable_name = c.variable_name",
+        )
+        .bind(self.id)
+        .execute(&mut *txn)
+        .await?;
+        sqlx::query("DELETE FROM measurements WHERE calibration_id = $1")
+            .bind(self.id)
+            .execute(&mut *txn)
+            .await?;
+        Ok(())
+    }
+
+    /// Recalibrates the next batch of the oldest calibration being rebuilt, false if none is left
+    ///
+    /// Each batch is its own transaction, so requests and ingestion aren't blocked by long histories
+    pub async fn recompute_next(txn: &mut Transaction<'_>) -> Result<bool> {
+        // Skips calibrations locked by a concurrent run, or being replaced or deleted
+        let pending: Option<(SensorCalibrationId, Option<i64>)> = sqlx::query_as(
+            "SELECT id, recalibrated_after
+             FROM sensor_calibrations
+             WHERE recalibrating
+             ORDER BY updated_at ASC
+             LIMIT 1
+             FOR UPDATE SKIP LOCKED",
+        )
+        .fetch_optional(&mut *txn)
+        .await?;
+        let (id, recalibrated_after) = match pending {
+            Some(pending) => pending,
+            None => return Ok(false),
+        };
+        let calibration: Self = sqlx::query_as(
+            "SELECT id, compiler_id, sensor_id, variable_name, calibrated_variable_name, kind, ty, curve, recalibrating, created_at, updated_at
+             FROM sensor_calibrations
+             WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_one(&mut *txn)
+        .await?;
+
+        // Measurements calibrated on ingestion meanwhile are cleared too, and recalibrated with the rest
+        let recalibrated_after = match recalibrated_after {
+            Some(recalibrated_after) => recalibrated_after,
+            None => {
+                calibration.clear(txn).await?;
+                0
+            }
+        };
+        let last_id = calibration.recompute(txn, recalibrated_after).await?;
+        sqlx::query(
+            "UPDATE sensor_calibrations SET recalibrating = $2, recalibrated_after = $3 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(last_id.is_some())
+        .bind(last_id)
+        .execute(&mut *txn)
+        .await?;
+        if last_id.is_none() {
+            info!(
+                "Recalibrated {} into {}",
+                calibration.variable_name, calibration.calibrated_variable_name
+            );
+        }
+        Ok(true)
+    }
+
+    /// Recalibrates the raw measurements after `last_id` of every device running this compiler, returning the last one
+    async fn recompute(&self, txn: &mut Transaction<'_>, last_id: i64) -> Result<Option<i64>> {
+        let raw: Vec<(MeasurementId, EventId, DeviceId, f64, DateTime)> = sqlx::query_as(
+            "SELECT m.id, m.event_id, m.device_id, m.value, m.created_at
+             FROM measurements m
+             INNER JOIN devices ON devices.id = m.device_id
+             INNER JOIN collections ON collections.id = devices.collection_id
+             WHERE collections.compiler_id = $1 AND m.sensor_id = $2 AND m.variable_name = $3
+                   AND m.calibration_id IS NULL AND m.id > $4
+             ORDER BY m.id ASC
+             LIMIT $5",
+        )
+        .bind(self.compiler_id)
+        .bind(self.sensor_id)
+        .bind(&self.variable_name)
+        .bind(last_id)
+        .bind(RECOMPUTE_BATCH)
+        .fetch_all(&mut *txn)
+        .await?;
+        let (id, ..) = match raw.last() {
+            Some(last) => *last,
+            None => return Ok(None),
+        };
+
+        let event_ids: Vec<EventId> = raw.iter().map(|r| r.1).collect();
+        let device_ids: Vec<DeviceId> = raw.iter().map(|r| r.2).collect();
+        let values: Vec<f64> = raw.iter().map(|r| self.apply(r.3)).collect();
+        let created_ats: Vec<DateTime> = raw.iter().map(|r| r.4).collect();
+        sqlx::query(
+            "INSERT INTO measurements (event_id, device_id, sensor_id, variable_name, kind, ty, value, created_at, calibration_id)
+             SELECT event_id, device_id, $5, $6, $7, $8, value, created_at, $9
+             FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::DOUBLE PRECISION[], $4::TIMESTAMPTZ[]) AS raw (event_id, device_id, value, created_at)
+             ON CONFLICT (event_id, variable_name) DO NOTHING",
+        )
+        .bind(&event_ids)
+        .bind(&device_ids)
+        .bind(&values)
+        .bind(&created_ats)
+        .bind(self.sensor_id)
+        .bind(&self.calibrated_variable_name)
+        .bind(&self.kind)
+        .bind(&self.ty)
+        .bind(self.id)
+        .execute(&mut *txn)
+        .await?;
+        MeasurementRollup::mark_dirty(
+            txn,
+            &device_ids,
+            &vec![self.sensor_id; raw.len()],
+            &vec![self.calibrated_variable_name.clone(); raw.len()],
+            &created_ats,
+        )
+        .await?;
+        debug!(
+            "Recalibrated {} measurements of {} into {}",
+            raw.len(),
+            self.variable_name,
+            self.calibrated_variable_name
+        );
+        Ok(Some(i64::from(id)))
+    }
+
+    pub async fn delete(self, txn: &mut Transaction<'_>) -> Result<()> {
+        self.clear(txn).await?;
+        sqlx::query("DELETE FROM sensor_calibrations WHERE id = $1")
+            .bind(self.id)
+            .execute(txn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(points: &[(f64, f64)]) -> Vec<CalibrationPoint> {
+        points
+            .iter()
+            .map(|&(raw, calibrated)| CalibrationPoint { raw, calibrated })
+            .collect()
+    }
+
+    #[test]
+    fn linear_extrapolates() {
+        let mut curve = CalibrationCurve::Linear {
+            points: [
+                CalibrationPoint {
+                    raw: 10.,
+                    calibrated: 100.,
+                },
+                CalibrationPoint {
+                    raw: 0.,
+                    calibrated: 0.,
+                },
+            ],
+        };
+        curve.normalize().unwrap();
+        assert_eq!(curve.apply(5.), 50.);
+        assert_eq!(curve.apply(20.), 200.);
+        assert_eq!(curve.apply(-1.), -10.);
+    }
+
+    #[test]
+    fn piecewise_interpolates_and_clamps() {
+        let mut curve = CalibrationCurve::Piecewise {
+            points: points(&[(10., 0.), (0., 100.), (20., 40.)]),
+        };
+        curve.normalize().unwrap();
+        assert_eq!(curve.apply(5.), 50.);
+        assert_eq!(curve.apply(10.), 0.);
+        assert_eq!(curve.apply(15.), 20.);
+        assert_eq!(curve.apply(-5.), 100.);
+        assert_eq!(curve.apply(25.), 40.);
+    }
+
+    #[test]
+    fn normalize_rejects_non_functions() {
+        for invalid in [
+            points(&[(1., 1.)]),
+            points(&[(1., 1.), (1., 2.)]),
+            points(&[(1., f64::NAN), (2., 2.)]),
+        ] {
+            let mut curve = CalibrationCurve::Piecewise { points: invalid };
+            assert!(matches!(
+                curve.normalize(),
+                Err(Error::InvalidCalibration(_))
+            ));
+        }
+    }
+}
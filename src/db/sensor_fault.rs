@@ -0,0 +1,145 @@
+use crate::{Cursor, DateTime, Device, Event, EventId, Page, Result, SensorId, Transaction};
+use derive::id;
+use derive_get::Getters;
+use serde::{Deserialize, Serialize};
+
+#[derive(sqlx::Type, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorFaultReason {
+    /// Reading the sensor failed, it reported null (or NaN, which JSON serializers turn into null)
+    Null,
+    /// The compiler expects the measurement but the event doesn't have it
+    Missing,
+    /// The value can't be parsed as the measurement's type
+    InvalidType,
+    /// The value is outside of what the sensor can measure
+    OutOfRange,
+    /// The event has a measurement the compiler doesn't know about
+    Unexpected,
+}
+
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewSensorFault {
+    #[copy]
+    pub sensor_id: Option<SensorId>,
+    pub variable_name: String,
+    #[copy]
+    pub reason: SensorFaultReason,
+    pub value: Option<serde_json::Value>,
+}
+
+#[id]
+pub struct SensorFaultId;
+
+/// Measurement of an event that couldn't be stored, the rest of the event is still accepted
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorFault {
+    #[copy]
+    id: SensorFaultId,
+    #[copy]
+    event_id: EventId,
+    #[copy]
+    sensor_id: Option<SensorId>,
+    variable_name: String,
+    #[copy]
+    reason: SensorFaultReason,
+    value: Option<serde_json::Value>,
+    #[copy]
+    created_at: DateTime,
+}
+
+/// Amount of faults of a measurement in a time range
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SensorFaultCount {
+    #[copy]
+    sensor_id: Option<SensorId>,
+    variable_name: String,
+    #[copy]
+    reason: SensorFaultReason,
+    #[copy]
+    count: i64,
+    #[copy]
+    last_at: DateTime,
+}
+
+impl SensorFault {
+    pub async fn new(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        event: &Event,
+        new_fault: NewSensorFault,
+    ) -> Result<Self> {
+        let (id,): (SensorFaultId,) = sqlx::query_as(
+            "INSERT INTO sensor_faults (event_id, device_id, sensor_id, variable_name, reason, value, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        )
+        .bind(event.id())
+        .bind(device.id())
+        .bind(new_fault.sensor_id)
+        .bind(&new_fault.variable_name)
+        .bind(new_fault.reason)
+        .bind(&new_fault.value)
+        .bind(event.measured_or_created_at())
+        .fetch_one(txn)
+        .await?;
+        Ok(Self {
+            id,
+            event_id: event.id(),
+            sensor_id: new_fault.sensor_id,
+            variable_name: new_fault.variable_name,
+            reason: new_fault.reason,
+            value: new_fault.value,
+            created_at: event.measured_or_created_at(),
+        })
+    }
+
+    pub async fn list(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        until: Option<DateTime>,
+        cursor: Option<Cursor>,
+        limit: u32,
+    ) -> Result<Page<Self>> {
+        let faults: Vec<Self> = sqlx::query_as(
+            "SELECT id, event_id, sensor_id, variable_name, reason, value, created_at
+             FROM sensor_faults
+             WHERE device_id = $1
+                   AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR (created_at, id) < ($3, $4))
+             ORDER BY created_at DESC, id DESC
+             LIMIT $5",
+        )
+        .bind(device.id())
+        .bind(until)
+        .bind(cursor.map(|c| c.created_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(i64::from(limit) + 1)
+        .fetch_all(txn)
+        .await?;
+        Ok(Page::new(faults, limit, |f| {
+            Cursor::new(f.created_at, f.id)
+        }))
+    }
+
+    pub async fn count(
+        txn: &mut Transaction<'_>,
+        device: &Device,
+        since: DateTime,
+        until: DateTime,
+    ) -> Result<Vec<SensorFaultCount>> {
+        let counts = sqlx::query_as(
+            "SELECT sensor_id, variable_name, reason, COUNT(*) AS count, MAX(created_at) AS last_at
+             FROM sensor_faults
+             WHERE device_id = $1 AND created_at >= $2 AND created_at < $3
+             GROUP BY sensor_id, variable_name, reason
+             ORDER BY sensor_id ASC NULLS LAST, variable_name ASC, reason ASC",
+        )
+        .bind(device.id())
+        .bind(since)
+        .bind(until)
+        .fetch_all(txn)
+        .await?;
+        Ok(counts)
+    }
+}
@@ -7,13 +7,108 @@ pub enum SensorMeasurementKind {
     SoilTemperature,
     AirHumidity,
     SoilMoisture,
+    Light,
+    Ph,
+    ElectricalConductivity,
+    Co2,
+    BarometricPressure,
+    WaterLevel,
+    FlowVolume,
+    BatteryVoltage,
+    OnOff,
 }
 
 #[derive(sqlx::Type, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum SensorMeasurementType {
     FloatCelsius,
     Percentage,
-    RawAnalogRead, // (0-1024)
+    RawAnalogRead, // (0-1024 on ESP8266, 0-4095 on ESP32)
+    FloatLux,
+    FloatPh,
+    FloatMicrosiemensPerCentimeter,
+    FloatPartsPerMillion,
+    FloatHectopascal,
+    FloatCentimeters,
+    FloatLiters,
+    FloatVolts,
+    Boolean, // Stored as 0 or 1
+}
+
+impl SensorMeasurementType {
+    /// Values outside of it can't be produced by a working sensor
+    pub fn range(&self) -> MeasurementRange {
+        match self {
+            Self::FloatCelsius => MeasurementRange::new(-55., 125.),
+            Self::Percentage => MeasurementRange::new(0., 100.),
+            Self::RawAnalogRead => MeasurementRange::new(0., 4095.),
+            Self::FloatLux => MeasurementRange::new(0., 200_000.),
+            Self::FloatPh => MeasurementRange::new(0., 14.),
+            Self::FloatMicrosiemensPerCentimeter => MeasurementRange::new(0., 200_000.),
+            Self::FloatPartsPerMillion => MeasurementRange::new(0., 1_000_000.),
+            Self::FloatHectopascal => MeasurementRange::new(300., 1100.),
+            Self::FloatCentimeters => MeasurementRange::new(0., 100_000.),
+            Self::FloatLiters => MeasurementRange::new(0., 1_000_000_000.),
+            Self::FloatVolts => MeasurementRange::new(0., 60.),
+            Self::Boolean => MeasurementRange::new(0., 1.),
+        }
+    }
+
+    pub fn unit(&self) -> Option<&'static str> {
+        match self {
+            Self::FloatCelsius => Some("°C"),
+            Self::Percentage => Some("%"),
+            Self::RawAnalogRead => None,
+            Self::FloatLux => Some("lx"),
+            Self::FloatPh => Some("pH"),
+            Self::FloatMicrosiemensPerCentimeter => Some("µS/cm"),
+            Self::FloatPartsPerMillion => Some("ppm"),
+            Self::FloatHectopascal => Some("hPa"),
+            Self::FloatCentimeters => Some("cm"),
+            Self::FloatLiters => Some("L"),
+            Self::FloatVolts => Some("V"),
+            Self::Boolean => None,
+        }
+    }
+
+    /// JSON type the device must send
+    pub fn json_type(&self) -> &'static str {
+        match self {
+            Self::RawAnalogRead => "i64",
+            Self::Boolean => "bool",
+            _ => "f64",
+        }
+    }
+
+    /// Numeric value of a reading, `None` if it doesn't match `json_type`
+    pub fn parse(&self, value: &serde_json::Value) -> Option<f64> {
+        match self {
+            Self::RawAnalogRead => value.as_i64().map(|v| v as f64),
+            Self::Boolean => value.as_bool().map(|v| if v { 1. } else { 0. }),
+            _ => value.as_f64(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MeasurementRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl MeasurementRange {
+    pub fn new(min: f64, max: f64) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+impl std::fmt::Display for MeasurementRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.min, self.max)
+    }
 }
 
 #[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -24,6 +119,9 @@ pub struct SensorMeasurementView {
     ty: SensorMeasurementType,
     kind: SensorMeasurementKind,
     color: String,
+    // Events stored before units existed don't have it
+    #[serde(default)]
+    unit: Option<String>,
 }
 
 impl SensorMeasurementView {
@@ -31,6 +129,7 @@ impl SensorMeasurementView {
         Self {
             name: m.name,
             variable_name,
+            unit: m.ty.unit().map(ToOwned::to_owned),
             ty: m.ty,
             kind: m.kind,
             color,
@@ -38,11 +137,25 @@ impl SensorMeasurementView {
     }
 }
 
-#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(sqlx::FromRow, Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SensorMeasurement {
     pub name: String,
     pub variable_name: String,
     pub value: String,
     pub ty: SensorMeasurementType,
     pub kind: SensorMeasurementKind,
+    // Overrides the type's range, for sensors that can't cover all of it
+    #[serde(default)]
+    #[copy]
+    pub min: Option<f64>,
+    #[serde(default)]
+    #[copy]
+    pub max: Option<f64>,
+}
+
+impl SensorMeasurement {
+    pub fn range(&self) -> MeasurementRange {
+        let range = self.ty.range();
+        MeasurementRange::new(self.min.unwrap_or(range.min), self.max.unwrap_or(range.max))
+    }
 }
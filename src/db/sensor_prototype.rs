@@ -1,14 +1,16 @@
 use crate::{
-    AuthenticatedAction, Definition, Dependency, Include, NewDependency, NewSensorConfigRequest,
-    Result, SensorConfigRequest, SensorConfigRequestView, SensorMeasurement,
-    SensorPrototypeDefinitionId, Setup, Target, Transaction, UnauthenticatedAction,
+    AuthenticatedAction, CommandArgument, Definition, Dependency, Include, NewDependency,
+    NewSensorConfigRequest, Result, SensorCommand, SensorConfigRequest, SensorConfigRequestView,
+    SensorMeasurement, SensorPrototypeDefinitionId, Setup, Target, Transaction,
+    UnauthenticatedAction,
 };
 use derive::id;
 use derive_get::Getters;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use std::convert::TryFrom;
 
-#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[derive(Getters, Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct SensorPrototypeView {
     #[copy]
@@ -21,6 +23,7 @@ pub struct SensorPrototypeView {
     authenticated_actions: Vec<AuthenticatedAction>,
     unauthenticated_actions: Vec<UnauthenticatedAction>,
     measurements: Vec<SensorMeasurement>,
+    commands: Vec<SensorCommand>,
     configuration_requests: Vec<SensorConfigRequestView>,
     variable_name: Option<String>,
 }
@@ -48,6 +51,7 @@ impl SensorPrototypeView {
             authenticated_actions: prototype.authenticated_actions(txn).await?,
             unauthenticated_actions: prototype.unauthenticated_actions(txn).await?,
             measurements: prototype.measurements(txn).await?,
+            commands: prototype.commands(txn).await?,
             configuration_requests: configuration_requests_view,
         })
     }
@@ -81,6 +85,8 @@ pub struct NewSensorPrototype {
     unauthenticated_actions: Vec<UnauthenticatedAction>,
     #[serde(default)]
     measurements: Vec<SensorMeasurement>,
+    #[serde(default)]
+    commands: Vec<SensorCommand>,
     config_requests: Vec<NewSensorConfigRequest>,
 }
 
@@ -107,6 +113,20 @@ impl SensorPrototype {
 
         if let Some(sensor) = maybe {
             // TODO: support changing sensor prototypes
+
+            // Ranges don't affect the generated code, so they can be tuned in place
+            for measurement in &prototype.measurements {
+                sqlx::query(
+                    "UPDATE sensor_prototype_measurements SET min = $1, max = $2 WHERE sensor_prototype_id = $3 AND variable_name = $4",
+                )
+                .bind(measurement.min())
+                .bind(measurement.max())
+                .bind(sensor.id)
+                .bind(measurement.variable_name())
+                .execute(&mut *txn)
+                .await?;
+            }
+            sensor.set_commands(txn, &prototype.commands).await?;
             return Ok(sensor);
         }
 
@@ -191,7 +211,7 @@ impl SensorPrototype {
         }
         for measurement in &prototype.measurements {
             sqlx::query(
-                "INSERT INTO sensor_prototype_measurements (variable_name, value, ty, sensor_prototype_id, name, kind) VALUES ($1, $2, $3, $4, $5, $6)",
+                "INSERT INTO sensor_prototype_measurements (variable_name, value, ty, sensor_prototype_id, name, kind, min, max) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
             )
             .bind(measurement.variable_name())
             .bind(measurement.value())
@@ -199,9 +219,14 @@ impl SensorPrototype {
             .bind(id)
             .bind(measurement.name())
             .bind(measurement.kind())
+            .bind(measurement.min())
+            .bind(measurement.max())
             .execute(&mut *txn)
             .await?;
         }
+        sensor_prototype
+            .set_commands(txn, &prototype.commands)
+            .await?;
         for config_request in prototype.config_requests {
             SensorConfigRequest::new(
                 &mut *txn,
@@ -305,7 +330,7 @@ impl SensorPrototype {
     /// A sensor should execute N measurements and store them in the JSON
     pub async fn measurements(&self, txn: &mut Transaction<'_>) -> Result<Vec<SensorMeasurement>> {
         let list = sqlx::query_as(
-            "SELECT name, variable_name, value, ty, kind FROM sensor_prototype_measurements WHERE sensor_prototype_id = $1 ORDER BY id ASC",
+            "SELECT name, variable_name, value, ty, kind, min, max FROM sensor_prototype_measurements WHERE sensor_prototype_id = $1 ORDER BY id ASC",
         )
         .bind(self.id)
         .fetch_all(&mut *txn)
@@ -313,6 +338,51 @@ impl SensorPrototype {
         Ok(list)
     }
 
+    /// A sensor may accept commands from the user, delivered with the event responses
+    pub async fn commands(&self, txn: &mut Transaction<'_>) -> Result<Vec<SensorCommand>> {
+        let list: Vec<(String, String, Json<Vec<CommandArgument>>)> = sqlx::query_as(
+            "SELECT name, variable_name, arguments FROM sensor_prototype_commands WHERE sensor_prototype_id = $1 ORDER BY id ASC",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *txn)
+        .await?;
+        Ok(list
+            .into_iter()
+            .map(|(name, variable_name, Json(arguments))| SensorCommand {
+                name,
+                variable_name,
+                arguments,
+            })
+            .collect())
+    }
+
+    /// Commands don't affect the generated code, so they are replaced in place
+    async fn set_commands(
+        &self,
+        txn: &mut Transaction<'_>,
+        commands: &[SensorCommand],
+    ) -> Result<()> {
+        for command in commands {
+            command.validate()?;
+        }
+        sqlx::query("DELETE FROM sensor_prototype_commands WHERE sensor_prototype_id = $1")
+            .bind(self.id)
+            .execute(&mut *txn)
+            .await?;
+        for command in commands {
+            sqlx::query(
+                "INSERT INTO sensor_prototype_commands (sensor_prototype_id, name, variable_name, arguments) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(self.id)
+            .bind(command.name())
+            .bind(command.variable_name())
+            .bind(Json(command.arguments()))
+            .execute(&mut *txn)
+            .await?;
+        }
+        Ok(())
+    }
+
     /// A sensor should require 0-N configuration variables to be defined by the user
     pub async fn configuration_requests(
         &self,
@@ -1,5 +1,6 @@
 use crate::{
-    CompilerId, NewSensor, SensorPrototypeId, SensorWidgetKindView, TargetPrototypeId, ValRaw,
+    CompilerId, DateTime, DeviceCommandId, NewSensor, SensorPrototypeId, SensorWidgetKindView,
+    TargetPrototypeId, ValRaw,
 };
 use axum::response::{IntoResponse, Response};
 use axum::{http::StatusCode, Json};
@@ -44,12 +45,6 @@ pub enum Error {
     Git2(#[from] git2::Error),
     #[error("event must be object")]
     EventMustBeObject,
-    #[error("measurement missing")]
-    MeasurementMissing,
-    #[error("value: {0} range: {1}")]
-    MeasurementOutOfRange(String, String),
-    #[error("type: {0:?} expected: {1}")]
-    InvalidMeasurementType(serde_json::Value, String),
     #[error("missing measurement {0}")]
     MissingMeasurement(String),
     #[error("duplicated config")]
@@ -68,12 +63,48 @@ pub enum Error {
     NoBinaryAvailable,
     #[error("no update available")]
     NoUpdateAvailable,
+    #[error("binary of {0} bytes doesn't fit in {1} bytes")]
+    NotEnoughSpace(u64, u64),
+    #[error("outside maintenance window")]
+    OutsideMaintenanceWindow,
+    #[error("invalid weekday {0}")]
+    InvalidWeekday(u8),
+    #[error("invalid aggregation {0}")]
+    InvalidAggregation(String),
     #[error("asked for too many")]
     AskedForTooMany,
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
+    #[error("event measured at {0} is too far from server time {1}")]
+    ClockSkew(DateTime, DateTime),
+    #[error("batch of {0} events is too big")]
+    EventBatchTooBig(usize),
+    #[error("invalid calibration: {0}")]
+    InvalidCalibration(String),
+    #[error("invalid retention policy: {0}")]
+    InvalidRetentionPolicy(String),
+    #[error("unsupported event schema version {0}")]
+    UnsupportedSchemaVersion(u32),
+    #[error("invalid alert rule: {0}")]
+    InvalidAlertRule(String),
+    #[error("invalid notification channel: {0}")]
+    InvalidNotificationChannel(String),
+    #[error("invalid device command: {0}")]
+    InvalidDeviceCommand(String),
+    #[error("device command {0:?} is not pending")]
+    DeviceCommandNotPending(DeviceCommandId),
+    #[error("device command {0:?} was not delivered")]
+    DeviceCommandNotDelivered(DeviceCommandId),
+    #[error("invalid mqtt message: {0}")]
+    InvalidMqttMessage(String),
     #[error("corrupted binary")]
     CorruptedBinary,
     #[error("missing binary")]
     MissingBinary,
+    #[error("invalid firmware signing key at {0}")]
+    InvalidSigningKey(String),
+    #[error("FIRMWARE_SIGNING_KEY is not set")]
+    MissingSigningKey,
     #[error("nothing found")]
     NothingFound,
     #[error("no collection for compiler: {0}")]
@@ -176,6 +207,14 @@ impl IntoResponse for Error {
                 error!("{:?} {}", error, error);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
             }
+            Self::InvalidSigningKey(path) => {
+                error!("Invalid Signing Key: {path}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+            }
+            Self::MissingSigningKey => {
+                error!("Missing Signing Key");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
+            }
             Self::NewSensorReferencedDoesntExist(pk, list) => {
                 error!("Unable to find sensor {} in {:?}", pk, list);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error")
@@ -200,14 +239,6 @@ impl IntoResponse for Error {
                 warn!("Event Must Be Object");
                 (StatusCode::BAD_REQUEST, "Event Must Be Object")
             }
-            Self::MeasurementOutOfRange(val, range) => {
-                warn!("Measurement Out of Range: {val} expected {range}");
-                (StatusCode::BAD_REQUEST, "Measurement Out of Range")
-            }
-            Self::InvalidMeasurementType(json, ty) => {
-                warn!("Invalid Measurement Type: {json:?} expected {ty}");
-                (StatusCode::BAD_REQUEST, "Invalid Measurement Type")
-            }
             Self::DuplicatedConfig => {
                 warn!("Duplicated Config");
                 (StatusCode::BAD_REQUEST, "Duplicated Config")
@@ -216,10 +247,6 @@ impl IntoResponse for Error {
                 warn!("Duplicated Key");
                 (StatusCode::BAD_REQUEST, "Duplicated Key")
             }
-            Self::MeasurementMissing => {
-                warn!("Measurement Missing");
-                (StatusCode::BAD_REQUEST, "Measurement Missing")
-            }
             Self::NoCollectionForCompiler(id) => {
                 warn!("No Collection For Compiler: {id:?}");
                 (StatusCode::BAD_REQUEST, "No Collection For Compiler")
@@ -248,14 +275,78 @@ impl IntoResponse for Error {
                 warn!("No Update Available");
                 (StatusCode::BAD_REQUEST, "No Update Available")
             }
+            Self::NotEnoughSpace(size, free_space) => {
+                warn!("Not Enough Space: binary has {size} bytes but device has {free_space} free");
+                (StatusCode::BAD_REQUEST, "Not Enough Space")
+            }
+            Self::OutsideMaintenanceWindow => {
+                warn!("Outside Maintenance Window");
+                (StatusCode::BAD_REQUEST, "Outside Maintenance Window")
+            }
+            Self::InvalidWeekday(weekday) => {
+                warn!("Invalid Weekday: {weekday}");
+                (StatusCode::BAD_REQUEST, "Invalid Weekday")
+            }
             Self::CorruptedBinary => {
                 warn!("Corrupted Binary");
                 (StatusCode::BAD_REQUEST, "Corrupted Binary")
             }
+            Self::InvalidAggregation(aggregation) => {
+                warn!("Invalid Aggregation: {aggregation}");
+                (StatusCode::BAD_REQUEST, "Invalid Aggregation")
+            }
             Self::AskedForTooMany => {
                 warn!("Asked For Too Many");
                 (StatusCode::BAD_REQUEST, "Asked For Too Many")
             }
+            Self::InvalidCursor(cursor) => {
+                warn!("Invalid Cursor: {cursor}");
+                (StatusCode::BAD_REQUEST, "Invalid Cursor")
+            }
+            Self::ClockSkew(measured_at, now) => {
+                warn!("Clock Skew: measured at {measured_at}, now is {now}");
+                (StatusCode::BAD_REQUEST, "Clock Skew")
+            }
+            Self::EventBatchTooBig(size) => {
+                warn!("Event Batch Too Big: {size}");
+                (StatusCode::BAD_REQUEST, "Event Batch Too Big")
+            }
+            Self::InvalidCalibration(reason) => {
+                warn!("Invalid Calibration: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Calibration")
+            }
+            Self::InvalidRetentionPolicy(reason) => {
+                warn!("Invalid Retention Policy: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Retention Policy")
+            }
+            Self::UnsupportedSchemaVersion(version) => {
+                warn!("Unsupported Schema Version: {version}");
+                (StatusCode::BAD_REQUEST, "Unsupported Schema Version")
+            }
+            Self::InvalidAlertRule(reason) => {
+                warn!("Invalid Alert Rule: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Alert Rule")
+            }
+            Self::InvalidNotificationChannel(reason) => {
+                warn!("Invalid Notification Channel: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Notification Channel")
+            }
+            Self::InvalidDeviceCommand(reason) => {
+                warn!("Invalid Device Command: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Device Command")
+            }
+            Self::DeviceCommandNotPending(id) => {
+                warn!("Device Command Not Pending: {id:?}");
+                (StatusCode::BAD_REQUEST, "Device Command Not Pending")
+            }
+            Self::DeviceCommandNotDelivered(id) => {
+                warn!("Device Command Not Delivered: {id:?}");
+                (StatusCode::BAD_REQUEST, "Device Command Not Delivered")
+            }
+            Self::InvalidMqttMessage(reason) => {
+                warn!("Invalid Mqtt Message: {reason}");
+                (StatusCode::BAD_REQUEST, "Invalid Mqtt Message")
+            }
             Self::InvalidTimezone(err, tz) => {
                 warn!("Invalid Timezone {tz}: {err}");
                 (StatusCode::BAD_REQUEST, "Invalid Timezone")
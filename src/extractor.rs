@@ -1,9 +1,12 @@
-use crate::{AuthToken, Pool};
+use crate::{logger::*, AuthToken, Pool};
 use axum::{
     async_trait,
+    body::{Bytes, HttpBody},
     extract::{Extension, FromRequest, RequestParts, TypedHeader},
-    http::StatusCode,
+    http::{header::CONTENT_TYPE, StatusCode},
+    BoxError,
 };
+use serde::de::DeserializeOwned;
 
 pub struct Device(pub super::Device);
 
@@ -22,15 +25,20 @@ where
         let mut token = TypedHeader::<AuthorizationHeader>::from_request(req)
             .await
             .map_err(|_| (StatusCode::UNAUTHORIZED, "No auth token"))?;
-        let mac = match TypedHeader::<MacAddress>::from_request(req).await {
-            Ok(mac) => mac.0 .0,
-            Err(_) => {
-                TypedHeader::<Esp8266StaMac>::from_request(req)
-                    .await
-                    .map_err(|_| (StatusCode::UNAUTHORIZED, "No mac address"))?
-                    .0
-                     .0
-            }
+        let mac = if let Ok(TypedHeader(MacAddress(mac))) =
+            TypedHeader::<MacAddress>::from_request(req).await
+        {
+            mac
+        } else if let Ok(TypedHeader(Esp8266StaMac(mac))) =
+            TypedHeader::<Esp8266StaMac>::from_request(req).await
+        {
+            mac
+        } else {
+            TypedHeader::<Esp32StaMac>::from_request(req)
+                .await
+                .map_err(|_| (StatusCode::UNAUTHORIZED, "No mac address"))?
+                .0
+                 .0
         };
         if token.0 .0.starts_with("Basic ") {
             token.0 .0.drain(.."Basic ".len());
@@ -88,6 +96,233 @@ where
     }
 }
 
+/// Headers sent by the ESP8266 and ESP32 http updaters
+#[derive(Debug)]
+pub struct Ota {
+    pub md5: String,
+    pub free_space: Option<u64>,
+    pub chip_size: Option<u64>,
+    pub sdk_version: Option<String>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Ota
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if let Ok(TypedHeader(Esp8266Md5(md5))) = TypedHeader::<Esp8266Md5>::from_request(req).await
+        {
+            let free_space = TypedHeader::<Esp8266FreeSpace>::from_request(req)
+                .await
+                .ok()
+                .and_then(|TypedHeader(Esp8266FreeSpace(space))| space.parse().ok());
+            let chip_size = TypedHeader::<Esp8266ChipSize>::from_request(req)
+                .await
+                .ok()
+                .and_then(|TypedHeader(Esp8266ChipSize(size))| size.parse().ok());
+            let sdk_version = TypedHeader::<Esp8266SdkVersion>::from_request(req)
+                .await
+                .ok()
+                .map(|TypedHeader(Esp8266SdkVersion(version))| version);
+            Ok(Self {
+                md5,
+                free_space,
+                chip_size,
+                sdk_version,
+            })
+        } else if let Ok(TypedHeader(Esp32Md5(md5))) =
+            TypedHeader::<Esp32Md5>::from_request(req).await
+        {
+            let free_space = TypedHeader::<Esp32FreeSpace>::from_request(req)
+                .await
+                .ok()
+                .and_then(|TypedHeader(Esp32FreeSpace(space))| space.parse().ok());
+            let chip_size = TypedHeader::<Esp32ChipSize>::from_request(req)
+                .await
+                .ok()
+                .and_then(|TypedHeader(Esp32ChipSize(size))| size.parse().ok());
+            let sdk_version = TypedHeader::<Esp32SdkVersion>::from_request(req)
+                .await
+                .ok()
+                .map(|TypedHeader(Esp32SdkVersion(version))| version);
+            Ok(Self {
+                md5,
+                free_space,
+                chip_size,
+                sdk_version,
+            })
+        } else {
+            Err((StatusCode::BAD_REQUEST, "No sketch md5"))
+        }
+    }
+}
+
+/// Device health reported in the headers of every event
+pub struct Stat(pub super::DeviceStat);
+
+#[async_trait]
+impl<B> FromRequest<B> for Stat
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let invalid = |_| (StatusCode::BAD_REQUEST, "Invalid device stat");
+        let TypedHeader(Version(version)) = TypedHeader::<Version>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "No version"))?;
+        let TypedHeader(TimeRunning(time_running)) = TypedHeader::<TimeRunning>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "No time running"))?;
+        let TypedHeader(Vcc(vcc)) = TypedHeader::<Vcc>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "No vcc"))?;
+        let TypedHeader(FreeStack(free_stack)) = TypedHeader::<FreeStack>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "No free stack"))?;
+        let TypedHeader(FreeDram(free_dram)) = TypedHeader::<FreeDram>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "No free dram"))?;
+        let TypedHeader(BiggestDramBlock(biggest_dram_block)) =
+            TypedHeader::<BiggestDramBlock>::from_request(req)
+                .await
+                .map_err(|_| (StatusCode::BAD_REQUEST, "No biggest dram block"))?;
+        let free_iram = TypedHeader::<FreeIram>::from_request(req)
+            .await
+            .ok()
+            .and_then(|TypedHeader(FreeIram(iram))| iram.parse().ok());
+        let biggest_iram_block = TypedHeader::<BiggestIramBlock>::from_request(req)
+            .await
+            .ok()
+            .and_then(|TypedHeader(BiggestIramBlock(size))| size.parse().ok());
+
+        Ok(Self(super::DeviceStat {
+            version: version.to_lowercase(),
+            time_running: time_running.parse().map_err(invalid)?,
+            vcc: vcc.parse().map_err(invalid)?,
+            free_dram: free_dram.parse().map_err(invalid)?,
+            free_iram,
+            free_stack: free_stack.parse().map_err(invalid)?,
+            biggest_dram_block: biggest_dram_block.parse().map_err(invalid)?,
+            biggest_iram_block,
+            extra: Default::default(),
+        }))
+    }
+}
+
+/// Encodings devices may send their bodies in, the compact ones save RAM and bandwidth on the device
+pub const BODY_CONTENT_TYPES: [&str; 3] = [
+    "application/json",
+    "application/cbor",
+    "application/msgpack",
+];
+
+pub(crate) enum Encoding {
+    /// Neither CBOR nor MessagePack, what it actually is depends on the extractor
+    Default,
+    Cbor,
+    MessagePack,
+}
+
+impl Encoding {
+    /// Names used where there is no `Content-Type`, like MQTT topics
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Default),
+            "cbor" => Some(Self::Cbor),
+            "msgpack" => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// None if the `Content-Type` isn't one of the supported encodings, a missing one is the default
+    fn from_request<B>(req: &RequestParts<B>) -> Option<Self> {
+        let content_type = match req.headers().get(CONTENT_TYPE) {
+            Some(content_type) => content_type.to_str().ok()?,
+            None => return Some(Self::Default),
+        };
+        let mime: mime::Mime = content_type.parse().ok()?;
+        match (mime.type_(), mime.subtype().as_str()) {
+            (mime::APPLICATION, "cbor") => Some(Self::Cbor),
+            (mime::APPLICATION, "msgpack" | "x-msgpack" | "vnd.msgpack") => Some(Self::MessagePack),
+            (mime::APPLICATION, "json") | (mime::TEXT, "plain") => Some(Self::Default),
+            _ if mime.suffix() == Some(mime::JSON) => Some(Self::Default),
+            _ => None,
+        }
+    }
+
+    /// The error describes why the body couldn't be decoded, so it can be logged
+    pub(crate) fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Default => serde_json::from_slice(body).map_err(|err| err.to_string()),
+            Self::Cbor => ciborium::de::from_reader(body).map_err(|err| err.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(body).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Like `Json`, but decodes CBOR and MessagePack too, depending on the `Content-Type`
+pub struct Body<T>(pub T);
+
+#[async_trait]
+impl<B, T> FromRequest<B> for Body<T>
+where
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let encoding = Encoding::from_request(req).ok_or((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Unsupported content type",
+        ))?;
+        let body = Bytes::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Unable to read body"))?;
+        let value = encoding.decode(&body).map_err(|err| {
+            warn!("Invalid Body: {err}");
+            (StatusCode::BAD_REQUEST, "Invalid body")
+        })?;
+        Ok(Self(value))
+    }
+}
+
+/// Plain text body, or a single string encoded as CBOR or MessagePack
+pub struct Text(pub String);
+
+#[async_trait]
+impl<B> FromRequest<B> for Text
+where
+    B: HttpBody + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        // Logs used to be sent without caring about the content type, so anything else is plain text
+        let encoding = Encoding::from_request(req).unwrap_or(Encoding::Default);
+        let body = Bytes::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Unable to read body"))?;
+        let text = match encoding {
+            Encoding::Default => String::from_utf8_lossy(&body).into_owned(),
+            _ => encoding.decode(&body).map_err(|err| {
+                warn!("Invalid Body: {err}");
+                (StatusCode::BAD_REQUEST, "Invalid body")
+            })?,
+        };
+        Ok(Self(text))
+    }
+}
+
 pub struct MaybeTargetPrototype(pub Option<super::TargetPrototype>);
 
 #[async_trait]
@@ -508,3 +743,227 @@ impl headers_core::Header for BiggestIramBlock {
         }
     }
 }
+
+#[derive(Debug)]
+pub struct Esp8266FreeSpace(pub String);
+
+const ESP8266_FREE_SPACE_NAME: &str = "x-esp8266-free-space";
+impl headers_core::Header for Esp8266FreeSpace {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP8266_FREE_SPACE_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp8266ChipSize(pub String);
+
+const ESP8266_CHIP_SIZE_NAME: &str = "x-esp8266-chip-size";
+impl headers_core::Header for Esp8266ChipSize {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP8266_CHIP_SIZE_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp8266SdkVersion(pub String);
+
+const ESP8266_SDK_VERSION_NAME: &str = "x-esp8266-sdk-version";
+impl headers_core::Header for Esp8266SdkVersion {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP8266_SDK_VERSION_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp32StaMac(pub String);
+
+const ESP32_STA_MAC_NAME: &str = "x-esp32-sta-mac";
+impl headers_core::Header for Esp32StaMac {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP32_STA_MAC_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp32Md5(pub String);
+
+const ESP32_MD5_NAME: &str = "x-esp32-sketch-md5";
+impl headers_core::Header for Esp32Md5 {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP32_MD5_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp32FreeSpace(pub String);
+
+const ESP32_FREE_SPACE_NAME: &str = "x-esp32-free-space";
+impl headers_core::Header for Esp32FreeSpace {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP32_FREE_SPACE_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp32ChipSize(pub String);
+
+const ESP32_CHIP_SIZE_NAME: &str = "x-esp32-chip-size";
+impl headers_core::Header for Esp32ChipSize {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP32_CHIP_SIZE_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Esp32SdkVersion(pub String);
+
+const ESP32_SDK_VERSION_NAME: &str = "x-esp32-sdk-version";
+impl headers_core::Header for Esp32SdkVersion {
+    fn name() -> &'static headers_core::HeaderName {
+        thread_local! {
+            static NAME: &'static headers_core::HeaderName = Box::leak(headers_core::HeaderName::from_static(ESP32_SDK_VERSION_NAME).into());
+        }
+        NAME.with(|n| *n)
+    }
+
+    fn decode<'i, I: Iterator<Item = &'i headers_core::HeaderValue>>(
+        values: &mut I,
+    ) -> Result<Self, headers_core::Error> {
+        values
+            .next()
+            .and_then(|val| val.to_str().ok().map(|v| Self(v.to_owned())))
+            .ok_or_else(headers_core::Error::invalid)
+    }
+
+    fn encode<E: Extend<headers_core::HeaderValue>>(&self, values: &mut E) {
+        if let Ok(name) = headers_core::HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(name));
+        }
+    }
+}
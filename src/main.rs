@@ -7,7 +7,13 @@ use std::path::PathBuf;
 #[cfg(not(debug_assertions))]
 use axum_server::tls_rustls::RustlsConfig;
 
-use server::{logger::*, router, Certificate, Compilation, Pool, Result, TargetPrototype};
+use rumqttc::MqttOptions;
+use server::{
+    logger::*, mqtt, router, utils, Activity, Certificate, Compilation, DeviceCommand,
+    DeviceConnectivity, GarbageCollection, Mailer, MeasurementRollup, Notification, Pool, Result,
+    RetentionPolicy, RollupResolution, SensorCalibration, TargetPrototype,
+    KEEP_LATEST_COMPILATIONS, MEASUREMENTS_INTERVAL_SECONDS, RETENTION_BATCH_SIZE,
+};
 use tracing_subscriber::{prelude::*, EnvFilter};
 
 #[tokio::main]
@@ -44,10 +50,28 @@ async fn main() {
         .expect("Unable to connect to database");
     let pool: &'static Pool = Box::leak(pool.into());
 
-    let router = router(pool).await;
+    // Firmwares are signed on demand, a missing key must stop the deploy instead of the first compilation
+    utils::signing_key()
+        .await
+        .expect("unable to load firmware signing key");
+
+    let router = router(pool, Activity::listen(pool)).await;
 
     tokio::task::spawn(update_compilations(pool));
     tokio::task::spawn(recompile(pool));
+    tokio::task::spawn(collect_garbage(pool));
+    tokio::task::spawn(recalibrate_measurements(pool));
+    tokio::task::spawn(rollup_measurements(pool));
+    tokio::task::spawn(enforce_retention(pool));
+    tokio::task::spawn(deliver_notifications(pool));
+    tokio::task::spawn(detect_offline_devices(pool));
+    tokio::task::spawn(expire_device_commands(pool));
+
+    // Optional, HTTP is always available
+    if let Ok(url) = std::env::var("MQTT_URL") {
+        let options = MqttOptions::parse_url(url).expect("invalid MQTT_URL");
+        tokio::task::spawn(mqtt::gateway(pool, options));
+    }
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 4001));
 
@@ -176,6 +200,125 @@ async fn recompile_each(
     Ok(())
 }
 
+async fn collect_garbage(pool: &'static Pool) {
+    loop {
+        wrap_panic("collect garbage".to_owned(), collect_garbage_tick(pool)).await;
+        tokio::time::sleep(Duration::from_secs(3600 * 24)).await;
+    }
+}
+
+async fn collect_garbage_tick(pool: &'static Pool) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    let garbage = GarbageCollection::find(&mut txn, None, KEEP_LATEST_COMPILATIONS).await?;
+    garbage.execute(&mut txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+async fn recalibrate_measurements(pool: &'static Pool) {
+    loop {
+        wrap_panic(
+            "recalibrate measurements".to_owned(),
+            recalibrate_measurements_tick(pool),
+        )
+        .await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+async fn recalibrate_measurements_tick(pool: &'static Pool) -> Result<()> {
+    loop {
+        let mut txn = pool.begin().await?;
+        let recalibrated = SensorCalibration::recompute_next(&mut txn).await?;
+        txn.commit().await?;
+        if !recalibrated {
+            return Ok(());
+        }
+    }
+}
+
+async fn rollup_measurements(pool: &'static Pool) {
+    loop {
+        for resolution in [RollupResolution::Hour, RollupResolution::Day] {
+            wrap_panic(
+                format!("rollup measurements ({resolution:?})"),
+                rollup_measurements_each(pool, resolution),
+            )
+            .await;
+        }
+        tokio::time::sleep(Duration::from_secs(300)).await;
+    }
+}
+
+async fn rollup_measurements_each(pool: &'static Pool, resolution: RollupResolution) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    MeasurementRollup::update(&mut txn, resolution).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+async fn enforce_retention(pool: &'static Pool) {
+    loop {
+        wrap_panic("enforce retention".to_owned(), enforce_retention_tick(pool)).await;
+        tokio::time::sleep(Duration::from_secs(3600 * 24)).await;
+    }
+}
+
+async fn enforce_retention_tick(pool: &'static Pool) -> Result<()> {
+    RetentionPolicy::enforce(pool, RETENTION_BATCH_SIZE).await
+}
+
+async fn deliver_notifications(pool: &'static Pool) {
+    let mailer = Mailer::from_env();
+    loop {
+        wrap_panic(
+            "deliver notifications".to_owned(),
+            Notification::deliver(pool, &mailer),
+        )
+        .await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+async fn detect_offline_devices(pool: &'static Pool) {
+    loop {
+        wrap_panic(
+            "detect offline devices".to_owned(),
+            detect_offline_devices_tick(pool),
+        )
+        .await;
+        tokio::time::sleep(Duration::from_secs(MEASUREMENTS_INTERVAL_SECONDS as u64)).await;
+    }
+}
+
+async fn detect_offline_devices_tick(pool: &'static Pool) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    DeviceConnectivity::detect(&mut txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+async fn expire_device_commands(pool: &'static Pool) {
+    loop {
+        wrap_panic(
+            "expire device commands".to_owned(),
+            expire_device_commands_tick(pool),
+        )
+        .await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+}
+
+async fn expire_device_commands_tick(pool: &'static Pool) -> Result<()> {
+    let mut txn = pool.begin().await?;
+    let expired = DeviceCommand::expire(&mut txn).await?;
+    txn.commit().await?;
+    if expired > 0 {
+        info!("Expired {expired} device commands");
+    }
+    Ok(())
+}
+
 async fn wrap_panic<F: Future<Output = Result<()>>>(label: String, future: F) {
     match AssertUnwindSafe(future).catch_unwind().await {
         Ok(Ok(())) => {}
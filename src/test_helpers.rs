@@ -5,11 +5,14 @@ use crate::{
     },
     extractor::MacAddress,
     extractor::Version,
-    AuthToken, CollectionId, CollectionView, CompilationView, DeviceId, DeviceLogView,
-    DevicePanicView, DeviceView, Login, NewCompiler, NewDevicePanic, NewUser, OrganizationId,
-    OrganizationView, SensorPrototypeView, TargetId, TargetView,
+    utils, AuthToken, CollectionId, CollectionView, CompilationView, CompilerId, DeviceId,
+    DeviceLogView, DevicePanicView, DeviceView, Login, NewCompiler, NewDevicePanic, NewUser,
+    OrganizationId, OrganizationView, Page, Pool, SensorId, SensorPrototypeView, TargetId,
+    TargetView, EVENT_SCHEMA_VERSION,
+};
+use axum::{
+    body::Body, http, http::HeaderMap, http::Method, http::Request, http::StatusCode, Router,
 };
-use axum::{body::Body, http, http::Method, http::Request, http::StatusCode, Router};
 use tower::ServiceExt;
 
 pub async fn signup(app: Router, new_user: NewUser) -> AuthToken {
@@ -53,7 +56,9 @@ pub async fn login(
         request
     };
     let request = if let Some(mac) = mac_address {
-        request.header("MAC_ADDRESS", mac)
+        request
+            .header("MAC_ADDRESS", mac)
+            .header("DRIVER", "esp8266")
     } else {
         request
     };
@@ -196,7 +201,7 @@ pub async fn list_device_logs(
     app: Router,
     token: &AuthToken,
     device_id: DeviceId,
-) -> Vec<DeviceLogView> {
+) -> Page<DeviceLogView> {
     let response = app
         .oneshot(
             Request::builder()
@@ -244,7 +249,7 @@ pub async fn list_device_panics(
     app: Router,
     token: &AuthToken,
     device_id: DeviceId,
-) -> Vec<DevicePanicView> {
+) -> Page<DevicePanicView> {
     let response = app
         .oneshot(
             Request::builder()
@@ -457,3 +462,296 @@ pub async fn set_sensor_color(app: Router, token: &AuthToken, request: SetColorR
         .unwrap();
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+pub fn new_user(name: &str) -> NewUser {
+    serde_json::from_value(serde_json::json!({
+        "email": format!("{name}@example.com"),
+        "password": format!("{name}1234"),
+        "username": name,
+        "organizationName": name,
+    }))
+    .unwrap()
+}
+
+/// Organization with a single esp8266 device, everything is named after `name` so tests don't collide
+pub struct TestDevice {
+    pub token: AuthToken,
+    pub device_token: AuthToken,
+    pub mac: String,
+    pub version: String,
+    pub organization_id: OrganizationId,
+    pub collection_id: CollectionId,
+    pub device_id: DeviceId,
+}
+
+pub async fn setup_device(app: Router, name: &str) -> TestDevice {
+    let token = signup(app.clone(), new_user(name)).await;
+    add_device(app, token, name, name).await
+}
+
+/// Logs in another device to `organization`, its mac and version are derived from `name`
+pub async fn add_device(
+    app: Router,
+    token: AuthToken,
+    organization: &str,
+    name: &str,
+) -> TestDevice {
+    let hash = md5::compute(name);
+    let mac = hash[..6]
+        .iter()
+        .map(|byte| utils::hex(&[*byte]))
+        .collect::<Vec<_>>()
+        .join(":");
+    let version = utils::hex(&*hash);
+    let device_token = login(
+        app.clone(),
+        Login {
+            organization: Some(organization.to_owned()),
+            email: format!("{organization}@example.com"),
+            password: format!("{organization}1234"),
+        },
+        Some(mac.clone()),
+        Some(version.clone()),
+    )
+    .await;
+
+    let organization = list_organizations(app, &token)
+        .await
+        .into_iter()
+        .find(|o| o.name() == organization)
+        .unwrap();
+    let (collection, device) = organization
+        .collections()
+        .iter()
+        .find_map(|c| {
+            c.devices()
+                .iter()
+                .find(|d| d.mac() == &mac)
+                .map(|d| (c.id(), d.id()))
+        })
+        .unwrap();
+    TestDevice {
+        token,
+        device_token,
+        mac,
+        version,
+        organization_id: organization.id(),
+        collection_id: collection,
+        device_id: device,
+    }
+}
+
+/// Compiling needs platformio, so the collection's compiler and its firmware are inserted directly
+///
+/// Returns the firmware's hash, it becomes the collection's update
+pub async fn seed_firmware(pool: &'static Pool, collection_id: CollectionId, bin: &[u8]) -> String {
+    let compiler_id = seed_compiler(pool, collection_id).await;
+    let mut txn = pool.begin().await.unwrap();
+    let (organization_id, target_prototype_id): (OrganizationId, i64) = sqlx::query_as(
+        "SELECT organization_id, target_prototype_id FROM compilers
+         INNER JOIN targets ON targets.id = compilers.target_id
+         WHERE compilers.id = $1",
+    )
+    .bind(compiler_id)
+    .fetch_one(&mut txn)
+    .await
+    .unwrap();
+    let (certificate_id,): (i64,) = sqlx::query_as(
+        "INSERT INTO certificates (target_prototype_id, hash, payload) VALUES ($1, 'test', '')
+         ON CONFLICT (target_prototype_id, hash) DO UPDATE SET hash = EXCLUDED.hash
+         RETURNING id",
+    )
+    .bind(target_prototype_id)
+    .fetch_one(&mut txn)
+    .await
+    .unwrap();
+    let binary_hash = utils::hex(&*md5::compute(bin));
+    sqlx::query(
+        "WITH compilation AS (
+             INSERT INTO compilations (compiler_id, platformio_ini, main_cpp, pin_hpp, certificate_id)
+             VALUES ($1, '', $2, '', $3)
+             RETURNING id
+         )
+         INSERT INTO firmwares (compilation_id, organization_id, bin, binary_hash, signature)
+         SELECT compilation.id, $4, $5, $2, $6 FROM compilation",
+    )
+    .bind(compiler_id)
+    .bind(&binary_hash)
+    .bind(certificate_id)
+    .bind(organization_id)
+    .bind(bin)
+    .bind(utils::sign(bin).await.unwrap())
+    .execute(&mut txn)
+    .await
+    .unwrap();
+    txn.commit().await.unwrap();
+    binary_hash
+}
+
+/// Inserts a compiler for the collection if it doesn't have one, returning it
+pub async fn seed_compiler(pool: &'static Pool, collection_id: CollectionId) -> CompilerId {
+    let mut txn = pool.begin().await.unwrap();
+    let (organization_id, target_prototype_id, compiler_id): (
+        OrganizationId,
+        i64,
+        Option<CompilerId>,
+    ) = sqlx::query_as(
+        "SELECT cbt.organization_id, c.target_prototype_id, c.compiler_id
+         FROM collections c
+         INNER JOIN collection_belongs_to_organization cbt ON cbt.collection_id = c.id
+         WHERE c.id = $1",
+    )
+    .bind(collection_id)
+    .fetch_one(&mut txn)
+    .await
+    .unwrap();
+    if let Some(compiler_id) = compiler_id {
+        return compiler_id;
+    }
+
+    let (compiler_id,): (CompilerId,) = sqlx::query_as(
+        "INSERT INTO compilers (organization_id, target_id)
+         SELECT $1, id FROM targets WHERE target_prototype_id = $2 ORDER BY id LIMIT 1
+         RETURNING id",
+    )
+    .bind(organization_id)
+    .bind(target_prototype_id)
+    .fetch_one(&mut txn)
+    .await
+    .unwrap();
+    sqlx::query("UPDATE collections SET compiler_id = $1 WHERE id = $2")
+        .bind(compiler_id)
+        .bind(collection_id)
+        .execute(&mut txn)
+        .await
+        .unwrap();
+    txn.commit().await.unwrap();
+    compiler_id
+}
+
+/// Adds a sensor of the prototype named `prototype` to the collection's compiler
+///
+/// Compilers always have a compilation, seed the firmware before ingesting events
+pub async fn seed_sensor(
+    pool: &'static Pool,
+    collection_id: CollectionId,
+    prototype: &str,
+    index: i64,
+) -> SensorId {
+    let compiler_id = seed_compiler(pool, collection_id).await;
+    let (sensor_id,): (SensorId,) = sqlx::query_as(
+        "WITH sensor AS (
+             INSERT INTO sensors (prototype_id, index)
+             SELECT id, $2 FROM sensor_prototypes WHERE name = $1
+             RETURNING id
+         )
+         INSERT INTO sensor_belongs_to_compiler (compiler_id, sensor_id, alias, color)
+         SELECT $3, sensor.id, '', '' FROM sensor
+         RETURNING sensor_id",
+    )
+    .bind(prototype)
+    .bind(index)
+    .bind(compiler_id)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    sensor_id
+}
+
+/// Sends `body` as JSON, returning the response's status, headers and body
+pub async fn request(
+    app: Router,
+    method: Method,
+    uri: &str,
+    token: &AuthToken,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let request = Request::builder()
+        .uri(uri)
+        .header("Authorization", format!("Basic {}", token))
+        .method(method);
+    let request = match body {
+        Some(body) => request
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_vec(&body).unwrap())),
+        None => request.body(Body::empty()),
+    };
+    let response = app.oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, body.to_vec())
+}
+
+/// Sends the events to `/v2/event` with the device's stat, `events` are `{ measuredAt?, measurements }`
+pub async fn send_events(
+    app: Router,
+    device: &TestDevice,
+    events: Vec<serde_json::Value>,
+) -> (StatusCode, HeaderMap) {
+    send_versioned_events(
+        app,
+        device,
+        serde_json::json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": events,
+        }),
+    )
+    .await
+}
+
+pub async fn send_versioned_events(
+    app: Router,
+    device: &TestDevice,
+    body: serde_json::Value,
+) -> (StatusCode, HeaderMap) {
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/v2/event")
+                .header("Authorization", format!("Basic {}", device.device_token))
+                .header("MAC_ADDRESS", &device.mac)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .method(Method::POST)
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    (response.status(), response.headers().clone())
+}
+
+pub fn stat(version: &str) -> serde_json::Value {
+    serde_json::json!({
+        "version": version,
+        "timeRunning": 1,
+        "vcc": 1,
+        "freeDram": 10000,
+        "freeStack": 10000,
+        "biggestDramBlock": 10000,
+    })
+}
+
+/// Requests `/v1/update` as the device's http updater, `headers` are the updater's (sketch md5, free space...)
+pub async fn download_update(
+    app: Router,
+    device: &TestDevice,
+    headers: &[(&str, &str)],
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut request = Request::builder()
+        .uri("/v1/update")
+        .header("Authorization", format!("Basic {}", device.device_token))
+        .method(Method::GET);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, body.to_vec())
+}
@@ -1,9 +1,14 @@
-use std::{fmt::Write, path::Path, path::PathBuf};
+use std::{convert::TryInto, fmt::Write, path::Path, path::PathBuf};
 
-use crate::{logger::*, Pool, Result};
+use crate::{logger::*, Error, Pool, Result};
 use derive_get::Getters;
+use ed25519_dalek::{Signer, SigningKey};
+#[cfg(debug_assertions)]
+use rand::rngs::OsRng;
 use rand::{distributions::Alphanumeric, Rng};
-use tokio::fs;
+#[cfg(debug_assertions)]
+use tokio::io::AsyncWriteExt;
+use tokio::{fs, sync::OnceCell};
 
 #[derive(sqlx::FromRow, Getters, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Migration {
@@ -169,3 +174,76 @@ pub fn hash_password(password: &str) -> Result<String> {
 pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
     Ok(bcrypt::verify(password, hash)?)
 }
+
+static SIGNING_KEY: OnceCell<SigningKey> = OnceCell::const_new();
+
+/// Loads the ed25519 key used to sign firmwares from `FIRMWARE_SIGNING_KEY`, the file holds the raw 32 bytes secret
+///
+/// Devices only accept firmwares signed by the key they were flashed with, so release builds refuse to start without it.
+/// Debug builds and tests default to `signing.key`, generating it if it doesn't exist
+pub async fn signing_key() -> Result<&'static SigningKey> {
+    SIGNING_KEY
+        .get_or_try_init(|| async {
+            #[cfg(not(debug_assertions))]
+            {
+                let path =
+                    std::env::var("FIRMWARE_SIGNING_KEY").map_err(|_| Error::MissingSigningKey)?;
+                read_signing_key(&path).await
+            }
+
+            // Concurrent first calls wait for the same initialization, so a single key is ever generated
+            #[cfg(debug_assertions)]
+            {
+                let path = std::env::var("FIRMWARE_SIGNING_KEY")
+                    .unwrap_or_else(|_| "signing.key".to_owned());
+                match read_signing_key(&path).await {
+                    Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                        warn!("Firmware signing key not found, generating a new one at {path}");
+                        persist_signing_key(&path, SigningKey::generate(&mut OsRng)).await
+                    }
+                    result => result,
+                }
+            }
+        })
+        .await
+}
+
+async fn read_signing_key(path: &str) -> Result<SigningKey> {
+    let bytes = fs::read(path).await?;
+    let secret: [u8; 32] = bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::InvalidSigningKey(path.to_owned()))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+#[cfg(debug_assertions)]
+/// Only the owner may read the secret, it's written to a temporary file and linked in place so readers never see it half written
+///
+/// If another server got there first its key is kept, firmwares it signed must keep verifying
+async fn persist_signing_key(path: &str, key: SigningKey) -> Result<SigningKey> {
+    let tmp = format!("{path}.{}.tmp", random_string(8));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp).await?;
+    file.write_all(&key.to_bytes()).await?;
+    file.sync_all().await?;
+
+    let linked = fs::hard_link(&tmp, path).await;
+    fs::remove_file(&tmp).await?;
+    match linked {
+        Ok(()) => Ok(key),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => read_signing_key(path).await,
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn sign(bin: &[u8]) -> Result<String> {
+    Ok(hex(&signing_key().await?.sign(bin).to_bytes()))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
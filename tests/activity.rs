@@ -0,0 +1,88 @@
+use axum::{
+    body::{Body, HttpBody},
+    http::{Method, Request, StatusCode},
+};
+use server::test_helpers::{request, send_device_log, setup_device};
+use server::test_router;
+use std::time::Duration;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn live_requires_authorization() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "activity1").await;
+    let other = setup_device(app.clone(), "activity2").await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/v1/device/live?deviceId={}", device.device_id))
+                .method(Method::GET)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let uri = format!("/v1/device/live?deviceId={}", device.device_id);
+    let (status, _, _) = request(app.clone(), Method::GET, &uri, &other.token, None).await;
+    assert_ne!(status, StatusCode::OK);
+
+    let uri = format!("/v1/collection/live?collectionId={}", device.collection_id);
+    let (status, _, _) = request(app, Method::GET, &uri, &other.token, None).await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn live_streams_device_logs() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "activity3").await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!(
+                    "/v1/collection/live?collectionId={}",
+                    device.collection_id
+                ))
+                .header("Authorization", format!("Basic {}", device.token))
+                .method(Method::GET)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut body = response.into_body();
+
+    // The listener connects in the background, logs sent before it's listening are never streamed
+    let log = "streamed log";
+    let mut received = String::new();
+    for _ in 0..20 {
+        send_device_log(
+            app.clone(),
+            &device.device_token,
+            &device.mac,
+            &device.version,
+            log,
+        )
+        .await;
+        while let Ok(Some(chunk)) =
+            tokio::time::timeout(Duration::from_millis(500), body.data()).await
+        {
+            received.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap());
+            if received.contains(log) {
+                break;
+            }
+        }
+        if received.contains(log) {
+            break;
+        }
+    }
+    assert!(received.contains("event:log"), "{}", received);
+    assert!(received.contains(log), "{}", received);
+}
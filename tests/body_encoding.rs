@@ -0,0 +1,165 @@
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, Method, Request, StatusCode},
+};
+use serde_json::json;
+use server::test_helpers::{
+    list_device_logs, request, seed_firmware, setup_device, stat, TestDevice,
+};
+use server::{test_pool, test_router, EVENT_SCHEMA_VERSION};
+use tower::ServiceExt;
+
+fn encode(content_type: &str, value: &serde_json::Value) -> Vec<u8> {
+    match content_type {
+        "application/json" => serde_json::to_vec(value).unwrap(),
+        "application/cbor" => {
+            let mut body = Vec::new();
+            ciborium::ser::into_writer(value, &mut body).unwrap();
+            body
+        }
+        "application/msgpack" => rmp_serde::to_vec(value).unwrap(),
+        _ => unreachable!(),
+    }
+}
+
+async fn send(
+    app: axum::Router,
+    device: &TestDevice,
+    uri: &str,
+    content_type: &str,
+    body: Vec<u8>,
+) -> StatusCode {
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri(uri)
+                .header("Authorization", format!("Basic {}", device.device_token))
+                .header("MAC_ADDRESS", &device.mac)
+                .header("VERSION", &device.version)
+                .header(CONTENT_TYPE, content_type)
+                .method(Method::POST)
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    response.status()
+}
+
+#[tokio::test]
+async fn events_decode_like_json() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "body-encoding1").await;
+    seed_firmware(test_pool().await, device.collection_id, b"body-encoding1").await;
+
+    let content_types = [
+        "application/json",
+        "application/cbor",
+        "application/msgpack",
+    ];
+    for (index, content_type) in content_types.iter().enumerate() {
+        let body = json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": [{
+                "measurements": {
+                    "encoding": content_type,
+                    "index": index,
+                    "air_temperature_celsius0": 21.5,
+                    "nested": { "ok": true, "list": [1, null, "two"] },
+                },
+            }],
+        });
+        let status = send(
+            app.clone(),
+            &device,
+            "/v2/event",
+            content_type,
+            encode(content_type, &body),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", content_type);
+    }
+
+    let status = send(
+        app.clone(),
+        &device,
+        "/v2/event",
+        "application/xml",
+        b"<events/>".to_vec(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let status = send(
+        app.clone(),
+        &device,
+        "/v2/event",
+        "application/cbor",
+        b"not cbor".to_vec(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let since = (chrono::Utc::now() - chrono::Duration::hours(1))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let uri = format!(
+        "/v1/device/events?deviceId={}&since={since}",
+        device.device_id
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let mut measurements: Vec<serde_json::Value> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["measurements"].clone())
+        .collect();
+    measurements.sort_by_key(|measurements| measurements["index"].as_u64());
+    let expected: Vec<serde_json::Value> = content_types
+        .iter()
+        .enumerate()
+        .map(|(index, content_type)| {
+            json!({
+                "encoding": content_type,
+                "index": index,
+                "air_temperature_celsius0": 21.5,
+                "nested": { "ok": true, "list": [1, null, "two"] },
+            })
+        })
+        .collect();
+    assert_eq!(measurements, expected);
+}
+
+#[tokio::test]
+async fn logs_decode_like_text() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "body-encoding2").await;
+
+    let log = json!("encoded log");
+    for content_type in ["application/cbor", "application/msgpack"] {
+        let status = send(
+            app.clone(),
+            &device,
+            "/v1/log",
+            content_type,
+            encode(content_type, &log),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", content_type);
+    }
+    // Unknown content types are still plain text
+    let status = send(
+        app.clone(),
+        &device,
+        "/v1/log",
+        "application/octet-stream",
+        b"encoded log".to_vec(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let logs = list_device_logs(app, &device.token, device.device_id).await;
+    assert_eq!(logs.items().len(), 3);
+    assert!(logs.items().iter().all(|item| item.log() == "encoded log"));
+}
@@ -0,0 +1,160 @@
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, Method, Request, StatusCode},
+};
+use serde_json::json;
+use server::test_helpers::{request, seed_firmware, send_events, setup_device, TestDevice};
+use server::{test_pool, test_router, DeviceCommand, DeviceCommandStatus, Page};
+use tower::ServiceExt;
+
+async fn queue(app: axum::Router, device: &TestDevice, command: &str) -> (StatusCode, Vec<u8>) {
+    let body = json!({
+        "deviceId": device.device_id,
+        "command": command,
+        "arguments": {},
+        "expiresAt": null,
+    });
+    let (status, _, body) = request(
+        app,
+        Method::POST,
+        "/v1/device/command",
+        &device.token,
+        Some(body),
+    )
+    .await;
+    (status, body)
+}
+
+/// Commands in the event response, empty if there is no header
+async fn report(app: axum::Router, device: &TestDevice) -> (Vec<serde_json::Value>, bool) {
+    let (status, headers) = send_events(app, device, vec![json!({ "measurements": {} })]).await;
+    assert_eq!(status, StatusCode::OK);
+    let commands = headers
+        .get("commands")
+        .map(|commands| serde_json::from_slice(commands.as_bytes()).unwrap())
+        .unwrap_or_default();
+    (commands, headers.contains_key("latest_version"))
+}
+
+async fn acknowledge(app: axum::Router, device: &TestDevice, ack: serde_json::Value) -> StatusCode {
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/v1/command")
+                .header("Authorization", format!("Basic {}", device.device_token))
+                .header("MAC_ADDRESS", &device.mac)
+                .header(CONTENT_TYPE, "application/json")
+                .method(Method::POST)
+                .body(Body::from(serde_json::to_vec(&ack).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    response.status()
+}
+
+async fn list_commands(app: axum::Router, device: &TestDevice) -> Vec<DeviceCommand> {
+    let uri = format!("/v1/device/commands?deviceId={}", device.device_id);
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: Page<DeviceCommand> = serde_json::from_slice(&body).unwrap();
+    page.items().to_vec()
+}
+
+fn status_of(commands: &[DeviceCommand], command: &str) -> DeviceCommandStatus {
+    commands
+        .iter()
+        .find(|c| c.command() == command)
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn queue_deliver_acknowledge_and_expire() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "device-commands").await;
+    // Newer than what the device runs, so every event response offers the update
+    seed_firmware(test_pool().await, device.collection_id, b"device-commands update").await;
+
+    let (status, _) = queue(app.clone(), &device, "selfDestruct").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    for command in ["reboot", "flushLogs"] {
+        let (status, _) = queue(app.clone(), &device, command).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let commands = list_commands(app.clone(), &device).await;
+    assert_eq!(status_of(&commands, "reboot"), DeviceCommandStatus::Pending);
+
+    // Delivered even with the update, which the device may never apply
+    let (delivered, offers_update) = report(app.clone(), &device).await;
+    assert!(offers_update);
+    let mut names: Vec<_> = delivered
+        .iter()
+        .map(|c| c["command"].as_str().unwrap())
+        .collect();
+    names.sort_unstable();
+    assert_eq!(names, ["flushLogs", "reboot"]);
+    let commands = list_commands(app.clone(), &device).await;
+    assert_eq!(
+        status_of(&commands, "reboot"),
+        DeviceCommandStatus::Delivered
+    );
+
+    // Delivered commands aren't sent again
+    assert!(report(app.clone(), &device).await.0.is_empty());
+
+    let id =
+        |command: &str| delivered.iter().find(|c| c["command"] == command).unwrap()["id"].clone();
+    let status = acknowledge(app.clone(), &device, json!({ "id": id("reboot") })).await;
+    assert_eq!(status, StatusCode::OK);
+    // Retried acknowledgements are ignored
+    let status = acknowledge(app.clone(), &device, json!({ "id": id("reboot") })).await;
+    assert_eq!(status, StatusCode::OK);
+    let status = acknowledge(
+        app.clone(),
+        &device,
+        json!({ "id": id("flushLogs"), "error": "busy" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let commands = list_commands(app.clone(), &device).await;
+    assert_eq!(
+        status_of(&commands, "reboot"),
+        DeviceCommandStatus::Acknowledged
+    );
+    let failed = commands
+        .iter()
+        .find(|c| c.command() == "flushLogs")
+        .unwrap();
+    assert_eq!(failed.status(), DeviceCommandStatus::Failed);
+    assert_eq!(failed.error().as_deref(), Some("busy"));
+
+    // Neither delivered nor acknowledged in time
+    let (status, body) = queue(app.clone(), &device, "factoryReset").await;
+    assert_eq!(status, StatusCode::OK);
+    let pending: DeviceCommand = serde_json::from_slice(&body).unwrap();
+    sqlx::query(
+        "UPDATE device_commands SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1",
+    )
+    .bind(pending.id())
+    .execute(test_pool().await)
+    .await
+    .unwrap();
+    assert!(report(app.clone(), &device).await.0.is_empty());
+
+    let mut txn = test_pool().await.begin().await.unwrap();
+    assert!(DeviceCommand::expire(&mut txn).await.unwrap() >= 1);
+    txn.commit().await.unwrap();
+    let commands = list_commands(app.clone(), &device).await;
+    assert_eq!(
+        status_of(&commands, "factoryReset"),
+        DeviceCommandStatus::Expired
+    );
+    assert_eq!(
+        status_of(&commands, "reboot"),
+        DeviceCommandStatus::Acknowledged
+    );
+
+    let status = acknowledge(app, &device, json!({ "id": pending.id() })).await;
+    assert_ne!(status, StatusCode::OK);
+}
@@ -1,28 +1,23 @@
 use axum::{body::Body, http::Method, http::Request, http::StatusCode};
-use server::test_helpers::{list_device_logs, list_organizations, login, send_device_log, signup};
-use server::{db::user::Login, db::user::NewUser, test_router};
+use server::test_helpers::{
+    list_device_logs, list_organizations, login, new_user, request, send_device_log, setup_device,
+    signup,
+};
+use server::{test_pool, test_router, DeviceLogView, Login, Page, MAX_PAGE_SIZE};
 use tower::ServiceExt;
 
 #[tokio::test]
 async fn device_log() {
     let app = test_router().await;
 
-    let token = signup(
-        app.clone(),
-        NewUser {
-            email: "bobão4@example.com".to_owned(),
-            username: "bobão4".to_owned(),
-            password: "bobão1234".to_owned(),
-            organization_name: "bobão4".to_owned(),
-        },
-    )
-    .await;
+    let token = signup(app.clone(), new_user("bobão4")).await;
 
     let token_device1 = login(
         app.clone(),
         Login {
+            organization: Some("bobão4".to_owned()),
             email: "bobão4@example.com".to_owned(),
-            password: "bobão1234".to_owned(),
+            password: "bobão41234".to_owned(),
         },
         Some("aaaa".to_owned()),
         Some("bbba".to_owned()),
@@ -30,34 +25,27 @@ async fn device_log() {
     .await;
 
     let orgs = list_organizations(app.clone(), &token).await;
-    let device_id1 = orgs[0].collections[0].devices[0].id();
+    let device_id1 = orgs[0].collections()[0].devices()[0].id();
 
     let logs = list_device_logs(app.clone(), &token, device_id1).await;
-    assert_eq!(logs.len(), 0);
+    assert_eq!(logs.items().len(), 0);
+    assert_eq!(logs.next_cursor(), None);
 
     let log = "my loggy log logger";
-    send_device_log(app.clone(), &token_device1, "aaaa", "bbbb", &log).await;
+    send_device_log(app.clone(), &token_device1, "aaaa", "bbbb", log).await;
 
     let logs = list_device_logs(app.clone(), &token, device_id1).await;
-    assert_eq!(logs.len(), 1);
-    assert_eq!(logs[0].log(), log);
+    assert_eq!(logs.items().len(), 1);
+    assert_eq!(logs.items()[0].log(), log);
 
-    let token = signup(
-        app.clone(),
-        NewUser {
-            email: "bobão5@example.com".to_owned(),
-            username: "bobão5".to_owned(),
-            password: "bobão1234".to_owned(),
-            organization_name: "bobão5".to_owned(),
-        },
-    )
-    .await;
+    let token = signup(app.clone(), new_user("bobão5")).await;
 
     let token_device = login(
         app.clone(),
         Login {
+            organization: Some("bobão5".to_owned()),
             email: "bobão5@example.com".to_owned(),
-            password: "bobão1234".to_owned(),
+            password: "bobão51234".to_owned(),
         },
         Some("ddd".to_owned()),
         Some("ccc".to_owned()),
@@ -65,10 +53,10 @@ async fn device_log() {
     .await;
 
     let orgs = list_organizations(app.clone(), &token).await;
-    let device_id = orgs[0].collections[0].devices[0].id();
+    let device_id = orgs[0].collections()[0].devices()[0].id();
 
     let logs = list_device_logs(app.clone(), &token, device_id).await;
-    assert_eq!(logs.len(), 0);
+    assert_eq!(logs.items().len(), 0);
 
     let response = app
         .clone()
@@ -76,9 +64,9 @@ async fn device_log() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/logs?deviceId={}&limit={}",
-                    device_id1.0, 10
+                    device_id1, 10
                 ))
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -93,9 +81,9 @@ async fn device_log() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/logs?deviceId={}&limit={}",
-                    device_id.0, 10001
+                    device_id, 10001
                 ))
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -110,9 +98,9 @@ async fn device_log() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/logs?deviceId={}&limit={}",
-                    device_id.0, 10
+                    device_id, 10
                 ))
-                .header("Authorization", format!("Basic {}", token_device.0))
+                .header("Authorization", format!("Basic {}", token_device))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -127,7 +115,7 @@ async fn device_log() {
         .oneshot(
             Request::builder()
                 .uri("/v1/log")
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .header("MAC_ADDRESS", "ddd")
                 .header("VERSION", "ccc")
                 .method(Method::POST)
@@ -138,3 +126,77 @@ async fn device_log() {
         .unwrap();
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
+
+#[tokio::test]
+async fn device_log_pagination() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "log-pages").await;
+    for index in 0..5 {
+        let log = format!("log {index}");
+        send_device_log(
+            app.clone(),
+            &device.device_token,
+            &device.mac,
+            &device.version,
+            &log,
+        )
+        .await;
+    }
+    // Two of them an hour ago
+    sqlx::query(
+        "UPDATE device_logs SET created_at = created_at - INTERVAL '1 hour'
+         WHERE device_id = $1 AND log IN ('log 0', 'log 1')",
+    )
+    .bind(device.device_id)
+    .execute(test_pool().await)
+    .await
+    .unwrap();
+
+    let list = |query: String| {
+        let app = app.clone();
+        let token = device.token.clone();
+        let uri = format!("/v1/device/logs?deviceId={}{}", device.device_id, query);
+        async move {
+            let (status, _, body) = request(app, Method::GET, &uri, &token, None).await;
+            (status, body)
+        }
+    };
+    let page = |body: Vec<u8>| -> (Vec<String>, Option<String>) {
+        let page: Page<DeviceLogView> = serde_json::from_slice(&body).unwrap();
+        let logs = page.items().iter().map(|l| l.log().to_owned()).collect();
+        (logs, page.next_cursor().map(ToOwned::to_owned))
+    };
+
+    // Newest first, following the cursor until the last page
+    let (status, body) = list("&limit=2".to_owned()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (logs, cursor) = page(body);
+    assert_eq!(logs, ["log 4", "log 3"]);
+    let (_, body) = list(format!("&limit=2&cursor={}", cursor.unwrap())).await;
+    let (logs, cursor) = page(body);
+    assert_eq!(logs, ["log 2", "log 1"]);
+    let (_, body) = list(format!("&limit=2&cursor={}", cursor.unwrap())).await;
+    let (logs, cursor) = page(body);
+    assert_eq!(logs, ["log 0"]);
+    assert_eq!(cursor, None);
+
+    // Exactly a page doesn't point to an empty one
+    let (_, body) = list("&limit=5".to_owned()).await;
+    assert_eq!(page(body).1, None);
+
+    let until = (chrono::Utc::now() - chrono::Duration::minutes(30)).to_rfc3339();
+    let until = until.replace('+', "%2B");
+    let (status, body) = list(format!("&until={until}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        page(body),
+        (vec!["log 1".to_owned(), "log 0".to_owned()], None)
+    );
+
+    let (status, _) = list(format!("&limit={MAX_PAGE_SIZE}")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = list(format!("&limit={}", MAX_PAGE_SIZE + 1)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = list("&cursor=nope".to_owned()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
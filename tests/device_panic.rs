@@ -1,31 +1,36 @@
 use axum::{body::Body, http::Method, http::Request, http::StatusCode};
 use server::test_helpers::{
-    list_device_panics, list_organizations, login, send_device_panic, signup,
+    add_device, list_device_panics, list_organizations, login, new_user, request, seed_firmware,
+    send_device_panic, setup_device, signup, TestDevice,
+};
+use server::{
+    test_pool, test_router, DevicePanicView, Login, NewDevicePanic, OrganizationId, Page,
+    PanicGroup,
 };
-use server::NewDevicePanic;
-use server::{db::user::Login, db::user::NewUser, test_router};
 use tower::ServiceExt;
 
+fn new_panic(msg: &str) -> NewDevicePanic {
+    serde_json::from_value(serde_json::json!({
+        "file": "myfile.cpp",
+        "line": 32,
+        "func": "myfunc()",
+        "msg": msg,
+    }))
+    .unwrap()
+}
+
 #[tokio::test]
 async fn device_panic() {
     let app = test_router().await;
 
-    let token = signup(
-        app.clone(),
-        NewUser {
-            email: "bobão4@example.com".to_owned(),
-            username: "bobão4".to_owned(),
-            password: "bobão1234".to_owned(),
-            organization_name: "bobão4".to_owned(),
-        },
-    )
-    .await;
+    let token = signup(app.clone(), new_user("bobão4")).await;
 
     let token_device1 = login(
         app.clone(),
         Login {
+            organization: Some("bobão4".to_owned()),
             email: "bobão4@example.com".to_owned(),
-            password: "bobão1234".to_owned(),
+            password: "bobão41234".to_owned(),
         },
         Some("aaaa".to_owned()),
         Some("bbba".to_owned()),
@@ -33,10 +38,10 @@ async fn device_panic() {
     .await;
 
     let orgs = list_organizations(app.clone(), &token).await;
-    let device_id1 = orgs[0].collections[0].devices[0].id();
+    let device_id1 = orgs[0].collections()[0].devices()[0].id();
 
     let panics = list_device_panics(app.clone(), &token, device_id1).await;
-    assert_eq!(panics.len(), 0);
+    assert_eq!(panics.items().len(), 0);
 
     let panic = "my panicgy panic panicger";
     send_device_panic(
@@ -44,35 +49,22 @@ async fn device_panic() {
         &token_device1,
         "aaaa",
         "bbbb",
-        &NewDevicePanic {
-            file: "myfile.cpp".to_owned(),
-            line: 32,
-            func: "myfunc()".to_owned(),
-            msg: panic.to_owned(),
-        },
+        &new_panic(panic),
     )
     .await;
 
     let panics = list_device_panics(app.clone(), &token, device_id1).await;
-    assert_eq!(panics.len(), 1);
-    assert_eq!(panics[0].msg(), panic);
+    assert_eq!(panics.items().len(), 1);
+    assert_eq!(panics.items()[0].msg(), panic);
 
-    let token = signup(
-        app.clone(),
-        NewUser {
-            email: "bobão5@example.com".to_owned(),
-            username: "bobão5".to_owned(),
-            password: "bobão1234".to_owned(),
-            organization_name: "bobão5".to_owned(),
-        },
-    )
-    .await;
+    let token = signup(app.clone(), new_user("bobão5")).await;
 
     let token_device = login(
         app.clone(),
         Login {
+            organization: Some("bobão5".to_owned()),
             email: "bobão5@example.com".to_owned(),
-            password: "bobão1234".to_owned(),
+            password: "bobão51234".to_owned(),
         },
         Some("ddd".to_owned()),
         Some("ccc".to_owned()),
@@ -80,10 +72,10 @@ async fn device_panic() {
     .await;
 
     let orgs = list_organizations(app.clone(), &token).await;
-    let device_id = orgs[0].collections[0].devices[0].id();
+    let device_id = orgs[0].collections()[0].devices()[0].id();
 
     let panics = list_device_panics(app.clone(), &token, device_id).await;
-    assert_eq!(panics.len(), 0);
+    assert_eq!(panics.items().len(), 0);
 
     let response = app
         .clone()
@@ -91,9 +83,9 @@ async fn device_panic() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/panics?deviceId={}&limit={}",
-                    device_id1.0, 10
+                    device_id1, 10
                 ))
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -108,9 +100,9 @@ async fn device_panic() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/panics?deviceId={}&limit={}",
-                    device_id.0, 10001
+                    device_id, 10001
                 ))
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -125,9 +117,9 @@ async fn device_panic() {
             Request::builder()
                 .uri(format!(
                     "/v1/device/panics?deviceId={}&limit={}",
-                    device_id.0, 10
+                    device_id, 10
                 ))
-                .header("Authorization", format!("Basic {}", token_device.0))
+                .header("Authorization", format!("Basic {}", token_device))
                 .method(Method::GET)
                 .body(Body::empty())
                 .unwrap(),
@@ -142,7 +134,7 @@ async fn device_panic() {
         .oneshot(
             Request::builder()
                 .uri("/v1/panic")
-                .header("Authorization", format!("Basic {}", token.0))
+                .header("Authorization", format!("Basic {}", token))
                 .header("MAC_ADDRESS", "ddd")
                 .header("VERSION", "ccc")
                 .method(Method::POST)
@@ -153,3 +145,196 @@ async fn device_panic() {
         .unwrap();
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
+
+#[tokio::test]
+async fn device_panic_pagination() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "panic-pages").await;
+    for index in 0..3 {
+        send_device_panic(
+            app.clone(),
+            &device.device_token,
+            &device.mac,
+            &device.version,
+            &new_panic(&format!("panic {index}")),
+        )
+        .await;
+    }
+
+    let mut uri = format!("/v1/device/panics?deviceId={}&limit=2", device.device_id);
+    let mut msgs = Vec::new();
+    loop {
+        let (status, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Page<DevicePanicView> = serde_json::from_slice(&body).unwrap();
+        assert!(page.items().len() <= 2);
+        msgs.extend(page.items().iter().map(|p| p.msg().to_owned()));
+        match page.next_cursor() {
+            Some(cursor) => {
+                uri = format!(
+                    "/v1/device/panics?deviceId={}&limit=2&cursor={}",
+                    device.device_id, cursor
+                )
+            }
+            None => break,
+        }
+    }
+    assert_eq!(msgs, ["panic 2", "panic 1", "panic 0"]);
+}
+
+/// Moves the device to its organization's firmware with that hash, like after an update
+async fn run_firmware(device: &TestDevice, hash: &str) {
+    sqlx::query(
+        "UPDATE devices SET firmware_id = (SELECT id FROM firmwares WHERE organization_id = $1 AND binary_hash = $2)
+         WHERE id = $3",
+    )
+    .bind(device.organization_id)
+    .bind(hash)
+    .bind(device.device_id)
+    .execute(test_pool().await)
+    .await
+    .unwrap();
+}
+
+async fn panic_groups(
+    app: axum::Router,
+    device: &TestDevice,
+    organization_id: OrganizationId,
+) -> Vec<PanicGroup> {
+    let uri = format!("/v1/organization/panics?organizationId={}", organization_id);
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: Page<PanicGroup> = serde_json::from_slice(&body).unwrap();
+    page.items().to_vec()
+}
+
+async fn panic(app: axum::Router, device: &TestDevice, func: &str, msg: &str) {
+    let panic = serde_json::from_value::<NewDevicePanic>(serde_json::json!({
+        "file": "main.cpp",
+        "line": 10,
+        "func": func,
+        "msg": msg,
+    }))
+    .unwrap();
+    send_device_panic(
+        app,
+        &device.device_token,
+        &device.mac,
+        &device.version,
+        &panic,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn panic_groups_solve_and_reopen() {
+    let app = test_router().await;
+    let device1 = setup_device(app.clone(), "panic-groups").await;
+    let device2 = add_device(
+        app.clone(),
+        device1.token.clone(),
+        "panic-groups",
+        "panic-groups-2",
+    )
+    .await;
+    let other = setup_device(app.clone(), "panic-groups-other").await;
+
+    let old_hash = seed_firmware(test_pool().await, device1.collection_id, b"panic-old").await;
+    let new_hash = seed_firmware(test_pool().await, device1.collection_id, b"panic-new").await;
+    // The same binary in another organization, uploaded after the newer firmware
+    seed_firmware(test_pool().await, other.collection_id, b"panic-old").await;
+    run_firmware(&device1, &old_hash).await;
+    run_firmware(&device2, &old_hash).await;
+
+    panic(
+        app.clone(),
+        &device1,
+        "loop()",
+        "stack overflow at 0x3ffb1234, depth 12",
+    )
+    .await;
+    panic(
+        app.clone(),
+        &device2,
+        "loop()",
+        "stack overflow at 0x3ffb9999, depth 7",
+    )
+    .await;
+    panic(
+        app.clone(),
+        &device1,
+        "setup()",
+        "stack overflow at 0x3ffb1234, depth 12",
+    )
+    .await;
+
+    let groups = panic_groups(app.clone(), &device1, device1.organization_id).await;
+    assert_eq!(groups.len(), 2);
+    let group = groups
+        .iter()
+        .find(|g| g.func() == "loop()")
+        .unwrap()
+        .clone();
+    assert_eq!(group.msg(), "stack overflow at #, depth #");
+    assert_eq!(group.occurrences(), 2);
+    assert_eq!(group.affected_devices(), 2);
+    assert!(!group.is_solved());
+    assert!(panic_groups(app.clone(), &other, other.organization_id)
+        .await
+        .is_empty());
+
+    let (status, _, _) = request(
+        app.clone(),
+        Method::POST,
+        "/v1/organization/panic/solve",
+        &device1.token,
+        Some(serde_json::json!({
+            "organizationId": device1.organization_id,
+            "panicGroupId": group.id(),
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (unsolved,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM device_panics WHERE panic_group_id = $1 AND NOT is_solved",
+    )
+    .bind(group.id())
+    .fetch_one(test_pool().await)
+    .await
+    .unwrap();
+    assert_eq!(unsolved, 0);
+    let find = |groups: Vec<PanicGroup>| groups.into_iter().find(|g| g.id() == group.id()).unwrap();
+    let solved = find(panic_groups(app.clone(), &device1, device1.organization_id).await);
+    assert!(solved.is_solved());
+    assert_eq!(
+        solved.solved_firmware_hash().as_deref(),
+        Some(old_hash.as_str())
+    );
+
+    // Happening again on the firmware it was solved on doesn't reopen it
+    panic(
+        app.clone(),
+        &device2,
+        "loop()",
+        "stack overflow at 0x3ffb0000, depth 1",
+    )
+    .await;
+    let solved = find(panic_groups(app.clone(), &device1, device1.organization_id).await);
+    assert!(solved.is_solved());
+    assert_eq!(solved.occurrences(), 3);
+
+    // The other organization's copy of the solved binary is newer, but only this organization's firmwares count
+    run_firmware(&device1, &new_hash).await;
+    panic(
+        app.clone(),
+        &device1,
+        "loop()",
+        "stack overflow at 0x3ffb0000, depth 1",
+    )
+    .await;
+    let reopened = find(panic_groups(app.clone(), &device1, device1.organization_id).await);
+    assert!(!reopened.is_solved());
+    assert_eq!(reopened.solved_at(), None);
+    assert_eq!(reopened.solved_firmware_hash(), &None);
+    assert_eq!(reopened.occurrences(), 4);
+}
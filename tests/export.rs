@@ -0,0 +1,187 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use server::test_helpers::{
+    request, seed_firmware, seed_sensor, send_events, setup_device, stat, TestDevice,
+};
+use server::{test_pool, test_router, STAT_COLUMNS};
+
+async fn setup(name: &str) -> (axum::Router, TestDevice) {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), name).await;
+    seed_firmware(test_pool().await, device.collection_id, name.as_bytes()).await;
+    let sensor = seed_sensor(test_pool().await, device.collection_id, "DHT", 0).await;
+    sqlx::query(
+        "UPDATE sensor_belongs_to_compiler SET alias = 'North, \"big\" one' WHERE sensor_id = $1",
+    )
+    .bind(sensor)
+    .execute(test_pool().await)
+    .await
+    .unwrap();
+
+    let (status, _) = send_events(
+        app.clone(),
+        &device,
+        vec![
+            json!({ "measurements": { "air_temperature_celsius0": 21.5, "air_humidity_percentage0": 40 } }),
+            json!({ "measurements": { "air_temperature_celsius0": 22, "note": "said \"hi\"\nbye" } }),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    (app, device)
+}
+
+async fn export(
+    app: axum::Router,
+    device: &TestDevice,
+    query: &str,
+) -> (axum::http::HeaderMap, String) {
+    let since = (chrono::Utc::now() - chrono::Duration::hours(1))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let uri = format!(
+        "/v1/device/export?deviceId={}&since={since}{query}",
+        device.device_id
+    );
+    let (status, headers, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    (headers, String::from_utf8(body).unwrap())
+}
+
+/// Splits the CSV on the line breaks outside of quotes
+fn csv_lines(csv: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let (mut start, mut quoted) = (0, false);
+    for (index, char) in csv.char_indices() {
+        match char {
+            '"' => quoted = !quoted,
+            '\n' if !quoted => {
+                assert_eq!(&csv[index - 1..index], "\r");
+                lines.push(&csv[start..index - 1]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(start, csv.len());
+    lines
+}
+
+#[tokio::test]
+async fn csv_columns_and_quoting() {
+    let (app, device) = setup("export-csv").await;
+
+    let (headers, csv) = export(app.clone(), &device, "&format=csv").await;
+    assert_eq!(headers["content-type"], "text/csv; charset=utf-8");
+    let disposition = headers["content-disposition"].to_str().unwrap();
+    assert!(disposition.starts_with(&format!(
+        "attachment; filename=\"device-{}-",
+        device.device_id
+    )));
+    assert!(disposition.ends_with(".csv\""));
+
+    let lines = csv_lines(&csv);
+    assert_eq!(lines.len(), 3);
+    // Sensor columns come first, labeled by alias, what else was reported is appended
+    assert_eq!(
+        lines[0],
+        "measuredAt,createdAt,deviceId,deviceName,\
+         \"North, \"\"big\"\" one - Air Temperature\",\"North, \"\"big\"\" one - Air Humidity\",note"
+    );
+    let measurements = |line: &str| {
+        let fields: Vec<&str> = line.splitn(5, ',').collect();
+        chrono::DateTime::parse_from_rfc3339(fields[0]).unwrap();
+        chrono::DateTime::parse_from_rfc3339(fields[1]).unwrap();
+        assert_eq!(fields[2], device.device_id.to_string());
+        fields[4].to_owned()
+    };
+    assert_eq!(measurements(lines[1]), "21.5,40,");
+    assert_eq!(measurements(lines[2]), "22,,\"said \"\"hi\"\"\nbye\"");
+
+    let (_, csv) = export(app.clone(), &device, "&format=csv&stat=true").await;
+    let lines = csv_lines(&csv);
+    assert!(lines[0].ends_with(&format!(",note,{}", STAT_COLUMNS.join(","))));
+    let stat = stat(&device.version);
+    let stat: Vec<String> = STAT_COLUMNS
+        .iter()
+        .map(|name| match stat.get(name) {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        })
+        .collect();
+    assert!(
+        lines[1].ends_with(&format!(",40,,{}", stat.join(","))),
+        "{}",
+        lines[1]
+    );
+}
+
+#[tokio::test]
+async fn ndjson_lines() {
+    let (app, device) = setup("export-ndjson").await;
+
+    let (headers, ndjson) = export(app.clone(), &device, "&format=ndjson").await;
+    assert_eq!(headers["content-type"], "application/x-ndjson");
+    assert!(ndjson.ends_with('\n'));
+    let lines: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["deviceId"], json!(device.device_id));
+    assert!(lines[0].get("stat").is_none());
+    assert_eq!(
+        lines[0]["measurements"],
+        json!({
+            "North, \"big\" one - Air Temperature": 21.5,
+            "North, \"big\" one - Air Humidity": 40,
+        })
+    );
+    // Missing measurements are left out instead of null
+    assert_eq!(
+        lines[1]["measurements"],
+        json!({
+            "North, \"big\" one - Air Temperature": 22,
+            "note": "said \"hi\"\nbye",
+        })
+    );
+
+    let (_, ndjson) = export(app.clone(), &device, "&format=ndjson&stat=true").await;
+    let line: serde_json::Value = serde_json::from_str(ndjson.lines().next().unwrap()).unwrap();
+    assert_eq!(line["stat"]["version"], json!(device.version));
+}
+
+#[tokio::test]
+async fn batched_events_by_measured_at() {
+    let (app, device) = setup("export-batched").await;
+
+    let measured_at = chrono::Utc::now() - chrono::Duration::hours(3);
+    let (status, _) = send_events(
+        app.clone(),
+        &device,
+        vec![json!({
+            "measuredAt": measured_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "measurements": { "offline_note": "buffered" },
+        })],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Received now, but measured before the range of the other events
+    let format = |date: chrono::DateTime<chrono::Utc>| {
+        date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    };
+    let uri = format!(
+        "/v1/device/export?deviceId={}&since={}&until={}&format=csv",
+        device.device_id,
+        format(measured_at - chrono::Duration::minutes(1)),
+        format(measured_at + chrono::Duration::minutes(1)),
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let csv = String::from_utf8(body).unwrap();
+    let lines = csv_lines(&csv);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(",offline_note"), "{}", lines[0]);
+    assert!(lines[1].ends_with(",buffered"), "{}", lines[1]);
+}
@@ -0,0 +1,116 @@
+use axum::http::StatusCode;
+use server::test_helpers::{download_update, seed_firmware, setup_device};
+use server::{test_pool, test_router};
+
+async fn updates_recorded(device: &server::test_helpers::TestDevice) -> i64 {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM firmware_updates WHERE device_id = $1")
+            .bind(device.device_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    count
+}
+
+#[tokio::test]
+async fn esp32_updater_headers() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "ota-esp32").await;
+    let hash = seed_firmware(test_pool().await, device.collection_id, b"esp32 binary").await;
+
+    let (status, headers, body) = download_update(
+        app.clone(),
+        &device,
+        &[
+            ("x-esp32-sta-mac", &device.mac),
+            ("x-esp32-sketch-md5", &device.version.to_uppercase()),
+            ("x-esp32-free-space", "4096"),
+            ("x-esp32-chip-size", "4194304"),
+            ("x-esp32-sdk-version", "v4.4"),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"esp32 binary");
+    assert_eq!(headers["x-MD5"], hash.as_str());
+    assert!(headers.contains_key("x-Signature"));
+    assert_eq!(updates_recorded(&device).await, 1);
+
+    // Already running it
+    let (status, _, _) = download_update(
+        app.clone(),
+        &device,
+        &[
+            ("x-esp32-sta-mac", &device.mac),
+            ("x-esp32-sketch-md5", &hash),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn updates_that_dont_fit_are_refused() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "ota-space").await;
+    seed_firmware(test_pool().await, device.collection_id, b"too big binary").await;
+
+    let (status, _, body) = download_update(
+        app.clone(),
+        &device,
+        &[
+            ("x-esp8266-sta-mac", &device.mac),
+            ("x-esp8266-sketch-md5", &device.version),
+            ("x-esp8266-free-space", "4"),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        serde_json::json!({ "error": "Not Enough Space" })
+    );
+
+    let (status, _, body) = download_update(
+        app.clone(),
+        &device,
+        &[
+            ("x-esp32-sta-mac", &device.mac),
+            ("x-esp32-sketch-md5", &device.version),
+            ("x-esp32-free-space", "4"),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        serde_json::json!({ "error": "Not Enough Space" })
+    );
+    assert_eq!(updates_recorded(&device).await, 0);
+
+    // Exactly fits
+    let (status, _, _) = download_update(
+        app.clone(),
+        &device,
+        &[
+            ("x-esp8266-sta-mac", &device.mac),
+            ("x-esp8266-sketch-md5", &device.version),
+            ("x-esp8266-free-space", "14"),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(updates_recorded(&device).await, 1);
+}
+
+#[tokio::test]
+async fn updater_without_sketch_md5_is_refused() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "ota-no-md5").await;
+    seed_firmware(test_pool().await, device.collection_id, b"binary").await;
+
+    let (status, _, _) =
+        download_update(app.clone(), &device, &[("x-esp32-sta-mac", &device.mac)]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(updates_recorded(&device).await, 0);
+}
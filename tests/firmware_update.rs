@@ -0,0 +1,110 @@
+use axum::http::{Method, StatusCode};
+use server::test_helpers::{
+    download_update, request, seed_firmware, send_events, setup_device, TestDevice,
+};
+use server::{test_pool, test_router, DeviceUpdateView, RolloutView, UpdateStatus};
+
+async fn updates(app: axum::Router, device: &TestDevice) -> DeviceUpdateView {
+    let uri = format!("/v1/device/updates?deviceId={}", device.device_id);
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+async fn download(app: axum::Router, device: &TestDevice) -> Vec<u8> {
+    let (status, _, body) = download_update(
+        app,
+        device,
+        &[
+            ("x-esp8266-sta-mac", &device.mac),
+            ("x-esp8266-sketch-md5", &device.version),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+#[tokio::test]
+async fn in_flight_events_dont_fail_the_update() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "update-grace").await;
+    let hash = seed_firmware(test_pool().await, device.collection_id, b"grace binary").await;
+
+    assert_eq!(download(app.clone(), &device).await, b"grace binary");
+    let view = updates(app.clone(), &device).await;
+    assert_eq!(view.status(), Some(UpdateStatus::Downloaded));
+    assert_eq!(view.history().len(), 1);
+    assert_eq!(view.history()[0].to_hash(), &hash);
+    assert_eq!(
+        view.history()[0].binary_size(),
+        b"grace binary".len() as i64
+    );
+
+    // Sent before rebooting, still on the old firmware
+    let (status, _) = send_events(app.clone(), &device, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let view = updates(app.clone(), &device).await;
+    assert_eq!(view.status(), Some(UpdateStatus::Downloaded));
+
+    sqlx::query("UPDATE firmware_updates SET created_at = NOW() - INTERVAL '11 minutes' WHERE device_id = $1")
+        .bind(device.device_id)
+        .execute(test_pool().await)
+        .await
+        .unwrap();
+    let (status, _) = send_events(app.clone(), &device, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let view = updates(app.clone(), &device).await;
+    assert_eq!(view.status(), Some(UpdateStatus::Failed));
+}
+
+#[tokio::test]
+async fn reporting_the_new_firmware_applies_the_update() {
+    let app = test_router().await;
+    let mut device = setup_device(app.clone(), "update-applied").await;
+    let hash = seed_firmware(test_pool().await, device.collection_id, b"applied binary").await;
+
+    download(app.clone(), &device).await;
+    device.version = hash.clone();
+    let (status, _) = send_events(app.clone(), &device, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let view = updates(app.clone(), &device).await;
+    assert_eq!(view.status(), Some(UpdateStatus::Applied));
+    assert_eq!(view.current_hash(), &hash);
+    assert_eq!(
+        view.history()[0].status(),
+        server::FirmwareUpdateStatus::Applied
+    );
+}
+
+#[tokio::test]
+async fn rollout_counts_each_device() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "update-rollout").await;
+    let uri = format!(
+        "/v1/collection/rollout?collectionId={}",
+        device.collection_id
+    );
+
+    let (status, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let rollout: RolloutView = serde_json::from_slice(&body).unwrap();
+    assert!(rollout.latest().is_none());
+    assert!(rollout.devices().is_empty());
+
+    let hash = seed_firmware(test_pool().await, device.collection_id, b"rollout binary").await;
+    let (_, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    let rollout: RolloutView = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rollout.latest().as_ref().unwrap().hash(), &hash);
+    assert_eq!(rollout.pending(), 1);
+    assert_eq!(rollout.devices()[0].status(), UpdateStatus::Pending);
+    assert_eq!(rollout.devices()[0].current_hash(), &device.version);
+
+    download(app.clone(), &device).await;
+    let (_, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    let rollout: RolloutView = serde_json::from_slice(&body).unwrap();
+    assert_eq!((rollout.pending(), rollout.downloaded()), (0, 1));
+    assert_eq!(rollout.devices()[0].device_id(), device.device_id);
+    assert_eq!(rollout.devices()[0].status(), UpdateStatus::Downloaded);
+}
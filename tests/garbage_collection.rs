@@ -0,0 +1,87 @@
+use axum::http::{Method, StatusCode};
+use server::test_helpers::{request, seed_firmware, send_events, setup_device, TestDevice};
+use server::{
+    test_pool, test_router, CompilationId, FirmwareId, GarbageCollection, Organization, User,
+};
+
+async fn firmware(hash: &str) -> (FirmwareId, CompilationId) {
+    sqlx::query_as("SELECT id, compilation_id FROM firmwares WHERE binary_hash = $1")
+        .bind(hash)
+        .fetch_one(test_pool().await)
+        .await
+        .unwrap()
+}
+
+async fn report(app: axum::Router, device: &TestDevice) -> GarbageCollection {
+    let uri = format!(
+        "/v1/organization/garbage?organizationId={}",
+        device.organization_id
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn report_keeps_latest_and_running_firmwares() {
+    let app = test_router().await;
+    let mut device = setup_device(app.clone(), "gc-report").await;
+    let mut hashes = Vec::new();
+    for index in 0..5 {
+        let bin = format!("gc binary {index}");
+        hashes.push(seed_firmware(test_pool().await, device.collection_id, bin.as_bytes()).await);
+    }
+
+    // Running the oldest keeps it around
+    device.version = hashes[0].clone();
+    let (status, _) = send_events(app.clone(), &device, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let garbage = report(app.clone(), &device).await;
+    let (firmware_id, compilation_id) = firmware(&hashes[1]).await;
+    assert_eq!(garbage.compilations(), &[compilation_id]);
+    assert_eq!(garbage.firmwares(), &[firmware_id]);
+    assert_eq!(garbage.bytes(), "gc binary 1".len() as i64);
+
+    // It's a dry-run
+    let garbage = report(app.clone(), &device).await;
+    assert_eq!(garbage.compilations(), &[compilation_id]);
+
+    // Other organizations' garbage isn't reported
+    let other = setup_device(app.clone(), "gc-report-other").await;
+    let garbage = report(app.clone(), &other).await;
+    assert!(garbage.compilations().is_empty());
+}
+
+#[tokio::test]
+async fn latest_compilation_is_never_collected() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "gc-latest").await;
+    let old = seed_firmware(test_pool().await, device.collection_id, b"gc old").await;
+    let latest = seed_firmware(test_pool().await, device.collection_id, b"gc latest").await;
+
+    let mut txn = test_pool().await.begin().await.unwrap();
+    let user = User::find_by_auth_token(&mut txn, device.token.clone())
+        .await
+        .unwrap();
+    let organization = Organization::find_by_id(&mut txn, device.organization_id, &user)
+        .await
+        .unwrap();
+    let garbage = GarbageCollection::find(&mut txn, Some(&organization), 0)
+        .await
+        .unwrap();
+    let (_, old) = firmware(&old).await;
+    let (_, latest) = firmware(&latest).await;
+    assert!(garbage.compilations().contains(&old));
+    assert!(!garbage.compilations().contains(&latest));
+
+    garbage.execute(&mut txn).await.unwrap();
+    txn.commit().await.unwrap();
+
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM compilations WHERE id = ANY($1)")
+        .bind(&[old, latest][..])
+        .fetch_one(test_pool().await)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
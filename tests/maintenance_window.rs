@@ -0,0 +1,108 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use server::test_helpers::{
+    add_device, request, seed_firmware, send_events, setup_device, TestDevice,
+};
+use server::{test_pool, test_router, CollectionId, MaintenanceWindow};
+
+async fn add_window(app: axum::Router, device: &TestDevice) {
+    let (status, _, _) = request(
+        app.clone(),
+        Method::POST,
+        "/v1/collection/maintenance/window",
+        &device.token,
+        Some(json!({
+            "collectionId": device.collection_id,
+            "weekday": null,
+            "startsAt": "02:00:00",
+            "endsAt": "04:00:00",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let uri = format!(
+        "/v1/collection/maintenance/windows?collectionId={}",
+        device.collection_id
+    );
+    let (_, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    let windows: Vec<MaintenanceWindow> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(windows.len(), 1);
+}
+
+async fn collection_exists(device: &TestDevice) -> bool {
+    let (exists,): (bool,) =
+        sqlx::query_as("SELECT EXISTS (SELECT 1 FROM collections WHERE id = $1)")
+            .bind(device.collection_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    exists
+}
+
+#[tokio::test]
+async fn device_leaves_a_collection_with_a_window_when_it_reports_a_known_firmware() {
+    let app = test_router().await;
+    let first = setup_device(app.clone(), "window-move").await;
+    let hash = seed_firmware(test_pool().await, first.collection_id, b"window binary").await;
+
+    let mut second = add_device(
+        app.clone(),
+        first.token.clone(),
+        "window-move",
+        "window-move-second",
+    )
+    .await;
+    assert_ne!(first.collection_id, second.collection_id);
+    add_window(app.clone(), &second).await;
+
+    // Running the first collection's firmware moves it there, deleting its now empty collection
+    second.version = hash;
+    let (status, _) = send_events(app.clone(), &second, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!collection_exists(&second).await);
+
+    let (collection_id,): (CollectionId,) =
+        sqlx::query_as("SELECT collection_id FROM devices WHERE id = $1")
+            .bind(second.device_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    assert_eq!(collection_id, first.collection_id);
+}
+
+#[tokio::test]
+async fn collections_with_windows_merge_into_the_compilers() {
+    let app = test_router().await;
+    let first = setup_device(app.clone(), "window-merge").await;
+    seed_firmware(test_pool().await, first.collection_id, b"merge binary").await;
+    let (compiler_id,): (i64,) =
+        sqlx::query_as("SELECT compiler_id FROM collections WHERE id = $1")
+            .bind(first.collection_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+
+    let second = add_device(
+        app.clone(),
+        first.token.clone(),
+        "window-merge",
+        "window-merge-second",
+    )
+    .await;
+    add_window(app.clone(), &second).await;
+
+    let (status, _, _) = request(
+        app.clone(),
+        Method::POST,
+        "/v1/compiler/set",
+        &second.token,
+        Some(json!({
+            "id": { "collection_id": second.collection_id },
+            "compilerId": compiler_id,
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!collection_exists(&second).await);
+}
@@ -0,0 +1,141 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use server::test_helpers::{seed_firmware, seed_sensor, send_events, setup_device};
+use server::{test_pool, test_router, DeviceId, EventId, SensorId};
+
+type Row = (SensorId, String, String, String, f64);
+
+async fn measurements(device_id: DeviceId) -> Vec<Row> {
+    sqlx::query_as(
+        "SELECT sensor_id, variable_name, kind::TEXT, ty::TEXT, value
+         FROM measurements WHERE device_id = $1 ORDER BY variable_name",
+    )
+    .bind(device_id)
+    .fetch_all(test_pool().await)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn events_are_stored_as_a_row_per_measurement() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "measurement-rows").await;
+    seed_firmware(test_pool().await, device.collection_id, b"rows binary").await;
+    let dht = seed_sensor(test_pool().await, device.collection_id, "DHT", 0).await;
+    let dallas = seed_sensor(
+        test_pool().await,
+        device.collection_id,
+        "Dallas Temperature",
+        1,
+    )
+    .await;
+
+    let (status, _) = send_events(
+        app.clone(),
+        &device,
+        vec![json!({
+            "measurements": {
+                "air_temperature_celsius0": 21.5,
+                "air_humidity_percentage0": 40,
+                "soil_temperature_celsius1": 18.25,
+            }
+        })],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(
+        measurements(device.device_id).await,
+        vec![
+            (
+                dht,
+                "air_humidity_percentage0".to_owned(),
+                "AirHumidity".to_owned(),
+                "Percentage".to_owned(),
+                40.
+            ),
+            (
+                dht,
+                "air_temperature_celsius0".to_owned(),
+                "AirTemperature".to_owned(),
+                "FloatCelsius".to_owned(),
+                21.5
+            ),
+            (
+                dallas,
+                "soil_temperature_celsius1".to_owned(),
+                "SoilTemperature".to_owned(),
+                "FloatCelsius".to_owned(),
+                18.25
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn events_stored_before_are_backfilled() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "measurement-backfill").await;
+    let hash = seed_firmware(test_pool().await, device.collection_id, b"backfill binary").await;
+    let sensor = seed_sensor(test_pool().await, device.collection_id, "DHT", 2).await;
+
+    let (event_id,): (EventId,) = sqlx::query_as(
+        "INSERT INTO events (device_id, measurements, stat, metadatas, firmware_hash)
+         VALUES ($1, $2, '{}', '{}', $3)
+         RETURNING id",
+    )
+    .bind(device.device_id)
+    .bind(json!({
+        "air_temperature_celsius2": 30,
+        "air_humidity_percentage2": 55.5,
+        "unknown": 1,
+    }))
+    .bind(&hash)
+    .fetch_one(test_pool().await)
+    .await
+    .unwrap();
+
+    // Scoped to this device, so the other tests' events don't get in the way
+    let migration =
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations/8.sql")).unwrap();
+    let backfill = &migration[migration.find("INSERT INTO measurements").unwrap()..];
+    let backfill = backfill.replace(
+        "ON CONFLICT",
+        &format!("AND events.device_id = {}\nON CONFLICT", device.device_id),
+    );
+    for _ in 0..2 {
+        sqlx::query(backfill.trim_end().trim_end_matches(';'))
+            .execute(test_pool().await)
+            .await
+            .unwrap();
+    }
+
+    let rows = measurements(device.device_id).await;
+    assert_eq!(
+        rows,
+        vec![
+            (
+                sensor,
+                "air_humidity_percentage2".to_owned(),
+                "AirHumidity".to_owned(),
+                "Percentage".to_owned(),
+                55.5
+            ),
+            (
+                sensor,
+                "air_temperature_celsius2".to_owned(),
+                "AirTemperature".to_owned(),
+                "FloatCelsius".to_owned(),
+                30.
+            ),
+        ]
+    );
+    let (events,): (Vec<EventId>,) = sqlx::query_as(
+        "SELECT ARRAY_AGG(DISTINCT event_id) FROM measurements WHERE device_id = $1",
+    )
+    .bind(device.device_id)
+    .fetch_one(test_pool().await)
+    .await
+    .unwrap();
+    assert_eq!(events, vec![event_id]);
+}
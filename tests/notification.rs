@@ -0,0 +1,413 @@
+use axum::{
+    body::Bytes,
+    extract::Extension,
+    http::{HeaderMap, Method, StatusCode},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use server::test_helpers::{
+    request, seed_firmware, seed_sensor, send_events, setup_device, TestDevice,
+};
+use server::{test_pool, test_router, utils, Mailer, Notification};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// `Notification::deliver` sends every due notification, so tests can't deliver at the same time
+static DELIVERY: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+const TEMPERATURE: &str = "air_temperature_celsius0";
+
+/// Answers with the queued statuses, then 200, recording what it received
+#[derive(Clone, Default)]
+struct Webhook {
+    statuses: Arc<Mutex<VecDeque<StatusCode>>>,
+    received: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+}
+
+impl Webhook {
+    async fn start(self) -> String {
+        async fn receive(
+            Extension(webhook): Extension<Webhook>,
+            headers: HeaderMap,
+            body: Bytes,
+        ) -> StatusCode {
+            webhook.received.lock().unwrap().push((headers, body));
+            let status = webhook.statuses.lock().unwrap().pop_front();
+            status.unwrap_or(StatusCode::OK)
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/hook",
+            listener.local_addr().unwrap().port()
+        );
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .layer(Extension(self));
+        tokio::task::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        url
+    }
+
+    fn fail(&self, times: usize) {
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.extend((0..times).map(|_| StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    fn received(&self) -> Vec<(HeaderMap, Bytes)> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+/// Just enough SMTP for lettre to hand it a message, which is recorded with its headers
+async fn start_smtp(received: Arc<Mutex<Vec<String>>>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("smtp://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    tokio::task::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let received = Arc::clone(&received);
+            tokio::task::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 localhost\r\n").await.unwrap();
+                let mut data: Option<String> = None;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply: &[u8] = match data.as_mut() {
+                        Some(message) if line == "." => {
+                            received.lock().unwrap().push(std::mem::take(message));
+                            data = None;
+                            b"250 queued\r\n"
+                        }
+                        Some(message) => {
+                            message.push_str(&line);
+                            message.push('\n');
+                            continue;
+                        }
+                        None if line.starts_with("DATA") => {
+                            data = Some(String::new());
+                            b"354 go ahead\r\n"
+                        }
+                        None if line.starts_with("QUIT") => {
+                            writer.write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        }
+                        None => b"250 OK\r\n",
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+    url
+}
+
+async fn setup(name: &str) -> (Router, TestDevice) {
+    std::env::set_var("WEBHOOK_ALLOWED_HOSTS", "127.0.0.1");
+    let app = test_router().await;
+    let device = setup_device(app.clone(), name).await;
+    seed_firmware(test_pool().await, device.collection_id, name.as_bytes()).await;
+    seed_sensor(test_pool().await, device.collection_id, "DHT", 0).await;
+
+    let rule = json!({
+        "deviceId": device.device_id,
+        "name": "Too hot",
+        "variableName": TEMPERATURE,
+        "comparison": "Above",
+        "threshold": 50.,
+        "durationSeconds": 0,
+        "hysteresis": 0.,
+    });
+    let (status, _, _) = request(
+        app.clone(),
+        Method::POST,
+        "/v1/alert/rule",
+        &device.token,
+        Some(rule),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    (app, device)
+}
+
+async fn new_channel(
+    app: Router,
+    device: &TestDevice,
+    kind: &str,
+    target: &str,
+    max_per_hour: i32,
+) -> serde_json::Value {
+    let body = json!({
+        "organizationId": device.organization_id,
+        "name": kind,
+        "kind": kind,
+        "target": target,
+        "template": null,
+        "maxPerHour": max_per_hour,
+    });
+    let (status, _, body) = request(
+        app,
+        Method::POST,
+        "/v1/organization/notification/channel",
+        &device.token,
+        Some(body),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Fires the rule above 50 and resolves it below
+async fn measure(app: Router, device: &TestDevice, temperature: f64) {
+    let (status, _) = send_events(
+        app,
+        device,
+        vec![
+            json!({ "measurements": { TEMPERATURE: temperature, "air_humidity_percentage0": 50 } }),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+/// Oldest first
+async fn notifications(app: Router, device: &TestDevice) -> Vec<serde_json::Value> {
+    let uri = format!(
+        "/v1/organization/notifications?organizationId={}",
+        device.organization_id
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
+
+/// Due right away, instead of after the backoff
+async fn make_due(notification: &serde_json::Value) {
+    sqlx::query("UPDATE notifications SET next_attempt_at = NOW() WHERE id = $1")
+        .bind(notification["id"].as_i64().unwrap())
+        .execute(test_pool().await)
+        .await
+        .unwrap();
+}
+
+fn seconds_until(notification: &serde_json::Value) -> i64 {
+    let next_attempt_at =
+        chrono::DateTime::parse_from_rfc3339(notification["nextAttemptAt"].as_str().unwrap())
+            .unwrap();
+    (next_attempt_at.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_seconds()
+}
+
+#[tokio::test]
+async fn webhook_retries_with_backoff() {
+    let _delivery = DELIVERY.lock().await;
+    let (app, device) = setup("notification-webhook").await;
+    let mailer = Mailer::new("smtp://127.0.0.1:1", "iop@example.com");
+    let webhook = Webhook::default();
+    let url = webhook.clone().start().await;
+
+    let channel = new_channel(app.clone(), &device, "Webhook", &url, 10).await;
+    let secret = channel["secret"].as_str().unwrap().to_owned();
+    assert_eq!(secret.len(), 64);
+
+    measure(app.clone(), &device, 60.).await;
+    let notification = &notifications(app.clone(), &device).await[0];
+    assert_eq!(notification["status"], "Pending");
+
+    webhook.fail(1);
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    let notification = &notifications(app.clone(), &device).await[0];
+    assert_eq!(notification["status"], "Pending");
+    assert_eq!(notification["attempts"], 1);
+    assert!(notification["lastError"].as_str().unwrap().contains("500"));
+    assert!((25..=30).contains(&seconds_until(notification)));
+
+    // Not due until the backoff is over
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    assert_eq!(webhook.received().len(), 1);
+
+    make_due(notification).await;
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    let notification = &notifications(app.clone(), &device).await[0];
+    assert_eq!(notification["status"], "Sent");
+    assert_eq!(notification["attempts"], 2);
+    assert_eq!(notification["lastError"], json!(null));
+    assert!(notification["sentAt"].is_string());
+
+    let (headers, body) = webhook.received().pop().unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(headers["x-iop-timestamp"].as_bytes());
+    mac.update(b".");
+    mac.update(&body);
+    assert_eq!(
+        headers["x-iop-signature"],
+        format!("sha256={}", utils::hex(&mac.finalize().into_bytes()))
+    );
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["subject"], notification["subject"]);
+    assert_eq!(body["context"]["status"], "Firing");
+
+    // The delay doubles on every failure, and the notification is given up after the last attempt
+    measure(app.clone(), &device, 20.).await;
+    webhook.fail(2);
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    let notification = notifications(app.clone(), &device).await[1].clone();
+    make_due(&notification).await;
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    let notification = &notifications(app.clone(), &device).await[1];
+    assert_eq!(notification["attempts"], 2);
+    assert!((55..=60).contains(&seconds_until(notification)));
+
+    sqlx::query("UPDATE notifications SET attempts = 7, next_attempt_at = NOW() WHERE id = $1")
+        .bind(notification["id"].as_i64().unwrap())
+        .execute(test_pool().await)
+        .await
+        .unwrap();
+    webhook.fail(1);
+    Notification::deliver(test_pool().await, &mailer)
+        .await
+        .unwrap();
+    let notification = &notifications(app, &device).await[1];
+    assert_eq!(notification["status"], "Failed");
+    assert_eq!(notification["attempts"], 8);
+    assert_eq!(webhook.received().len(), 5);
+}
+
+#[tokio::test]
+async fn rate_limit_and_mute() {
+    let _delivery = DELIVERY.lock().await;
+    let (app, device) = setup("notification-limits").await;
+    let url = Webhook::default().start().await;
+    let channel = new_channel(app.clone(), &device, "Webhook", &url, 1).await;
+
+    measure(app.clone(), &device, 60.).await;
+    measure(app.clone(), &device, 20.).await;
+    let statuses: Vec<_> = notifications(app.clone(), &device)
+        .await
+        .iter()
+        .map(|n| n["status"].clone())
+        .collect();
+    assert_eq!(statuses, [json!("Pending"), json!("RateLimited")]);
+
+    let muted_until = chrono::Utc::now() + chrono::Duration::hours(1);
+    let mute = |muted_until: Option<chrono::DateTime<chrono::Utc>>| {
+        let app = app.clone();
+        let body = json!({
+            "organizationId": device.organization_id,
+            "notificationChannelId": channel["id"],
+            "mutedUntil": muted_until,
+        });
+        let token = device.token.clone();
+        async move {
+            let (status, _, body) = request(
+                app,
+                Method::POST,
+                "/v1/organization/notification/channel/mute",
+                &token,
+                Some(body),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+    let muted = mute(Some(muted_until)).await;
+    assert!(muted["mutedUntil"].is_string());
+    assert!(muted.get("secret").is_none());
+
+    // Muted channels don't even log the alerts
+    measure(app.clone(), &device, 60.).await;
+    assert_eq!(notifications(app.clone(), &device).await.len(), 2);
+
+    mute(None).await;
+    measure(app.clone(), &device, 20.).await;
+    let notifications = notifications(app.clone(), &device).await;
+    assert_eq!(notifications.len(), 3);
+    assert_eq!(notifications[2]["status"], "RateLimited");
+
+    let uri = format!(
+        "/v1/organization/notification/channels?organizationId={}",
+        device.organization_id
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let channels: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0]["id"], channel["id"]);
+    assert!(channels[0].get("secret").is_none());
+}
+
+#[tokio::test]
+async fn email() {
+    let _delivery = DELIVERY.lock().await;
+    let (app, device) = setup("notification-email").await;
+    new_channel(app.clone(), &device, "Email", "alerts@example.com", 10).await;
+    measure(app.clone(), &device, 60.).await;
+
+    // Email channels fail until SMTP is configured
+    let unconfigured = Mailer::new("smtp://127.0.0.1:1", "not an address");
+    Notification::deliver(test_pool().await, &unconfigured)
+        .await
+        .unwrap();
+    let notification = &notifications(app.clone(), &device).await[0];
+    assert_eq!(notification["status"], "Pending");
+    assert!(notification["lastError"]
+        .as_str()
+        .unwrap()
+        .contains("SMTP_FROM"));
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let url = start_smtp(Arc::clone(&received)).await;
+    make_due(notification).await;
+    Notification::deliver(
+        test_pool().await,
+        &Mailer::new(&url, "IoP <iop@example.com>"),
+    )
+    .await
+    .unwrap();
+    let notification = &notifications(app, &device).await[0];
+    assert_eq!(notification["status"], "Sent");
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(
+        received[0].contains("To: alerts@example.com"),
+        "{}",
+        received[0]
+    );
+    assert!(
+        received[0].contains("From: IoP <iop@example.com>"),
+        "{}",
+        received[0]
+    );
+    assert!(
+        received[0].contains(&format!(
+            "Subject: {}",
+            notification["subject"].as_str().unwrap()
+        )),
+        "{}",
+        received[0]
+    );
+}
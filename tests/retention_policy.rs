@@ -0,0 +1,199 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use server::test_helpers::{
+    request, seed_firmware, seed_sensor, send_device_log, send_device_panic, send_events,
+    setup_device, TestDevice,
+};
+use server::{test_pool, test_router, RetentionPolicy, MIN_RAW_EVENTS_DAYS};
+
+async fn set_policy(
+    app: axum::Router,
+    device: &TestDevice,
+    raw: i32,
+    hourly: i32,
+    daily: i32,
+    logs: i32,
+) -> (StatusCode, Vec<u8>) {
+    let (status, _, body) = request(
+        app,
+        Method::POST,
+        "/v1/organization/retention",
+        &device.token,
+        Some(json!({
+            "organizationId": device.organization_id,
+            "rawEventsDays": raw,
+            "hourlyAggregatesDays": hourly,
+            "dailyAggregatesDays": daily,
+            "logsDays": logs,
+            "panicsDays": logs,
+        })),
+    )
+    .await;
+    (status, body)
+}
+
+/// Events, measurements, faults, logs and panics of a device, one of each backdated by `days`
+async fn seed_activity(app: axum::Router, device: &TestDevice, days: i32) {
+    seed_firmware(
+        test_pool().await,
+        device.collection_id,
+        device.mac.as_bytes(),
+    )
+    .await;
+    seed_sensor(test_pool().await, device.collection_id, "DHT", 0).await;
+    let (status, _) = send_events(
+        app.clone(),
+        device,
+        vec![
+            json!({ "measurements": { "air_temperature_celsius0": 10, "air_humidity_percentage0": null } }),
+            json!({ "measurements": { "air_temperature_celsius0": 20, "air_humidity_percentage0": null } }),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    for log in ["old log", "new log"] {
+        send_device_log(
+            app.clone(),
+            &device.device_token,
+            &device.mac,
+            &device.version,
+            log,
+        )
+        .await;
+    }
+    for msg in ["old panic", "new panic"] {
+        let panic = serde_json::from_value(
+            json!({ "file": "main.cpp", "line": 1, "func": "loop", "msg": msg }),
+        )
+        .unwrap();
+        send_device_panic(
+            app.clone(),
+            &device.device_token,
+            &device.mac,
+            &device.version,
+            &panic,
+        )
+        .await;
+    }
+
+    let backdate = |query: &'static str| async move {
+        sqlx::query(query)
+            .bind(device.device_id)
+            .bind(days)
+            .execute(test_pool().await)
+            .await
+            .unwrap();
+    };
+    backdate(
+        "UPDATE events SET created_at = created_at - make_interval(days => $2)
+         WHERE device_id = $1 AND measurements->>'air_temperature_celsius0' = '10'",
+    )
+    .await;
+    backdate("UPDATE device_logs SET created_at = created_at - make_interval(days => $2) WHERE device_id = $1 AND log = 'old log'").await;
+    backdate("UPDATE device_panics SET created_at = created_at - make_interval(days => $2) WHERE device_id = $1 AND msg = 'old panic'").await;
+}
+
+/// Amount of events, measurements, faults, logs and panics of the device
+async fn count(device: &TestDevice) -> [i64; 5] {
+    let mut counts = [0; 5];
+    for (count, table) in counts.iter_mut().zip([
+        "events",
+        "measurements",
+        "sensor_faults",
+        "device_logs",
+        "device_panics",
+    ]) {
+        let (rows,): (i64,) = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM {table} WHERE device_id = $1"
+        ))
+        .bind(device.device_id)
+        .fetch_one(test_pool().await)
+        .await
+        .unwrap();
+        *count = rows;
+    }
+    counts
+}
+
+#[tokio::test]
+async fn policies_are_validated() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "retention-validate").await;
+
+    // Nothing is deleted until a policy is saved
+    let uri = format!(
+        "/v1/organization/retention?organizationId={}",
+        device.organization_id
+    );
+    let (status, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        json!(null)
+    );
+
+    let min = MIN_RAW_EVENTS_DAYS;
+    for (raw, hourly, daily, logs) in [
+        (min, min, min, 0),
+        (min, min, min, 100 * 365 + 1),
+        (min - 1, min, min, 1),
+        (min, min - 1, min, 1),
+        (min, min + 1, min, 1),
+    ] {
+        let (status, _) = set_policy(app.clone(), &device, raw, hourly, daily, logs).await;
+        assert_eq!(
+            status,
+            StatusCode::BAD_REQUEST,
+            "{raw} {hourly} {daily} {logs}"
+        );
+    }
+
+    let (status, body) = set_policy(app.clone(), &device, min, min + 1, min + 2, 1).await;
+    assert_eq!(status, StatusCode::OK);
+    let policy: RetentionPolicy = serde_json::from_slice(&body).unwrap();
+    assert_eq!(policy.raw_events_days(), min);
+    assert_eq!(policy.daily_aggregates_days(), min + 2);
+
+    let (_, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    assert_eq!(
+        serde_json::from_slice::<RetentionPolicy>(&body).unwrap(),
+        policy
+    );
+}
+
+#[tokio::test]
+async fn enforcing_only_deletes_what_policies_allow() {
+    let app = test_router().await;
+    let kept = setup_device(app.clone(), "retention-kept").await;
+    seed_activity(app.clone(), &kept, 10 * 365).await;
+    let expired = setup_device(app.clone(), "retention-expired").await;
+    seed_activity(app.clone(), &expired, MIN_RAW_EVENTS_DAYS + 1).await;
+    let min = MIN_RAW_EVENTS_DAYS;
+    let (status, _) = set_policy(app.clone(), &expired, min, min, min, 1).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(count(&expired).await, [2, 2, 2, 2, 2]);
+
+    // One row per transaction
+    RetentionPolicy::enforce(test_pool().await, 1)
+        .await
+        .unwrap();
+
+    assert_eq!(count(&kept).await, [2, 2, 2, 2, 2]);
+    assert_eq!(count(&expired).await, [1, 1, 1, 1, 1]);
+    let (logs,): (Vec<String>,) =
+        sqlx::query_as("SELECT ARRAY_AGG(log) FROM device_logs WHERE device_id = $1")
+            .bind(expired.device_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    assert_eq!(logs, ["new log"]);
+    let (old_rollups,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM measurement_rollups WHERE device_id = $1 AND bucket_start < NOW() - make_interval(days => $2)",
+    )
+    .bind(expired.device_id)
+    .bind(min)
+    .fetch_one(test_pool().await)
+    .await
+    .unwrap();
+    assert_eq!(old_rollups, 0);
+}
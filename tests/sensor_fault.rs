@@ -0,0 +1,143 @@
+use axum::http::{Method, StatusCode};
+use serde_json::{json, Value};
+use server::test_helpers::{request, seed_firmware, seed_sensor, send_events, setup_device};
+use server::{test_pool, test_router, Page, SensorFault, SensorFaultCount, SensorFaultReason};
+
+#[tokio::test]
+async fn bad_readings_are_faults_and_the_rest_is_stored() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "faults").await;
+    seed_firmware(test_pool().await, device.collection_id, b"faults binary").await;
+    let dht = seed_sensor(test_pool().await, device.collection_id, "DHT", 0).await;
+    let dallas = seed_sensor(
+        test_pool().await,
+        device.collection_id,
+        "Dallas Temperature",
+        1,
+    )
+    .await;
+
+    let (status, _) = send_events(
+        app.clone(),
+        &device,
+        vec![
+            // The prototype limits DHT temperatures to [-40, 80], the others are limited by their type
+            json!({ "measurements": {
+                "air_temperature_celsius0": 80,
+                "air_humidity_percentage0": 100.5,
+                "soil_temperature_celsius1": -60,
+            } }),
+            json!({ "measurements": {
+                "air_temperature_celsius0": null,
+                "air_humidity_percentage0": "wet",
+                "extra": 1,
+            } }),
+            json!({ "measurements": {
+                "air_temperature_celsius0": 80.5,
+                "air_humidity_percentage0": 0,
+                "soil_temperature_celsius1": 125,
+            } }),
+        ],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut stored: Vec<(String, f64)> =
+        sqlx::query_as("SELECT variable_name, value FROM measurements WHERE device_id = $1")
+            .bind(device.device_id)
+            .fetch_all(test_pool().await)
+            .await
+            .unwrap();
+    stored.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        stored,
+        [
+            ("air_humidity_percentage0".to_owned(), 0.),
+            ("air_temperature_celsius0".to_owned(), 80.),
+            ("soil_temperature_celsius1".to_owned(), 125.),
+        ]
+    );
+    let (events,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM events WHERE device_id = $1")
+        .bind(device.device_id)
+        .fetch_one(test_pool().await)
+        .await
+        .unwrap();
+    assert_eq!(events, 3);
+
+    let uri = format!("/v1/device/faults?deviceId={}", device.device_id);
+    let (status, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: Page<SensorFault> = serde_json::from_slice(&body).unwrap();
+    let mut faults: Vec<_> = page
+        .items()
+        .iter()
+        .map(|f| {
+            (
+                f.variable_name().to_owned(),
+                format!("{:?}", f.reason()),
+                f.sensor_id(),
+                f.value().clone(),
+            )
+        })
+        .collect();
+    faults.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    let fault = |name: &str, reason: SensorFaultReason, sensor, value: Option<Value>| {
+        (name.to_owned(), format!("{:?}", reason), sensor, value)
+    };
+    assert_eq!(
+        faults,
+        [
+            fault(
+                "air_humidity_percentage0",
+                SensorFaultReason::InvalidType,
+                Some(dht),
+                Some(json!("wet"))
+            ),
+            fault(
+                "air_humidity_percentage0",
+                SensorFaultReason::OutOfRange,
+                Some(dht),
+                Some(json!(100.5))
+            ),
+            fault(
+                "air_temperature_celsius0",
+                SensorFaultReason::Null,
+                Some(dht),
+                None
+            ),
+            fault(
+                "air_temperature_celsius0",
+                SensorFaultReason::OutOfRange,
+                Some(dht),
+                Some(json!(80.5))
+            ),
+            fault("extra", SensorFaultReason::Unexpected, None, Some(json!(1))),
+            fault(
+                "soil_temperature_celsius1",
+                SensorFaultReason::Missing,
+                Some(dallas),
+                None
+            ),
+            fault(
+                "soil_temperature_celsius1",
+                SensorFaultReason::OutOfRange,
+                Some(dallas),
+                Some(json!(-60))
+            ),
+        ]
+    );
+
+    let since = (chrono::Utc::now() - chrono::Duration::hours(1))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let uri = format!(
+        "/v1/device/faults/count?deviceId={}&since={since}",
+        device.device_id
+    );
+    let (status, _, body) = request(app.clone(), Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let counts: Vec<SensorFaultCount> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(counts.len(), 7);
+    assert!(counts.iter().all(|c| c.count() == 1));
+    // Faults without a sensor come last
+    assert_eq!(counts.last().unwrap().variable_name(), "extra");
+}
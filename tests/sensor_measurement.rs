@@ -0,0 +1,109 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use server::test_helpers::{
+    list_sensor_prototypes, seed_compiler, seed_firmware, seed_sensor, send_events, setup_device,
+};
+use server::{test_pool, test_router, SensorMeasurementKind, SensorMeasurementType, TargetId};
+
+/// Kind, type, reading sent and the value stored
+fn readings() -> Vec<(&'static str, &'static str, serde_json::Value, f64)> {
+    vec![
+        ("Light", "FloatLux", json!(1234.5), 1234.5),
+        ("Ph", "FloatPh", json!(6.5), 6.5),
+        (
+            "ElectricalConductivity",
+            "FloatMicrosiemensPerCentimeter",
+            json!(1500),
+            1500.,
+        ),
+        ("Co2", "FloatPartsPerMillion", json!(415), 415.),
+        (
+            "BarometricPressure",
+            "FloatHectopascal",
+            json!(1013.25),
+            1013.25,
+        ),
+        ("WaterLevel", "FloatCentimeters", json!(42), 42.),
+        ("FlowVolume", "FloatLiters", json!(3.5), 3.5),
+        ("BatteryVoltage", "FloatVolts", json!(3.7), 3.7),
+        ("OnOff", "Boolean", json!(true), 1.),
+    ]
+}
+
+#[tokio::test]
+async fn new_kinds_and_types_are_stored() {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), "measurement-kinds").await;
+    seed_firmware(test_pool().await, device.collection_id, b"kinds binary").await;
+
+    let (prototype_id,): (i64,) =
+        sqlx::query_as("INSERT INTO sensor_prototypes (name) VALUES ('Every Kind') RETURNING id")
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    for (kind, ty, _, _) in readings() {
+        sqlx::query(
+            "INSERT INTO sensor_prototype_measurements (sensor_prototype_id, name, variable_name, value, kind, ty)
+             VALUES ($1, $2, $2 || '{{index}}', '0', $2::SensorMeasurementKind, $3::SensorMeasurementType)",
+        )
+        .bind(prototype_id)
+        .bind(kind)
+        .bind(ty)
+        .execute(test_pool().await)
+        .await
+        .unwrap();
+    }
+    seed_sensor(test_pool().await, device.collection_id, "Every Kind", 0).await;
+
+    // Every prototype is decoded when listed
+    let compiler_id = seed_compiler(test_pool().await, device.collection_id).await;
+    let (target_id,): (TargetId,) = sqlx::query_as("SELECT target_id FROM compilers WHERE id = $1")
+        .bind(compiler_id)
+        .fetch_one(test_pool().await)
+        .await
+        .unwrap();
+    let prototypes = list_sensor_prototypes(app.clone(), &device.token, target_id).await;
+    let prototype = prototypes
+        .iter()
+        .find(|p| p.name() == "Every Kind")
+        .unwrap();
+    assert_eq!(prototype.measurements().len(), readings().len());
+    assert!(prototype
+        .measurements()
+        .iter()
+        .any(|m| m.kind == SensorMeasurementKind::OnOff && m.ty == SensorMeasurementType::Boolean));
+
+    let measurements: serde_json::Map<_, _> = readings()
+        .into_iter()
+        .map(|(kind, _, value, _)| (format!("{kind}0"), value))
+        .collect();
+    let (status, _) = send_events(
+        app.clone(),
+        &device,
+        vec![json!({ "measurements": measurements })],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut stored: Vec<(String, String, f64)> =
+        sqlx::query_as("SELECT kind::TEXT, ty::TEXT, value FROM measurements WHERE device_id = $1")
+            .bind(device.device_id)
+            .fetch_all(test_pool().await)
+            .await
+            .unwrap();
+    stored.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut expected: Vec<_> = readings()
+        .into_iter()
+        .map(|(kind, ty, _, value)| (kind.to_owned(), ty.to_owned(), value))
+        .collect();
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(stored, expected);
+
+    let (faults,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM sensor_faults WHERE device_id = $1")
+            .bind(device.device_id)
+            .fetch_one(test_pool().await)
+            .await
+            .unwrap();
+    assert_eq!(faults, 0);
+}
@@ -0,0 +1,127 @@
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use server::test_helpers::{
+    request, seed_firmware, send_versioned_events, setup_device, stat, TestDevice,
+};
+use server::{test_pool, test_router, EVENT_SCHEMA_VERSION, MAX_EVENT_BATCH};
+
+async fn setup(name: &str) -> (axum::Router, TestDevice) {
+    let app = test_router().await;
+    let device = setup_device(app.clone(), name).await;
+    seed_firmware(test_pool().await, device.collection_id, name.as_bytes()).await;
+    (app, device)
+}
+
+async fn list_events(app: axum::Router, device: &TestDevice) -> Vec<serde_json::Value> {
+    let since = (chrono::Utc::now() - chrono::Duration::days(1))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let uri = format!(
+        "/v1/device/events?deviceId={}&since={since}",
+        device.device_id
+    );
+    let (status, _, body) = request(app, Method::GET, &uri, &device.token, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    page["items"].as_array().unwrap().clone()
+}
+
+fn events(count: usize) -> Vec<serde_json::Value> {
+    (0..count)
+        .map(|index| json!({ "measurements": { "counter": index } }))
+        .collect()
+}
+
+#[tokio::test]
+async fn schema_version() {
+    let (app, device) = setup("versioned-event1").await;
+
+    let (status, _) = send_versioned_events(
+        app.clone(),
+        &device,
+        json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION + 1,
+            "stat": stat(&device.version),
+            "events": events(1),
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(list_events(app.clone(), &device).await.is_empty());
+
+    let measured_at = (chrono::Utc::now() - chrono::Duration::hours(2))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let (status, _) = send_versioned_events(
+        app.clone(),
+        &device,
+        json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": [
+                { "measuredAt": measured_at, "measurements": { "counter": 1 } },
+                { "measurements": { "counter": 2 } },
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let events = list_events(app, &device).await;
+    assert_eq!(events.len(), 2);
+    let batched = events
+        .iter()
+        .find(|event| event["measurements"]["counter"] == json!(1))
+        .unwrap();
+    assert_eq!(
+        chrono::DateTime::parse_from_rfc3339(batched["measuredAt"].as_str().unwrap()).unwrap(),
+        chrono::DateTime::parse_from_rfc3339(&measured_at).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn batch_limits() {
+    let (app, device) = setup("versioned-event2").await;
+
+    let (status, _) = send_versioned_events(
+        app.clone(),
+        &device,
+        json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": events(MAX_EVENT_BATCH + 1),
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(list_events(app.clone(), &device).await.is_empty());
+
+    // A single measurement too far in the future refuses the whole batch
+    let future = (chrono::Utc::now() + chrono::Duration::hours(1))
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let (status, _) = send_versioned_events(
+        app.clone(),
+        &device,
+        json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": [
+                { "measurements": { "counter": 1 } },
+                { "measuredAt": future, "measurements": { "counter": 2 } },
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(list_events(app.clone(), &device).await.is_empty());
+
+    let (status, _) = send_versioned_events(
+        app.clone(),
+        &device,
+        json!({
+            "schemaVersion": EVENT_SCHEMA_VERSION,
+            "stat": stat(&device.version),
+            "events": events(MAX_EVENT_BATCH),
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}